@@ -2,12 +2,12 @@
 
 extern crate test;
 
-use fastcgi_client::{request::Request, Client, Params};
-use std::env::current_dir;
+use fastcgi_client::{client::handle_fastcgi_request, request::Request, Client, Params};
+use std::{collections::HashSet, env::current_dir};
 use test::Bencher;
 use tokio::{
-    io::{self, AsyncRead, AsyncWrite},
-    net::TcpStream,
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::{TcpListener, TcpStream},
 };
 
 mod common;
@@ -38,7 +38,7 @@ async fn test_client<S: AsyncRead + AsyncWrite + Send + Sync + Unpin>(client: &m
         .set_content_length("0");
 
     let output = client
-        .execute(Request::new(params, &mut io::empty()))
+        .execute_buffered(Request::new(params, &mut io::empty()))
         .await
         .unwrap();
 
@@ -70,3 +70,163 @@ fn bench_execute(b: &mut Bencher) {
         });
     });
 }
+
+/// Writing a request with a 10KB body to a tcp socket, without php-fpm, the
+/// other end only discards what it reads.
+#[bench]
+fn bench_write_request_10kb(b: &mut Bencher) {
+    common::setup();
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut stream = rt.block_on(async {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            io::copy(&mut stream, &mut io::sink()).await.unwrap();
+        });
+        TcpStream::connect(addr).await.unwrap()
+    });
+
+    let params = Params::default()
+        .set_request_method("POST")
+        .set_script_filename("/index.php")
+        .set_content_type("application/octet-stream")
+        .set_content_length("10240");
+    let body = vec![b'x'; 10240];
+
+    b.iter(|| {
+        rt.block_on(async {
+            handle_fastcgi_request(&mut stream, true, 1, &params, &mut &body[..])
+                .await
+                .unwrap();
+        });
+    });
+}
+
+/// Server ending every request without output as soon as its STDIN ends.
+async fn end_requests(mut stream: DuplexStream) {
+    let mut header = [0; 8];
+    let mut ended = HashSet::new();
+    while stream.read_exact(&mut header).await.is_ok() {
+        let id = u16::from_be_bytes([header[2], header[3]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize;
+        let mut content = vec![0; length];
+        stream.read_exact(&mut content).await.unwrap();
+
+        // Begin of request, or the end of its STDIN.
+        if header[1] == 1 {
+            ended.remove(&id);
+        } else if header[1] == 5 && length == 0 && ended.insert(id) {
+            let mut end_request = vec![1, 3, header[2], header[3], 0, 8, 0, 0];
+            end_request.extend_from_slice(&[0; 8]);
+            stream.write_all(&end_request).await.unwrap();
+        }
+    }
+}
+
+/// Executing requests without body and output, so the time is mostly spent
+/// by the client, without php-fpm.
+#[bench]
+fn bench_execute_tiny_requests(b: &mut Bencher) {
+    common::setup();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut client = rt.block_on(async {
+        let (client_stream, server_stream) = io::duplex(1024 * 1024);
+        tokio::spawn(end_requests(server_stream));
+        Client::new(client_stream, true)
+    });
+
+    let params = Params::default()
+        .set_request_method("GET")
+        .set_script_filename("/index.php");
+
+    b.iter(|| {
+        rt.block_on(async {
+            client
+                .execute(Request::new(params.clone(), io::empty()), &mut io::sink(), &mut io::sink())
+                .await
+                .unwrap();
+        });
+    });
+}
+
+/// Server answering every request with 1000 STDOUT records of 16 bytes, as
+/// soon as its STDIN ends.
+async fn small_records_responses(stream: TcpStream) {
+    let (mut read, mut write) = stream.into_split();
+    let mut header = [0; 8];
+    while read.read_exact(&mut header).await.is_ok() {
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize;
+        let mut content = vec![0; length];
+        read.read_exact(&mut content).await.unwrap();
+
+        if header[1] == 5 && length == 0 {
+            let mut response = Vec::new();
+            for _ in 0..1000 {
+                response.extend_from_slice(&[1, 6, header[2], header[3], 0, 16, 0, 0]);
+                response.extend_from_slice(&[b'x'; 16]);
+            }
+            response.extend_from_slice(&[1, 3, header[2], header[3], 0, 8, 0, 0]);
+            response.extend_from_slice(&[0; 8]);
+            write.write_all(&response).await.unwrap();
+        }
+    }
+}
+
+/// Reading responses of many small records from a tcp socket, through the
+/// read buffer of `read_buffer_size`, 0 for reading the socket directly.
+fn bench_small_records(b: &mut Bencher, read_buffer_size: usize) {
+    common::setup();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut client = rt.block_on(async {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            stream.set_nodelay(true).unwrap();
+            small_records_responses(stream).await;
+        });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+        Client::new(stream, true).set_read_buffer_size(read_buffer_size)
+    });
+
+    let params = Params::default()
+        .set_request_method("GET")
+        .set_script_filename("/index.php");
+
+    b.iter(|| {
+        rt.block_on(async {
+            client
+                .execute(Request::new(params.clone(), io::empty()), &mut io::sink(), &mut io::sink())
+                .await
+                .unwrap();
+        });
+    });
+}
+
+#[bench]
+fn bench_small_records_buffered(b: &mut Bencher) {
+    bench_small_records(b, 64 * 1024);
+}
+
+#[bench]
+fn bench_small_records_unbuffered(b: &mut Bencher) {
+    bench_small_records(b, 0);
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use bytes::BytesMut;
+use fastcgi_client::{client::handle_fastcgi_response, codec::FastCgiCodec};
+use libfuzzer_sys::fuzz_target;
+use tokio::{io, runtime::Builder};
+
+// The response of any bytes from the server must fail or complete, never
+// panic, the same for the records decoded by the codec.
+fuzz_target!(|data: &[u8]| {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let _ = handle_fastcgi_response(&mut &data[..], 1, &mut io::sink(), &mut io::sink()).await;
+    });
+
+    let mut src = BytesMut::from(data);
+    let mut codec = FastCgiCodec::new();
+    while let Ok(Some(_)) = codec.decode(&mut src) {}
+});
@@ -1,89 +1,2001 @@
 use crate::{
-    id::RequestIdGenerator,
-    meta::{BeginRequestRec, EndRequestRec, Header, ParamPairs, RequestType, Role},
+    id::{IdAllocator, RequestIdGenerator, RequestIdGuard},
+    meta::{
+        decode_name_value_pairs, BeginRequestFlags, BeginRequestRec, EndRequestRec, GetValuesRec, Header, ParamPairs,
+        RecordBuf, RecordHeader, RequestType, Role, MAX_LENGTH, VERSION_1,
+    },
     params::Params,
-    request::Request,
-    ClientError, ClientResult,
+    request::{Request, RequestBuilder},
+    response::{RequestComplete, RequestStats, Response, ResponseChunk},
+    stream::{Address, ClientStream, Halves, Stream, TcpOptions},
+    ClientError, ClientResult, Phase,
 };
-use log::debug;
-use std::time::Duration;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "http")]
+use crate::response::AuthResult;
+use bytes::Bytes;
+use log::{debug, warn};
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc,
+    time::{sleep, timeout_at, Instant},
+};
+
+/// Async client for handling communication between fastcgi server.
+///
+/// # Cancellation
+///
+/// Dropping an `execute` future before it completes leaves the request in
+/// progress: the request may be partially written, or the response unread.
+/// Further requests fail with `ClientError::Busy` until the response of the
+/// request in progress is aborted with [`Client::abort`], which is only
+/// possible once the request was fully written. Otherwise the client has to be
+/// dropped.
+///
+/// With [`Client::set_abort_on_cancel`], the next request aborts the response
+/// of the cancelled one itself, such as one losing a `tokio::select!`.
+pub struct Client<S: ClientStream> {
+    /// The records are read through the buffer, whose bytes left after a
+    /// response are the start of the next one.
+    stream: BufReader<S>,
+    read_buffer_size: usize,
+    flags: BeginRequestFlags,
+    /// Flags of the last request sent, the flags of the client unless
+    /// overridden by `RequestBuilder::set_keep_alive`.
+    request_flags: BeginRequestFlags,
+    broken: bool,
+    /// Id of the request in progress, and the phase of its exchange.
+    in_progress: Option<(u16, Phase)>,
+    /// The response of a request without keep alive ended, so the server
+    /// closes the connection.
+    closed: bool,
+    max_response_bytes: Option<usize>,
+    max_records_per_response: Option<usize>,
+    request_id_generator: Arc<dyn IdAllocator>,
+    /// Buffer for reading the streams of requests, reused across the
+    /// requests.
+    scratch: RecordBuf,
+    /// Id and start time of the last request, for logging its duration.
+    last_request: Option<(u16, Instant)>,
+    /// Timeout of `execute`, see `ClientBuilder::set_request_timeout`.
+    request_timeout: Option<Duration>,
+    /// Deadline of all the requests, see `ClientBuilder::set_deadline`.
+    deadline: Option<Instant>,
+    /// Whether a record of another request fails the response, see
+    /// `Client::set_strict_records`.
+    strict_records: bool,
+    /// Whether a record of another version fails the response, see
+    /// `Client::set_strict_version`.
+    strict_version: bool,
+    /// `FCGI_MAX_REQS` of the server, once queried by `Client::get_values`.
+    max_reqs: Option<usize>,
+    server_values: HashMap<String, String>,
+    /// Stats of the last request, see `Client::last_request_stats`.
+    stats: RequestStats,
+    /// Ids allocated for the caller, by `generate_id` or `handle_new_request`,
+    /// which `abort` releases. An id of a cancelled `execute` was already
+    /// released by its guard, so it isn't released twice.
+    held_ids: HashSet<u16>,
+    /// Whether the response left by a cancelled request is aborted by the
+    /// next one, see `Client::set_abort_on_cancel`.
+    abort_on_cancel: bool,
+    /// Whether the response in progress is read by a future, which was
+    /// cancelled if the response is still in progress at the next request.
+    reading_response: bool,
+    /// Whether a record is partially read, so the stream is in an unknown
+    /// state if its future was cancelled.
+    reading_record: bool,
+    /// Ids of the requests sent by `Client::send` whose responses aren't
+    /// received yet, in the order they were sent.
+    pipelined: VecDeque<u16>,
+}
+
+impl<R, W> Client<Halves<R, W>>
+where
+    R: AsyncRead + Send + Sync + Unpin,
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    /// Construct a `Client` Object with the read and write halves of a
+    /// connection split beforehand, such as by `TcpStream::into_split`, the
+    /// requests are written to `write` and the responses read from `read`.
+    pub fn from_halves(read: R, write: W, keep_alive: bool) -> Self {
+        Self::new(Halves::new(read, write), keep_alive)
+    }
+}
+
+impl<S: ClientStream> Client<S> {
+    /// Construct a `Client` Object with stream, such as `tokio::net::TcpStream`
+    /// or `tokio::net::UnixStream`.
+    ///
+    /// If `keep_alive` is true, the server keeps the connection open after a
+    /// response, and requests can be executed one after another on the same
+    /// client while `is_reusable` is true. Otherwise the server closes the
+    /// connection after the response, and further requests fail with
+    /// `ClientError::ConnectionClosed`.
+    pub fn new(stream: S, keep_alive: bool) -> Self {
+        let generator = RequestIdGenerator::new(Duration::from_millis(1500));
+        Self::with_id_generator(stream, keep_alive, Arc::new(generator))
+    }
+
+    /// Construct a `Client` sending `flags` in the `FCGI_BEGIN_REQUEST` of
+    /// every request, like [`Client::new`] with `keep_alive` if
+    /// `BeginRequestFlags::KEEP_CONN` is set.
+    pub fn with_flags(stream: S, flags: BeginRequestFlags) -> Self {
+        let mut client = Self::new(stream, false);
+        client.flags = flags;
+        client.request_flags = flags;
+        client
+    }
+
+    /// Construct a `Client` allocating the request ids from `generator`,
+    /// which may be shared with other clients, e.g. to keep the ids unique
+    /// across the connections to a backend, or to get deterministic ids.
+    ///
+    /// Any [`IdAllocator`] can be given, such as one allocating random ids,
+    /// [`RequestIdGenerator`] allocates them sequentially.
+    pub fn with_id_generator(
+        stream: S,
+        keep_alive: bool,
+        generator: Arc<dyn IdAllocator>,
+    ) -> Self {
+        Self {
+            stream: BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, stream),
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            flags: keep_alive.into(),
+            request_flags: keep_alive.into(),
+            broken: false,
+            in_progress: None,
+            closed: false,
+            max_response_bytes: None,
+            max_records_per_response: None,
+            request_id_generator: generator,
+            scratch: RecordBuf::new(MAX_LENGTH),
+            last_request: None,
+            request_timeout: None,
+            deadline: None,
+            strict_records: true,
+            strict_version: false,
+            max_reqs: None,
+            server_values: HashMap::new(),
+            stats: RequestStats::default(),
+            held_ids: HashSet::new(),
+            abort_on_cancel: false,
+            reading_response: false,
+            reading_record: false,
+            pipelined: VecDeque::new(),
+        }
+    }
+
+    /// Set the timeout of allocating a request id when all the ids are in
+    /// use, after which the request fails with
+    /// `ClientError::NoAvailableRequestId`. Defaults to 1500 milliseconds.
+    ///
+    /// This replaces the generator, including one given to
+    /// `with_id_generator`, by a new one of the default pool size.
+    pub fn set_request_id_timeout(mut self, timeout: Duration) -> Self {
+        self.request_id_generator = Arc::new(RequestIdGenerator::new(timeout));
+        self
+    }
+
+    /// Set the max length of the content of the records of the PARAMS, STDIN
+    /// and DATA streams, and of [`Client::send_record`], clamped to
+    /// `1..=65535`. Defaults to 65535, smaller records may suit constrained
+    /// backends.
+    pub fn set_record_size(mut self, record_size: usize) -> Self {
+        let mut scratch = RecordBuf::new(record_size);
+        scratch.version = self.scratch.version;
+        scratch.flush_interval = self.scratch.flush_interval;
+        self.scratch = scratch;
+        self
+    }
+
+    /// Flush the stream every `records` records of STDIN and DATA, and after
+    /// the PARAMS before them, so the backend can set up the request while a
+    /// large body is streamed, `None` for flushing once the whole request is
+    /// written, which is the default. At least 1.
+    ///
+    /// Every flush may be a write of the stream, so it's a trade of latency
+    /// for throughput, e.g. 16 records are about 1MB with the default record
+    /// size.
+    pub fn set_flush_interval(mut self, records: Option<usize>) -> Self {
+        self.scratch.flush_interval = records.map(|records| records.max(1));
+        self
+    }
+
+    /// Set the version byte of the records written, for testing servers with
+    /// other versions than 1, the only version of the protocol. Defaults to 1.
+    pub fn set_version(mut self, version: u8) -> Self {
+        self.scratch.version = version;
+        self
+    }
+
+    /// Set whether a response record of another version than 1 fails the
+    /// response with `ClientError::UnsupportedVersion`. Otherwise the record
+    /// is read as version 1 with a warning, which is the default.
+    pub fn set_strict_version(mut self, strict_version: bool) -> Self {
+        self.strict_version = strict_version;
+        self
+    }
+
+    /// Set the size of the buffer the records are read through, so the many
+    /// small records of a response are read by few reads of the stream, 0 for
+    /// reading the stream directly. Defaults to 64KB.
+    ///
+    /// It must be set before the first request, it's left unchanged if bytes
+    /// of the stream are already buffered.
+    pub fn set_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        if !self.stream.buffer().is_empty() {
+            warn!("Read buffer size unchanged, the buffer isn't empty.");
+            return self;
+        }
+        self.stream = BufReader::with_capacity(read_buffer_size, self.stream.into_inner());
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Limit the bytes of STDOUT and STDERR of a response, `None` for no
+    /// limit, which is the default.
+    ///
+    /// A request exceeding the limit fails with `ClientError::ResponseTooLarge`
+    /// and, if the connection is keep alive, is aborted.
+    pub fn set_max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Limit the count of STDOUT and STDERR records of a response, `None` for
+    /// no limit, which is the default. Unlike `max_response_bytes`, it catches
+    /// a backend flooding empty records.
+    ///
+    /// A request exceeding the limit fails with `ClientError::TooManyRecords`.
+    /// The request isn't aborted, since the backend may never end the
+    /// response, so the connection can't be used for another request.
+    pub fn set_max_records_per_response(mut self, max_records_per_response: Option<usize>) -> Self {
+        self.max_records_per_response = max_records_per_response;
+        self
+    }
+
+    /// Set whether a record of another request than the one in progress, such
+    /// as a stray STDOUT of a request already ended by a buggy backend, fails
+    /// the response with `ClientError::UnexpectedRecord`. Otherwise the record
+    /// is discarded with a warning. Defaults to true.
+    pub fn set_strict_records(mut self, strict_records: bool) -> Self {
+        self.strict_records = strict_records;
+        self
+    }
+
+    /// Set whether a request cancelled while its response is read, such as an
+    /// `execute` losing a `tokio::select!`, is aborted by the next request of
+    /// the client, rather than failing it with `ClientError::Busy`. Defaults
+    /// to false.
+    ///
+    /// Dropping a future can't write to the stream, so the abort is deferred:
+    /// the next request, or [`Client::ping`], first sends the
+    /// `FCGI_ABORT_REQUEST` of the cancelled request and drains its response
+    /// until the `FCGI_END_REQUEST`, and fails if the abort fails. Meanwhile
+    /// the client is reusable, so a [`Pool`](crate::pool::Pool) keeps it.
+    ///
+    /// It's best effort, a request cancelled with its request partially
+    /// written, or a record partially read, still leaves the stream in an
+    /// unknown state. A record is only partially read if the read buffer is
+    /// disabled, or the record arrived partially when cancelled.
+    pub fn set_abort_on_cancel(mut self, abort_on_cancel: bool) -> Self {
+        self.abort_on_cancel = abort_on_cancel;
+        self
+    }
+
+    /// Connect to fastcgi server over TLS, `handshake` performs the TLS
+    /// handshake over the connected `TcpStream`, so any TLS implementation
+    /// can be used, such as `tokio-rustls`:
+    ///
+    /// ```ignore
+    /// let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    /// let server_name = "php.example.com".try_into().unwrap();
+    /// let client = Client::connect_tls(
+    ///     ("php.example.com", 9000),
+    ///     |stream| connector.connect(server_name, stream),
+    ///     true,
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn connect_tls<A, F, Fut>(addr: A, handshake: F, keep_alive: bool) -> ClientResult<Self>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce(TcpStream) -> Fut,
+        Fut: Future<Output = io::Result<S>>,
+    {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let stream = handshake(stream).await?;
+        Ok(Self::new(stream, keep_alive))
+    }
+
+    /// Whether the connection can be used for another request, i.e. it's keep
+    /// alive and no exchange failed with a stream or protocol error, which
+    /// leaves the stream in an unknown state.
+    pub fn is_reusable(&self) -> bool {
+        self.keep_alive()
+            && !self.broken
+            && (self.in_progress.is_none() || self.cancelled_response().is_some())
+    }
+
+    /// Id of the request cancelled while its response was read, which the
+    /// next request aborts, see [`Client::set_abort_on_cancel`].
+    fn cancelled_response(&self) -> Option<u16> {
+        match self.in_progress {
+            Some((id, Phase::Response))
+                if self.abort_on_cancel
+                    && self.reading_response
+                    && !self.reading_record
+                    && !self.broken =>
+            {
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Abort the response of the cancelled request, if any, before another
+    /// exchange.
+    async fn abort_cancelled(&mut self) -> ClientResult<()> {
+        let id = match self.cancelled_response() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        debug!("[id = {}] Abort response of cancelled request.", id);
+        match abort_request(&mut self.stream, id, self.scratch.version).await {
+            Ok(()) => {
+                // The response of the next pipelined request follows, if any.
+                self.in_progress = self.pipelined.front().map(|&next| (next, Phase::Response));
+                self.reading_response = false;
+                if !self.keep_alive() {
+                    self.closed = true;
+                }
+                Ok(())
+            }
+            Err(err) => {
+                self.broken = true;
+                Err(err.into_connection_closed(id, Phase::Response))
+            }
+        }
+    }
+
+    /// Bytes and records exchanged for the last request, the bytes read are
+    /// counted while its response is read. Requests failing before their
+    /// request is written leave it empty.
+    pub fn last_request_stats(&self) -> RequestStats {
+        self.stats
+    }
+
+    /// Whether the server keeps the connection open after the response of the
+    /// last request.
+    fn keep_alive(&self) -> bool {
+        self.request_flags.contains(BeginRequestFlags::KEEP_CONN)
+    }
+
+    /// Count of the request ids in use, i.e. the requests in flight. The ids
+    /// of a generator shared by `Client::with_id_generator` are counted for
+    /// all its clients.
+    pub fn in_flight(&self) -> usize {
+        self.request_id_generator.in_use()
+    }
+
+    /// Max count of the requests in flight, `FCGI_MAX_REQS` of the server if
+    /// known by `Client::get_values`, otherwise the pool size of the id
+    /// generator.
+    pub fn capacity(&self) -> usize {
+        self.max_reqs
+            .unwrap_or_else(|| self.request_id_generator.pool_size() as usize)
+    }
+
+    /// The variables of fastcgi server received by `Client::get_values`, or
+    /// negotiated on connect by [`ClientBuilder::set_negotiate_values`], so
+    /// they needn't be queried again.
+    pub fn server_values(&self) -> &HashMap<String, String> {
+        &self.server_values
+    }
+
+    /// Send request and receive response from fastcgi server, returns the
+    /// status of the completed request.
+    ///
+    /// The STDIN is streamed to the server as it's read, ended by an empty
+    /// record, so `CONTENT_LENGTH` has to be set for the server to read the
+    /// body. For a body of unknown length, use
+    /// [`Request::buffer_content_length`].
+    ///
+    /// If the client was built with a request timeout or a deadline by
+    /// [`ClientBuilder`], the exchange is limited like
+    /// [`Client::execute_with_timeout`].
+    ///
+    /// Like the STDIN, `stdout` and `stderr` may be trait objects, such as
+    /// `&mut (dyn AsyncWrite + Unpin)`.
+    ///
+    /// Every chunk of the response is written to `stdout` or `stderr` before
+    /// the next record is read, so a slow sink holds back the server by the
+    /// backpressure of the connection, rather than the response piling up in
+    /// memory.
+    pub async fn execute<I: AsyncRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        if let Some(deadline) = self.request_deadline(None) {
+            return self
+                .execute_before((&request.params).into(), (&mut request.stdin).into(), stdout, stderr, deadline)
+                .await;
+        }
+
+        let id = self.handle_new_request(&request.params, &mut request.stdin)
+            .await?;
+        self.handle_response(id, stdout, stderr).await
+    }
+
+    /// Like [`Client::execute`], but the records of STDIN are written straight
+    /// from the buffer of the body, such as a `BufReader` over a file, rather
+    /// than read into the buffer of the client first.
+    pub async fn execute_buf_read<I: AsyncBufRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let params = (&request.params).into();
+        let body = Body::BufRead(&mut request.stdin);
+        if let Some(deadline) = self.request_deadline(None) {
+            return self.execute_before(params, body, stdout, stderr, deadline).await;
+        }
+
+        let id = self
+            .new_request(Role::Responder, self.flags, params, Some(body), None, true)
+            .await?;
+        self.handle_response(id, stdout, stderr).await
+    }
+
+    /// Like [`Client::execute`], but the STDIN is written from the chunks
+    /// received from `body`, such as the chunks of an incoming HTTP body
+    /// forwarded by another task, each one framed into STDIN records as it's
+    /// received. The STDIN ends when all the senders are dropped.
+    ///
+    /// If a chunk is an error, the STDIN is ended, the request is aborted with
+    /// [`Client::abort`], and the error is returned. The timeouts of the client
+    /// don't apply.
+    pub async fn execute_stream_body(
+        &mut self,
+        params: &Params<'_>,
+        mut body: mpsc::Receiver<ClientResult<Bytes>>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let mut error = None;
+        let stdin = Body::Chunks {
+            chunks: &mut body,
+            error: &mut error,
+        };
+        let id = self
+            .new_request(Role::Responder, self.flags, params.into(), Some(stdin), None, true)
+            .await?;
+
+        if let Some(err) = error {
+            // The id is released by the abort, even if it fails.
+            let _ = self.abort(id).await;
+            return Err(err);
+        }
+        self.handle_response(id, stdout, stderr).await
+    }
+
+    /// Like [`Client::execute`], but the params are sent as the content
+    /// encoded by [`Params::encode`], so the same params aren't encoded again
+    /// for every request. The body is streamed as STDIN like
+    /// [`Client::execute`].
+    pub async fn execute_with_encoded_params(
+        &mut self,
+        encoded: &[u8],
+        body: &mut (dyn AsyncRead + Unpin),
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let params = RequestParams::Encoded(encoded);
+        if let Some(deadline) = self.request_deadline(None) {
+            return self.execute_before(params, body.into(), stdout, stderr, deadline).await;
+        }
+
+        let id = self
+            .new_request(Role::Responder, self.flags, params, Some(body.into()), None, true)
+            .await?;
+        self.handle_response(id, stdout, stderr).await
+    }
+
+    /// Like [`Client::execute`], but the response is read while the request
+    /// is written, on the halves of the stream split by `tokio::io::split`.
+    ///
+    /// For a backend responding before it reads the whole STDIN, such as a
+    /// filter of a large upload, [`Client::execute`] only reads the response
+    /// once the body is written, so both sides may wait on each other with
+    /// full socket buffers.
+    ///
+    /// The timeouts and `max_records_per_response` of the client apply, but
+    /// `max_response_bytes` doesn't, since the request can't be aborted while
+    /// it's written.
+    pub async fn execute_duplex<I: AsyncRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        self.abort_cancelled().await?;
+        let deadline = self.request_deadline(None);
+        let guard = match deadline {
+            Some(deadline) => timeout_at(deadline, RequestIdGuard::alloc(&self.request_id_generator))
+                .await
+                .map_err(|_| ClientError::NoAvailableRequestId)??,
+            None => RequestIdGuard::alloc(&self.request_id_generator).await?,
+        };
+        let id = guard.id();
+        self.check_idle(id)?;
+
+        debug!(
+            "[id = {}] Start handle duplex request, keep alive: {}.",
+            id,
+            self.flags.contains(BeginRequestFlags::KEEP_CONN)
+        );
+        self.last_request = Some((id, Instant::now()));
+        self.in_progress = Some((id, Phase::Request));
+
+        self.request_flags = self.flags;
+        self.stats = RequestStats::default();
+        let flags = self.flags;
+        let (strict_records, strict_version) = (self.strict_records, self.strict_version);
+        let max_records = self.max_records_per_response;
+        let scratch = &mut self.scratch;
+        let (mut read_stream, mut write_stream) = io::split(&mut self.stream);
+        let mut written = false;
+        let mut written_stats = RequestStats::default();
+        let mut stdout_bytes = 0;
+        let mut stderr_bytes = 0;
+        let (mut had_stdout, mut had_stderr) = (false, false);
+        let mut records_read = 0;
+        let mut output_records = 0;
+
+        let write = async {
+            written_stats = write_fastcgi_request(
+                &mut write_stream,
+                Role::Responder,
+                flags,
+                id,
+                (&request.params).into(),
+                Some((&mut request.stdin).into()),
+                None,
+                scratch,
+            )
+            .await?;
+            flush_request(&mut write_stream, id).await?;
+            written = true;
+            Ok::<_, ClientError>(())
+        };
+        let read = async {
+            loop {
+                let chunk = read_response_chunk(&mut read_stream, id, strict_records, strict_version).await?;
+                if let ResponseChunk::Stdout(_) | ResponseChunk::Stderr(_) = chunk {
+                    match max_records {
+                        Some(limit) if output_records >= limit => {
+                            debug!("[id = {}] Response exceeds limit of {} records.", id, limit);
+                            return Err(ClientError::TooManyRecords { id, limit });
+                        }
+                        _ => output_records += 1,
+                    }
+                }
+                records_read += 1;
+                match chunk {
+                    ResponseChunk::Stdout(content) => {
+                        stdout_bytes += content.len();
+                        had_stdout = true;
+                        stdout.write_all(&content).await?
+                    }
+                    ResponseChunk::Stderr(content) => {
+                        stderr_bytes += content.len();
+                        had_stderr = true;
+                        stderr.write_all(&content).await?
+                    }
+                    ResponseChunk::End {
+                        app_status,
+                        protocol_status,
+                    } => {
+                        return Ok::<_, ClientError>(RequestComplete {
+                            app_status,
+                            protocol_status,
+                        })
+                    }
+                }
+            }
+        };
+        let exchange = async { tokio::try_join!(write, read).map(|((), complete)| complete) };
+
+        let rv = match deadline {
+            Some(deadline) => match timeout_at(deadline, exchange).await {
+                Ok(rv) => rv,
+                Err(_) => Err(ClientError::Timeout {
+                    id,
+                    phase: if written { Phase::Response } else { Phase::Request },
+                }),
+            },
+            None => exchange.await,
+        };
+
+        self.stats = RequestStats {
+            stdout_bytes,
+            stderr_bytes,
+            had_stdout,
+            had_stderr,
+            records_read,
+            ..written_stats
+        };
+
+        // The request may be partially written when the response fails.
+        match &rv {
+            Err(err) if err.is_stream_error() || !written => self.broken = true,
+            Err(err) => {
+                // The end of request with an error protocol status.
+                if err.protocol_status().is_some() {
+                    self.stats.records_read += 1;
+                }
+                self.in_progress = None;
+                if !self.keep_alive() && err.protocol_status().is_some() {
+                    self.closed = true;
+                }
+            }
+            Ok(_) => {
+                self.in_progress = None;
+                if !self.keep_alive() {
+                    self.closed = true;
+                }
+            }
+        }
+        self.end_of_request(id, &rv, stdout_bytes, stderr_bytes);
+
+        drop(guard);
+
+        rv
+    }
+
+    /// Like [`Client::execute`], but fails with `ClientError::Timeout` if the
+    /// exchange doesn't complete within `timeout`.
+    ///
+    /// If the timeout elapses while reading the response of a keep alive
+    /// connection, an `FCGI_ABORT_REQUEST` is sent so the server stops working
+    /// on the request. Either way the connection is left in an unknown state
+    /// and shouldn't be used for another request.
+    ///
+    /// The timeout replaces the request timeout of the client, but the
+    /// deadline of the client, if any, still applies.
+    pub async fn execute_with_timeout<I: AsyncRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+        timeout: Duration,
+    ) -> ClientResult<RequestComplete> {
+        let deadline = self
+            .request_deadline(Some(timeout))
+            .unwrap_or_else(|| Instant::now() + timeout);
+        self.execute_before((&request.params).into(), (&mut request.stdin).into(), stdout, stderr, deadline)
+            .await
+    }
+
+    /// The deadline of a request starting now, by the timeout, or else the
+    /// request timeout of the client, and the deadline of the client,
+    /// whichever comes first.
+    fn request_deadline(&self, timeout: Option<Duration>) -> Option<Instant> {
+        let timeout = timeout
+            .or(self.request_timeout)
+            .map(|timeout| Instant::now() + timeout);
+        match (timeout, self.deadline) {
+            (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+            (timeout, deadline) => timeout.or(deadline),
+        }
+    }
+
+    async fn execute_before(
+        &mut self,
+        params: RequestParams<'_, '_>,
+        stdin: Body<'_>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+        deadline: Instant,
+    ) -> ClientResult<RequestComplete> {
+        let guard = timeout_at(deadline, RequestIdGuard::alloc(&self.request_id_generator))
+            .await
+            .map_err(|_| ClientError::NoAvailableRequestId)??;
+        let id = guard.id();
+
+        let rv = match timeout_at(
+            deadline,
+            self.request(id, Role::Responder, self.flags, params, Some(stdin), None, true),
+        )
+        .await
+        {
+            Ok(Ok(())) => match timeout_at(deadline, self.response(id, stdout, stderr)).await {
+                Ok(rv) => rv,
+                Err(_) => {
+                    self.broken = true;
+                    if self.keep_alive() {
+                        let header = Header::new(RequestType::AbortRequest, id, &[])
+                            .with_version(self.scratch.version);
+                        debug!("[id = {}] Send to stream: {:?}.", id, &header);
+                        let _ = header.write_to_stream(&mut self.stream, &[]).await;
+                        let _ = self.stream.flush().await;
+                    }
+                    Err(ClientError::Timeout {
+                        id,
+                        phase: Phase::Response,
+                    })
+                }
+            },
+            Ok(Err(err)) => Err(err),
+            Err(_) => {
+                self.broken = true;
+                Err(ClientError::Timeout {
+                    id,
+                    phase: Phase::Request,
+                })
+            }
+        };
+
+        drop(guard);
+
+        rv
+    }
+
+    /// Like [`Client::execute`], but if the connection is lost before any
+    /// output is received, such as being closed by the server after
+    /// `FCGI_MAX_REQS` requests, reconnects by `connect` and retries up to
+    /// `max_retries` times.
+    ///
+    /// The STDIN of the request is cloned for every attempt, so it must be
+    /// replayable, such as `&[u8]`. Only use it for idempotent requests, the
+    /// server may have processed a request whose connection was lost.
+    pub async fn execute_retry<I, F, Fut>(
+        &mut self,
+        connect: F,
+        request: &Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+        max_retries: usize,
+    ) -> ClientResult<RequestComplete>
+    where
+        I: AsyncRead + Unpin + Clone,
+        F: Fn() -> Fut,
+        Fut: Future<Output = ClientResult<S>>,
+    {
+        let mut retries = 0;
+
+        loop {
+            let mut stdin = request.stdin.clone();
+            let (rv, written) = match self
+                .new_request(Role::Responder, self.flags, (&request.params).into(), Some((&mut stdin).into()), None, true)
+                .await
+            {
+                Ok(id) => {
+                    let mut stdout = CountWrite::new(stdout);
+                    let mut stderr = CountWrite::new(stderr);
+                    let rv = self.handle_response(id, &mut stdout, &mut stderr).await;
+                    (rv, stdout.count + stderr.count)
+                }
+                Err(err) => (Err(err), 0),
+            };
+
+            match rv {
+                Err(err) if written == 0 && err.is_connection_lost() && retries < max_retries => {
+                    retries += 1;
+                    debug!("Connection lost ({}), reconnect and retry {}.", err, retries);
+                    self.stream = BufReader::with_capacity(self.read_buffer_size, connect().await?);
+                    self.broken = false;
+                    self.in_progress = None;
+                    self.closed = false;
+                }
+                rv => return rv,
+            }
+        }
+    }
+
+    /// Like [`Client::execute`], but if the server is overloaded, i.e. the
+    /// request ends with `FCGI_OVERLOADED` before any output, the request is
+    /// sent again after the delay of `policy`, up to its max retries, then it
+    /// fails with `ClientError::Overloaded`.
+    ///
+    /// The connection has to be keep alive to send the request again.
+    pub async fn execute_with_backoff<I>(
+        &mut self,
+        request: &Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+        policy: &BackoffPolicy,
+    ) -> ClientResult<RequestComplete>
+    where
+        I: AsyncRead + Unpin + Clone,
+    {
+        let mut retries = 0;
+
+        loop {
+            let mut stdin = request.stdin.clone();
+            let id = self
+                .new_request(Role::Responder, self.flags, (&request.params).into(), Some((&mut stdin).into()), None, true)
+                .await?;
+            let mut stdout = CountWrite::new(stdout);
+            let mut stderr = CountWrite::new(stderr);
+            let rv = self.handle_response(id, &mut stdout, &mut stderr).await;
+            let written = stdout.count + stderr.count;
+
+            match rv {
+                Err(ClientError::EndRequestOverloaded { .. }) if written == 0 => {
+                    if retries >= policy.max_retries {
+                        return Err(ClientError::Overloaded { retries });
+                    }
+                    let delay = policy.delay(retries);
+                    retries += 1;
+                    debug!("[id = {}] Server overloaded, retry {} after {:?}.", id, retries, delay);
+                    sleep(delay).await;
+                }
+                rv => return rv,
+            }
+        }
+    }
+
+    /// Send request to fastcgi server, then the response can be received
+    /// chunk by chunk from the returned [`ResponseStream`], without buffering
+    /// the whole response.
+    ///
+    /// There is no channel in between, a record is only read when the next
+    /// chunk is asked for, so at most one record is held while the consumer is
+    /// busy, and the server is held back by the backpressure of the
+    /// connection.
+    pub async fn execute_stream<I: AsyncRead + Unpin>(
+        &mut self,
+        mut request: Request<'_, I>,
+    ) -> ClientResult<ResponseStream<'_, S>> {
+        let id = self.handle_new_request(&request.params, &mut request.stdin)
+            .await?;
+        let guard = self.guard_held(id);
+        Ok(ResponseStream {
+            client: self,
+            id,
+            guard: Some(guard),
+            received: Received::default(),
+            done: false,
+        })
+    }
+
+    /// Send request and receive response from fastcgi server, collecting
+    /// STDOUT and STDERR of the response into a [`Response`].
+    ///
+    /// A non-zero app status fails with `ClientError::RequestFailed`, with the
+    /// STDERR of the response.
+    pub async fn execute_buffered<I: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I>,
+    ) -> ClientResult<Response> {
+        let mut response = Response::default();
+        let complete = self
+            .execute(request, &mut response.stdout, &mut response.stderr)
+            .await?;
+        response.into_result(complete.app_status)
+    }
+
+    /// Send request and receive response from fastcgi server, discarding
+    /// STDOUT and STDERR, for a request only made for its side effects.
+    /// Returns the app status.
+    pub async fn execute_discard<I: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I>,
+    ) -> ClientResult<u32> {
+        let complete = self
+            .execute(request, &mut io::sink(), &mut io::sink())
+            .await?;
+        Ok(complete.app_status)
+    }
+
+    /// Send request and receive response from fastcgi server, collecting
+    /// the STDOUT and STDERR chunks into `chunks` in the order they arrive,
+    /// so that warnings remain aligned with the output around them.
+    ///
+    /// Only `ResponseChunk::Stdout` and `ResponseChunk::Stderr` are pushed,
+    /// the end of request is returned.
+    pub async fn execute_interleaved<I: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I>,
+        chunks: &mut Vec<ResponseChunk>,
+    ) -> ClientResult<RequestComplete> {
+        let mut stream = self.execute_stream(request).await?;
+        loop {
+            match stream.next().await {
+                Some(Ok(ResponseChunk::End {
+                    app_status,
+                    protocol_status,
+                })) => {
+                    return Ok(RequestComplete {
+                        app_status,
+                        protocol_status,
+                    })
+                }
+                Some(Ok(chunk)) => chunks.push(chunk),
+                Some(Err(err)) => return Err(err),
+                // The stream always ends with `End` or an error.
+                None => unreachable!(),
+            }
+        }
+    }
+
+    /// Send request of the Authorizer role and receive response from fastcgi
+    /// server, only the params are sent since the authorizer receives no
+    /// STDIN.
+    pub async fn execute_authorizer(&mut self, params: &Params<'_>) -> ClientResult<Response> {
+        let mut response = Response::default();
+        self.execute_request(
+            RequestBuilder::new(params).set_role(Role::Authorizer),
+            &mut response.stdout,
+            &mut response.stderr,
+        )
+        .await?;
+        Ok(response)
+    }
+
+    /// Send request of the Authorizer role like
+    /// [`Client::execute_authorizer`], then parse the response into an
+    /// [`AuthResult`] with the status and the `Variable-*` headers.
+    #[cfg(feature = "http")]
+    pub async fn authorize(&mut self, params: &Params<'_>) -> ClientResult<AuthResult> {
+        self.execute_authorizer(params).await?.parse_auth()
+    }
+
+    /// Send request of the Filter role and receive response from fastcgi
+    /// server, the `data` (the file to be filtered) is sent as the
+    /// `FCGI_DATA` stream after STDIN.
+    ///
+    /// The server relies on the `FCGI_DATA_LENGTH` param to read the `data`,
+    /// so it's required, otherwise the request fails with
+    /// `ClientError::MissingParam` before anything is written. Setting the
+    /// `FCGI_DATA_LAST_MOD` param is the responsibility of the caller.
+    pub async fn execute_filter(
+        &mut self,
+        params: &Params<'_>,
+        stdin: &mut (dyn AsyncRead + Unpin),
+        data: &mut (dyn AsyncRead + Unpin),
+    ) -> ClientResult<Response> {
+        let mut response = Response::default();
+        self.execute_request(
+            RequestBuilder::new(params)
+                .set_role(Role::Filter)
+                .set_stdin(stdin)
+                .set_data(data),
+            &mut response.stdout,
+            &mut response.stderr,
+        )
+        .await?;
+        Ok(response)
+    }
+
+    /// Send request built by [`RequestBuilder`] and receive response from
+    /// fastcgi server, with the records of its role. The timeouts of the
+    /// client don't apply.
+    ///
+    /// A filter requires the `FCGI_DATA_LENGTH` param like
+    /// [`Client::execute_filter`], unless its DATA isn't set, then the param
+    /// is sent as 0.
+    pub async fn execute_request(
+        &mut self,
+        request: RequestBuilder<'_>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let RequestBuilder {
+            role,
+            params,
+            stdin,
+            data,
+            keep_alive,
+        } = request;
+        let flags = match keep_alive {
+            Some(keep_alive) => keep_alive.into(),
+            None => self.flags,
+        };
+
+        let with_data_length;
+        let params = match role {
+            Role::Filter if !params.contains_key("FCGI_DATA_LENGTH") => {
+                if data.is_some() {
+                    return Err(ClientError::MissingParam {
+                        name: "FCGI_DATA_LENGTH".to_owned(),
+                    });
+                }
+                let mut params = params.clone();
+                params.insert("FCGI_DATA_LENGTH", "0");
+                with_data_length = params;
+                &with_data_length
+            }
+            _ => params,
+        };
+        let (mut empty_stdin, mut empty_data) = (io::empty(), io::empty());
+        let stdin = match (role, stdin) {
+            (Role::Authorizer, _) => None,
+            (_, Some(stdin)) => Some(Body::Read(&mut *stdin)),
+            (_, None) => Some(Body::Read(&mut empty_stdin)),
+        };
+        let data: Option<&mut (dyn AsyncRead + Unpin)> = match (role, data) {
+            (Role::Filter, Some(data)) => Some(&mut *data),
+            (Role::Filter, None) => Some(&mut empty_data),
+            _ => None,
+        };
+
+        let id = self
+            .new_request(role, flags, params.into(), stdin, data, true)
+            .await?;
+        self.handle_response(id, stdout, stderr).await
+    }
+
+    /// Query the variables of fastcgi server by the `FCGI_GET_VALUES`
+    /// management record, such as `FCGI_MAX_CONNS`, `FCGI_MAX_REQS` and
+    /// `FCGI_MPXS_CONNS`.
+    ///
+    /// The variables unknown to the server are omitted from the result. The
+    /// result is kept in [`Client::server_values`].
+    ///
+    /// A server not supporting the management records replies with
+    /// `FCGI_UNKNOWN_TYPE`, which fails with
+    /// `ClientError::UnknownTypeResponse`, the connection can still be used.
+    pub async fn get_values(&mut self, names: &[&str]) -> ClientResult<HashMap<String, String>> {
+        let rv = handle_fastcgi_get_values(&mut self.stream, names).await;
+        match &rv {
+            Ok(values) => self.store_values(values),
+            Err(err) if err.is_stream_error() => self.broken = true,
+            Err(_) => {}
+        }
+
+        rv
+    }
+
+    /// Check that the connection is alive by a `FCGI_GET_VALUES` round trip,
+    /// such as a connection left idle in a pool, which the server may have
+    /// closed.
+    ///
+    /// The server replying is enough, even with `FCGI_UNKNOWN_TYPE`. A lost
+    /// connection fails with `ClientError::ConnectionClosed`.
+    pub async fn ping(&mut self) -> ClientResult<()> {
+        self.abort_cancelled().await?;
+        if let Some((in_progress, _)) = self.in_progress {
+            return Err(ClientError::Busy { id: in_progress });
+        }
+        if self.closed {
+            return Err(ClientError::ConnectionClosed {
+                id: 0,
+                during: Phase::Request,
+            });
+        }
+
+        match self.get_values(&["FCGI_MPXS_CONNS"]).await {
+            Ok(_) | Err(ClientError::UnknownTypeResponse { .. }) => Ok(()),
+            Err(err) => {
+                debug!("[id = 0] Ping failed: {}.", err);
+                Err(err.into_connection_closed(0, Phase::Response))
+            }
+        }
+    }
+
+    fn store_values(&mut self, values: &HashMap<String, String>) {
+        if let Some(max_reqs) = values.get("FCGI_MAX_REQS").and_then(|v| v.parse().ok()) {
+            self.max_reqs = Some(max_reqs);
+        }
+        self.server_values
+            .extend(values.iter().map(|(name, value)| (name.clone(), value.clone())));
+    }
+
+    /// Abort the request of id with the `FCGI_ABORT_REQUEST` record, the
+    /// response is drained until the `FCGI_END_REQUEST` of the request, and
+    /// the id is released.
+    ///
+    /// The request of a cancelled `execute` can be aborted this way once its
+    /// request was fully written, so the client can be used again. Its id was
+    /// already released when the `execute` was dropped, so it isn't released
+    /// again, which would free the id if it's allocated to another request.
+    pub async fn abort(&mut self, id: u16) -> ClientResult<()> {
+        if let Some((in_progress, Phase::Request)) = self.in_progress {
+            if in_progress == id {
+                return Err(ClientError::Busy { id });
+            }
+        }
+
+        let rv = abort_request(&mut self.stream, id, self.scratch.version).await;
+        if rv.is_err() {
+            self.broken = true;
+        } else if matches!(self.in_progress, Some((in_progress, _)) if in_progress == id) {
+            self.in_progress = None;
+            self.reading_response = false;
+        }
+
+        if self.held_ids.remove(&id) {
+            self.request_id_generator.release(id);
+        } else {
+            debug!("[id = {}] Id already released, not releasing it again.", id);
+        }
+
+        rv
+    }
+
+    /// Send the content as records of the type, split into several records if
+    /// it exceeds the max length of a record. An empty content is sent as one
+    /// empty record.
+    ///
+    /// This is the raw framing without the orchestration of `execute`, the
+    /// caller is responsible for the correctness of the protocol, such as
+    /// the request id and the empty records ending the streams.
+    pub async fn send_record(
+        &mut self,
+        r#type: RequestType,
+        id: u16,
+        content: &[u8],
+    ) -> ClientResult<()> {
+        let rv = async {
+            if content.is_empty() {
+                let header = Header::new(r#type, id, &[]).with_version(self.scratch.version);
+                debug!("[id = {}] Send to stream: {:?}.", id, &header);
+                header.write_to_stream(&mut self.stream, &[]).await?;
+            } else {
+                Header::write_to_stream_batches(
+                    r#type,
+                    id,
+                    &mut self.stream,
+                    &mut &content[..],
+                    &mut self.scratch,
+                    Some(|header| {
+                        debug!("[id = {}] Send to stream: {:?}.", id, &header);
+                        header
+                    }),
+                )
+                .await?;
+            }
+            self.stream.flush().await
+        }
+        .await;
+        if rv.is_err() {
+            self.broken = true;
+        }
+
+        Ok(rv?)
+    }
+
+    /// Read the next record of any request, returns its header and content
+    /// without padding.
+    ///
+    /// Like [`Client::send_record`], the caller is responsible for the
+    /// correctness of the protocol.
+    pub async fn read_record(&mut self) -> ClientResult<(RecordHeader, Vec<u8>)> {
+        let rv = async {
+            let header = Header::new_from_stream(&mut self.stream).await?;
+            debug!("[id = {}] Receive from stream: {:?}.", header.request_id, &header);
+            let content = header.read_content_from_stream(&mut self.stream).await?;
+            Ok::<_, io::Error>(((&header).into(), content.into()))
+        }
+        .await;
+        if rv.is_err() {
+            self.broken = true;
+        }
+
+        Ok(rv?)
+    }
+
+    pub async fn generate_id(&mut self) -> ClientResult<u16> {
+        let id = self.request_id_generator.alloc().await?;
+        self.held_ids.insert(id);
+        Ok(id)
+    }
+
+    pub async fn handle_new_request<'a>(
+        &mut self,
+        params: &Params<'a>,
+        body: &mut (dyn AsyncRead + Unpin),
+    ) -> ClientResult<u16> {
+        self.new_request(Role::Responder, self.flags, params.into(), Some(body.into()), None, true).await
+    }
+
+    /// Like [`Client::handle_new_request`], but without flushing the stream,
+    /// for a buffered stream whose writes are flushed at once with other
+    /// writes, see [`Client::flush`].
+    ///
+    /// The request may still be in the buffer of the stream, so the stream
+    /// must be flushed before waiting for the response, otherwise the
+    /// response may never come.
+    pub async fn handle_new_request_no_flush<'a>(
+        &mut self,
+        params: &Params<'a>,
+        body: &mut (dyn AsyncRead + Unpin),
+    ) -> ClientResult<u16> {
+        self.new_request(Role::Responder, self.flags, params.into(), Some(body.into()), None, false).await
+    }
+
+    /// Send the request without reading any response, returns its id, whose
+    /// response is received later by [`Client::recv`]. Several requests can
+    /// be sent before receiving their responses, pipelined into the stream.
+    ///
+    /// The server processes the requests in the order they were sent, so the
+    /// responses must be received in that order too. The connection must be
+    /// keep alive, otherwise the server closes it after the first response.
+    /// The server may also stop reading the requests while its responses
+    /// aren't read, so the requests pipelined at once should fit in the
+    /// buffers of the connection, otherwise `send` waits forever.
+    pub async fn send<I: AsyncRead + Unpin>(&mut self, mut request: Request<'_, I>) -> ClientResult<u16> {
+        // The response of the first pipelined request is the one in progress.
+        let pending = match self.in_progress {
+            Some((id, Phase::Response)) if self.pipelined.front() == Some(&id) => self.in_progress.take(),
+            _ => None,
+        };
+
+        let rv = self.handle_new_request(&request.params, &mut request.stdin).await;
+        if let Ok(id) = rv {
+            self.pipelined.push_back(id);
+        }
+        if pending.is_some() && (rv.is_ok() || self.in_progress.is_none()) {
+            self.in_progress = pending;
+        }
+        rv
+    }
+
+    /// Receive the response of the request of id sent by [`Client::send`],
+    /// writing its STDOUT and STDERR like [`Client::execute`].
+    ///
+    /// The responses are received in the order the requests were sent, so
+    /// receiving the response of a later request than the first one waiting
+    /// fails with `ClientError::Busy` of the first one. An id not sent by
+    /// `send`, or already received, fails with `ClientError::ResponseNotFound`.
+    pub async fn recv(
+        &mut self,
+        id: u16,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        match self.pipelined.front() {
+            Some(&first) if first == id => {}
+            Some(&first) if self.pipelined.contains(&id) => return Err(ClientError::Busy { id: first }),
+            _ => return Err(ClientError::ResponseNotFound { id }),
+        }
+        self.pipelined.pop_front();
+
+        let rv = self.handle_response(id, stdout, stderr).await;
+        // The response of the next pipelined request follows.
+        if self.in_progress.is_none() {
+            if let Some(&next) = self.pipelined.front() {
+                self.in_progress = Some((next, Phase::Response));
+            }
+        }
+        rv
+    }
+
+    /// Flush the stream, sending the writes left in its buffer, such as a
+    /// request sent by [`Client::handle_new_request_no_flush`].
+    pub async fn flush(&mut self) -> ClientResult<()> {
+        let rv = self.stream.flush().await;
+        if rv.is_err() {
+            self.broken = true;
+        }
+        Ok(rv?)
+    }
+
+    pub async fn handle_request<'a>(
+        &mut self,
+        id: u16,
+        params: &Params<'a>,
+        body: &mut (dyn AsyncRead + Unpin),
+    ) -> ClientResult<()> {
+        self.request(id, Role::Responder, self.flags, params.into(), Some(body.into()), None, true).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn new_request(
+        &mut self,
+        role: Role,
+        flags: BeginRequestFlags,
+        params: RequestParams<'_, '_>,
+        stdin: Option<Body<'_>>,
+        data: Option<&mut (dyn AsyncRead + Unpin)>,
+        flush: bool,
+    ) -> ClientResult<u16> {
+        let guard = RequestIdGuard::alloc(&self.request_id_generator).await?;
+        self.request(guard.id(), role, flags, params, stdin, data, flush)
+            .await?;
+        let id = guard.keep();
+        self.held_ids.insert(id);
+        Ok(id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn request(
+        &mut self,
+        id: u16,
+        role: Role,
+        flags: BeginRequestFlags,
+        params: RequestParams<'_, '_>,
+        stdin: Option<Body<'_>>,
+        data: Option<&mut (dyn AsyncRead + Unpin)>,
+        flush: bool,
+    ) -> ClientResult<()> {
+        self.abort_cancelled().await?;
+        self.check_idle(id)?;
+
+        debug!(
+            "[id = {}] Start handle request, role: {:?}, keep alive: {}.",
+            id,
+            role,
+            flags.contains(BeginRequestFlags::KEEP_CONN)
+        );
+        self.last_request = Some((id, Instant::now()));
+        self.request_flags = flags;
+        self.stats = RequestStats::default();
+
+        let write_stream = &mut self.stream;
+
+        self.in_progress = Some((id, Phase::Request));
+        let rv =
+            write_fastcgi_request(write_stream, role, flags, id, params, stdin, data, &mut self.scratch).await;
+        let rv = match rv {
+            Ok(stats) => {
+                self.stats = stats;
+                if flush {
+                    flush_request(write_stream, id).await
+                } else {
+                    Ok(())
+                }
+            }
+            Err(err) => Err(err),
+        };
+        match &rv {
+            Ok(()) => self.in_progress = Some((id, Phase::Response)),
+            Err(err) if err.is_stream_error() => self.broken = true,
+            Err(_) => self.in_progress = None,
+        }
+
+        rv
+    }
+
+    /// Check that a new request of id can be sent, no request is in progress
+    /// and the connection wasn't closed after the last response.
+    fn check_idle(&self, id: u16) -> ClientResult<()> {
+        if id == 0 {
+            return Err(ClientError::ReservedRequestId);
+        }
+        if let Some((in_progress, _)) = self.in_progress {
+            debug!("[id = {}] Request {} is still in progress.", id, in_progress);
+            return Err(ClientError::Busy { id: in_progress });
+        }
+        if self.closed {
+            debug!("[id = {}] Connection closed after the last response.", id);
+            return Err(ClientError::ConnectionClosed {
+                id,
+                during: Phase::Request,
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn handle_response(&mut self, id: u16,
+                             stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+                             stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let _guard = self.guard_held(id);
+        self.response(id, stdout, stderr).await
+    }
+
+    /// Guard of the id, which is no longer held for the caller, since the
+    /// guard releases it.
+    fn guard_held(&mut self, id: u16) -> RequestIdGuard {
+        self.held_ids.remove(&id);
+        RequestIdGuard::new(self.request_id_generator.clone(), id)
+    }
+
+    async fn response(
+        &mut self,
+        id: u16,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let mut received = Received::default();
+        let mut stdout_bytes = 0;
+        let mut stderr_bytes = 0;
+        self.reading_response = true;
+
+        let rv = loop {
+            let rv = match self.read_chunk(id, &mut received).await {
+                Ok(ResponseChunk::Stdout(content)) => {
+                    stdout_bytes += content.len();
+                    stdout.write_all(&content).await
+                }
+                Ok(ResponseChunk::Stderr(content)) => {
+                    stderr_bytes += content.len();
+                    stderr.write_all(&content).await
+                }
+                Ok(ResponseChunk::End {
+                    app_status,
+                    protocol_status,
+                }) => {
+                    break Ok(RequestComplete {
+                        app_status,
+                        protocol_status,
+                    })
+                }
+                Err(err) => break Err(err),
+            };
+            if let Err(err) = rv {
+                self.broken = true;
+                break Err(err.into());
+            }
+        };
+        self.reading_response = false;
+
+        self.end_of_request(id, &rv, stdout_bytes, stderr_bytes);
+
+        rv
+    }
+
+    /// Log the summary of the request, with the duration since its request
+    /// started to be written, and record it to the metrics.
+    fn end_of_request(
+        &mut self,
+        id: u16,
+        rv: &ClientResult<RequestComplete>,
+        stdout_bytes: usize,
+        stderr_bytes: usize,
+    ) {
+        let elapsed = match self.last_request.take() {
+            Some((last_id, started)) if last_id == id => Some(started.elapsed()),
+            last_request => {
+                self.last_request = last_request;
+                None
+            }
+        };
+        match rv {
+            Ok(complete) => debug!(
+                "[id = {}] End of request in {:?}, app status: {}, stdout: {} bytes, stderr: {} bytes.",
+                id, elapsed, complete.app_status, stdout_bytes, stderr_bytes
+            ),
+            Err(err) => debug!(
+                "[id = {}] Request failed in {:?}: {}, stdout: {} bytes, stderr: {} bytes.",
+                id, elapsed, err, stdout_bytes, stderr_bytes
+            ),
+        }
+        #[cfg(feature = "metrics")]
+        crate::metrics::record(rv, elapsed, stdout_bytes, stderr_bytes);
+    }
+
+    /// Read the next chunk of response, `received` counts the bytes and the
+    /// records of STDOUT and STDERR to enforce `max_response_bytes` and
+    /// `max_records_per_response`.
+    async fn read_chunk(&mut self, id: u16, received: &mut Received) -> ClientResult<ResponseChunk> {
+        // Waiting for the next record consumes nothing, so the response can be
+        // aborted if cancelled meanwhile.
+        if self.read_buffer_size > 0 {
+            if let Err(err) = self.stream.fill_buf().await {
+                self.broken = true;
+                return Err(ClientError::from(err).into_connection_closed(id, Phase::Response));
+            }
+        }
+        self.reading_record = true;
+        let rv = self.read_record_chunk(id, received).await;
+        self.reading_record = false;
+        rv
+    }
+
+    async fn read_record_chunk(&mut self, id: u16, received: &mut Received) -> ClientResult<ResponseChunk> {
+        let rv = read_response_chunk(&mut self.stream, id, self.strict_records, self.strict_version).await;
+
+        let rv = match (rv, self.max_records_per_response) {
+            (Ok(ResponseChunk::Stdout(_)), Some(limit))
+            | (Ok(ResponseChunk::Stderr(_)), Some(limit))
+                if received.records >= limit =>
+            {
+                debug!("[id = {}] Response exceeds limit of {} records.", id, limit);
+                Err(ClientError::TooManyRecords { id, limit })
+            }
+            (rv, _) => rv,
+        };
+
+        let rv = match (rv, self.max_response_bytes) {
+            (Ok(ResponseChunk::Stdout(content)), Some(limit))
+            | (Ok(ResponseChunk::Stderr(content)), Some(limit))
+                if received.bytes + content.len() > limit =>
+            {
+                received.bytes += content.len();
+                Err(self.abort_too_large(id, limit).await)
+            }
+            (Ok(chunk), _) => {
+                if let ResponseChunk::Stdout(content) | ResponseChunk::Stderr(content) = &chunk {
+                    received.bytes += content.len();
+                    received.records += 1;
+                    self.stats.records_read += 1;
+                }
+                match &chunk {
+                    ResponseChunk::Stdout(content) => {
+                        self.stats.stdout_bytes += content.len();
+                        self.stats.had_stdout = true;
+                    }
+                    ResponseChunk::Stderr(content) => {
+                        self.stats.stderr_bytes += content.len();
+                        self.stats.had_stderr = true;
+                    }
+                    ResponseChunk::End { .. } => {}
+                }
+                Ok(chunk)
+            }
+            (Err(err), _) => Err(err),
+        };
+
+        // The response is over, unless the stream is in an unknown state.
+        match &rv {
+            Ok(ResponseChunk::End { .. }) => self.in_progress = None,
+            Err(err) if !err.is_stream_error() => self.in_progress = None,
+            _ => {}
+        }
+
+        // The end of request, even with an error protocol status.
+        let ended = match &rv {
+            Ok(chunk) => matches!(chunk, ResponseChunk::End { .. }),
+            Err(err) => err.protocol_status().is_some(),
+        };
+        if ended {
+            self.stats.records_read += 1;
+            if !self.keep_alive() {
+                self.closed = true;
+            }
+        }
 
-/// Async client for handling communication between fastcgi server.
-pub struct Client<S: AsyncRead + AsyncWrite + Send + Sync + Unpin> {
-    stream: S,
+        if let Err(err) = &rv {
+            if err.is_stream_error() {
+                self.broken = true;
+            }
+        }
+
+        rv
+    }
+
+    /// Abort the request whose response exceeds the limit, the connection can
+    /// be reused if it's keep alive and the abort succeeds.
+    async fn abort_too_large(&mut self, id: u16, limit: usize) -> ClientError {
+        debug!("[id = {}] Response exceeds limit of {} bytes.", id, limit);
+        if !self.keep_alive()
+            || abort_request(&mut self.stream, id, self.scratch.version)
+                .await
+                .is_err()
+        {
+            self.broken = true;
+        }
+        ClientError::ResponseTooLarge { id, limit }
+    }
+}
+
+/// Bytes and records of STDOUT and STDERR received for a response.
+#[derive(Default)]
+struct Received {
+    bytes: usize,
+    records: usize,
+}
+
+/// Response of fastcgi server received chunk by chunk, created by
+/// [`Client::execute_stream`].
+///
+/// A record is only read from the connection when the next chunk is asked
+/// for. Dropping it before the end of response leaves the connection in an
+/// unknown state, so it can't be used for another request.
+pub struct ResponseStream<'a, S: ClientStream> {
+    client: &'a mut Client<S>,
+    id: u16,
+    /// Released at the end of response, or when dropped before.
+    guard: Option<RequestIdGuard>,
+    received: Received,
+    done: bool,
+}
+
+impl<S: ClientStream> ResponseStream<'_, S> {
+    /// Request id of the response.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Receive the next chunk of response, the last chunk is
+    /// `ResponseChunk::End` or an error, after which `None` is returned.
+    pub async fn next(&mut self) -> Option<ClientResult<ResponseChunk>> {
+        if self.done {
+            return None;
+        }
+
+        let rv = self.client.read_chunk(self.id, &mut self.received).await;
+        if matches!(rv, Ok(ResponseChunk::End { .. }) | Err(_)) {
+            self.done = true;
+        }
+        if self.done {
+            self.guard = None;
+        }
+
+        Some(rv)
+    }
+
+    /// Receive the rest of response, collecting STDOUT and STDERR into a
+    /// [`Response`] like [`Client::execute_buffered`], with the status of the
+    /// end of request. Unlike `execute_buffered`, a non-zero app status isn't
+    /// an error: it's returned in the [`RequestComplete`] for the caller to
+    /// check, with the STDERR of the response.
+    ///
+    /// The chunks already received aren't in the response, and a stream
+    /// already ended fails with `ClientError::ResponseNotFound`.
+    pub async fn collect_response(mut self) -> ClientResult<(Response, RequestComplete)> {
+        let mut response = Response::default();
+        while let Some(chunk) = self.next().await {
+            match chunk? {
+                ResponseChunk::Stdout(content) => response.stdout.extend_from_slice(&content),
+                ResponseChunk::Stderr(content) => response.stderr.extend_from_slice(&content),
+                ResponseChunk::End {
+                    app_status,
+                    protocol_status,
+                } => {
+                    return Ok((
+                        response,
+                        RequestComplete {
+                            app_status,
+                            protocol_status,
+                        },
+                    ))
+                }
+            }
+        }
+        Err(ClientError::ResponseNotFound { id: self.id })
+    }
+}
+
+impl<S: ClientStream> Drop for ResponseStream<'_, S> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.client.broken = true;
+        }
+    }
+}
+
+/// Default size of the buffer the records of responses are read through.
+const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Time for the server to answer the negotiation of
+/// [`ClientBuilder::set_negotiate_values`] without a connect timeout.
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Builder of [`Client`], with the timeouts of connecting and of requests.
+///
+/// The connect timeout limits [`ClientBuilder::connect`], the request timeout
+/// limits every `execute` of the client, and the deadline, if set, limits
+/// both. So an overall deadline of connecting and a request is expressed by
+/// `set_deadline(Instant::now() + timeout)`. Expired timeouts fail with
+/// `ClientError::Timeout` of the phase that was running.
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
     keep_alive: bool,
-    request_id_generator: RequestIdGenerator,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    max_response_bytes: Option<usize>,
+    max_records_per_response: Option<usize>,
+    read_buffer_size: Option<usize>,
+    tcp_options: TcpOptions,
+    negotiate_values: Vec<String>,
+    /// Uid and gid expected of the server of a unix socket, see
+    /// `ClientBuilder::expect_peer_cred`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    peer_cred: Option<(u32, u32)>,
 }
 
-impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin> Client<S> {
-    /// Construct a `Client` Object with stream, such as `tokio::net::TcpStream`
-    /// or `tokio::net::UnixStream`.
-    pub fn new(stream: S, keep_alive: bool) -> Self {
+impl ClientBuilder {
+    /// Builder of client without keep alive, timeouts or response limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn set_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn set_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    pub fn set_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// See [`Client::set_max_response_bytes`].
+    pub fn set_max_response_bytes(mut self, max_response_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// See [`Client::set_max_records_per_response`].
+    pub fn set_max_records_per_response(mut self, max_records_per_response: Option<usize>) -> Self {
+        self.max_records_per_response = max_records_per_response;
+        self
+    }
+
+    /// See [`Client::set_read_buffer_size`].
+    pub fn set_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = Some(read_buffer_size);
+        self
+    }
+
+    /// See [`TcpOptions::set_nodelay`], on by default.
+    pub fn set_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_options = self.tcp_options.set_nodelay(nodelay);
+        self
+    }
+
+    /// See [`TcpOptions::set_keepalive`].
+    pub fn set_tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.tcp_options = self.tcp_options.set_keepalive(keepalive);
+        self
+    }
+
+    /// See [`TcpOptions::set_linger`].
+    pub fn set_linger(mut self, linger: Option<Duration>) -> Self {
+        self.tcp_options = self.tcp_options.set_linger(linger);
+        self
+    }
+
+    /// Query the variables of the names, such as `FCGI_MAX_CONNS` and
+    /// `FCGI_MPXS_CONNS`, right after [`ClientBuilder::connect`], see
+    /// [`Client::server_values`].
+    ///
+    /// Connecting doesn't fail if the server doesn't answer within the connect
+    /// timeout, or 1 second without one, or answers with an error, such as
+    /// `FCGI_UNKNOWN_TYPE`, the values are left empty. If the connection was
+    /// left in an unknown state, another one is connected.
+    pub fn set_negotiate_values(mut self, names: &[&str]) -> Self {
+        self.negotiate_values = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Check that the server process of a unix socket runs as `uid` and
+    /// `gid`, by `SO_PEERCRED` right after connecting, otherwise connecting
+    /// fails with `ClientError::PeerCredMismatch`. It guards against another
+    /// process listening on the socket path, such as after the socket file
+    /// was replaced.
+    ///
+    /// Only on Linux, it doesn't apply to TCP connections.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn expect_peer_cred(mut self, uid: u32, gid: u32) -> Self {
+        self.peer_cred = Some((uid, gid));
+        self
+    }
+
+    /// Build the client over a connected stream.
+    pub fn build<S: ClientStream>(&self, stream: S) -> Client<S> {
+        let mut client = Client::new(stream, self.keep_alive)
+            .set_max_response_bytes(self.max_response_bytes)
+            .set_max_records_per_response(self.max_records_per_response);
+        if let Some(read_buffer_size) = self.read_buffer_size {
+            client = client.set_read_buffer_size(read_buffer_size);
+        }
+        client.request_timeout = self.request_timeout;
+        client.deadline = self.deadline;
+        client
+    }
+
+    /// Connect to fastcgi server of the address, by tcp or unix socket,
+    /// within the connect timeout and the deadline.
+    pub async fn connect(&self, addr: Address<'_>) -> ClientResult<Client<Stream>> {
+        let mut client = self.build(self.connect_stream(addr).await?);
+        if self.negotiate_values.is_empty() {
+            return Ok(client);
+        }
+
+        let names: Vec<&str> = self.negotiate_values.iter().map(String::as_str).collect();
+        let timeout = self.connect_timeout.unwrap_or(NEGOTIATE_TIMEOUT);
+        let deadline = match self.deadline {
+            Some(deadline) => deadline.min(Instant::now() + timeout),
+            None => Instant::now() + timeout,
+        };
+        match timeout_at(deadline, handle_fastcgi_get_values(&mut client.stream, &names)).await {
+            Ok(Ok(values)) => client.store_values(&values),
+            // The answer of a server not supporting the management records was
+            // read, so the connection can be used.
+            Ok(Err(ClientError::UnknownTypeResponse { .. })) => {
+                debug!("Server doesn't support FCGI_GET_VALUES.")
+            }
+            rv => {
+                match rv {
+                    Ok(Err(err)) => debug!("Negotiate with {} failed: {}.", addr, err),
+                    _ => debug!("Negotiate with {} timed out.", addr),
+                }
+                client = self.build(self.connect_stream(addr).await?);
+            }
+        }
+        Ok(client)
+    }
+
+    async fn connect_stream(&self, addr: Address<'_>) -> ClientResult<Stream> {
+        let connect_deadline = self
+            .connect_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let deadline = match (connect_deadline, self.deadline) {
+            (Some(connect_deadline), Some(deadline)) => Some(connect_deadline.min(deadline)),
+            (connect_deadline, deadline) => connect_deadline.or(deadline),
+        };
+
+        let stream = match deadline {
+            Some(deadline) => timeout_at(deadline, Stream::connect_with_options(addr, &self.tcp_options))
+                .await
+                .map_err(|_| {
+                    debug!("Connect to {} timed out.", addr);
+                    ClientError::Timeout {
+                        id: 0,
+                        phase: Phase::Connect,
+                    }
+                })??,
+            None => Stream::connect_with_options(addr, &self.tcp_options).await?,
+        };
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let (Some((uid, gid)), Stream::Unix(unix_stream)) = (self.peer_cred, &stream) {
+            let cred = unix_stream.peer_cred()?;
+            if cred.uid() != uid || cred.gid() != gid {
+                debug!(
+                    "Peer of {} runs as uid {} gid {}, expected uid {} gid {}.",
+                    addr,
+                    cred.uid(),
+                    cred.gid(),
+                    uid,
+                    gid
+                );
+                return Err(ClientError::PeerCredMismatch {
+                    uid: cred.uid(),
+                    gid: cred.gid(),
+                });
+            }
+        }
+
+        Ok(stream)
+    }
+}
+
+/// Exponential backoff of [`Client::execute_with_backoff`], the delay before
+/// the first retry is `initial_delay`, multiplied by `multiplier` for each
+/// later retry, up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct BackoffPolicy {
+    max_retries: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+}
+
+impl Default for BackoffPolicy {
+    /// 3 retries, 100 milliseconds initially, doubled up to 5 seconds.
+    fn default() -> Self {
         Self {
-            stream,
-            keep_alive,
-            request_id_generator: RequestIdGenerator::new(Duration::from_millis(1500)),
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2,
         }
     }
+}
 
-    /// Send request and receive response from fastcgi server.
-    pub async fn execute<I: AsyncRead + Unpin>(
-        &mut self,
-        mut request: Request<'_, I>,
-        stdout: &mut (impl AsyncWrite + Unpin),
-        stderr: &mut (impl AsyncWrite + Unpin),
-    ) -> ClientResult<()> {
-        let id = self.handle_new_request(&request.params, &mut request.stdin)
-            .await?;
-        self.handle_response(id, stdout, stderr).await
+impl BackoffPolicy {
+    pub fn set_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
     }
 
-    pub async fn generate_id(&mut self) -> ClientResult<u16> {
-        self.request_id_generator.alloc().await
+    pub fn set_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
     }
 
-    pub async fn handle_new_request<'a>(
-        &mut self,
-        params: &Params<'a>,
-        body: &mut (dyn AsyncRead + Unpin),
-    ) -> ClientResult<u16> {
-        let id = self.request_id_generator.alloc().await?;
-        match self.handle_request(id, params, body).await {
-            Ok(()) => Ok(id),
-            Err(err) => {
-                self.request_id_generator.release(id).await;
-                Err(err)
+    pub fn set_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn set_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Delay before the retry after `retries` retries.
+    fn delay(&self, retries: usize) -> Duration {
+        let mut delay = self.initial_delay;
+        for _ in 0..retries {
+            delay = delay.saturating_mul(self.multiplier);
+            if delay >= self.max_delay {
+                break;
             }
         }
+        delay.min(self.max_delay)
     }
+}
 
-    pub async fn handle_request<'a>(
-        &mut self,
-        id: u16,
-        params: &Params<'a>,
-        body: &mut (dyn AsyncRead + Unpin),
-    ) -> ClientResult<()> {
-        let write_stream = &mut self.stream;
+/// Writer counting the bytes written through it.
+struct CountWrite<'a, W: ?Sized> {
+    inner: &'a mut W,
+    count: usize,
+}
 
-        debug!("[id = {}] Start handle request.", id);
+impl<'a, W: ?Sized> CountWrite<'a, W> {
+    fn new(inner: &'a mut W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
 
-        handle_fastcgi_request(write_stream, self.keep_alive, id, params, body).await
+impl<W: AsyncWrite + Unpin + ?Sized> AsyncWrite for CountWrite<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.count += n;
+        }
+        poll
     }
 
-    pub async fn handle_response(&mut self, id: u16,
-                             stdout: &mut (impl AsyncWrite + Unpin),
-                             stderr: &mut (impl AsyncWrite + Unpin),
-    ) -> ClientResult<()> {
-        let read_stream = &mut self.stream;
-        let rv = handle_fastcgi_response(read_stream, id, stdout, stderr).await;
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
 
-        self.request_id_generator.release(id).await;
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
 
-        rv
+impl Client<Stream> {
+    /// Connect to fastcgi server of the address, by tcp or unix socket.
+    pub async fn connect(addr: Address<'_>, keep_alive: bool) -> ClientResult<Self> {
+        Ok(Self::new(Stream::connect(addr).await?, keep_alive))
     }
 }
 
+/// Connect to fastcgi server of the address without keep alive, execute one
+/// request with [`Client::execute_buffered`], then close the connection.
+///
+/// ```no_run
+/// use fastcgi_client::{Address, Params};
+/// use tokio::io;
+///
+/// # async fn run() -> fastcgi_client::ClientResult<()> {
+/// let params = Params::default()
+///     .set_request_method("GET")
+///     .set_script_filename("/var/www/index.php");
+/// let response = fastcgi_client::request(Address::Tcp("127.0.0.1", 9000), params, io::empty()).await?;
+/// println!("{}", String::from_utf8_lossy(&response.stdout));
+/// # Ok(())
+/// # }
+/// ```
+pub async fn request<I: AsyncRead + Unpin>(
+    addr: Address<'_>,
+    params: Params<'_>,
+    body: I,
+) -> ClientResult<Response> {
+    let mut client = Client::connect(addr, false).await?;
+    client.execute_buffered(Request::new(params, body)).await
+}
+
 pub async fn handle_fastcgi_request<'a>(
     write_stream: &mut (dyn AsyncWrite + Unpin),
     keep_alive: bool,
@@ -91,20 +2003,126 @@ pub async fn handle_fastcgi_request<'a>(
     params: &Params<'a>,
     body: &mut (dyn AsyncRead + Unpin),
 ) -> ClientResult<()> {
+    let scratch = &mut RecordBuf::new(MAX_LENGTH);
+    write_fastcgi_request(write_stream, Role::Responder, keep_alive.into(), id, params.into(), Some(body.into()), None, scratch).await?;
+    flush_request(write_stream, id).await
+}
+
+async fn flush_request(write_stream: &mut (dyn AsyncWrite + Unpin), id: u16) -> ClientResult<()> {
+    write_stream
+        .flush()
+        .await
+        .map_err(|err| ClientError::from(err).into_connection_closed(id, Phase::Request))
+}
+
+/// STDIN of a request, read into the buffer of the client, or written from
+/// its own buffer, or from the chunks received from a channel.
+enum Body<'a> {
+    Read(&'a mut (dyn AsyncRead + Unpin)),
+    BufRead(&'a mut (dyn AsyncBufRead + Unpin)),
+    /// The STDIN is ended at the first error of the chunks, which is kept in
+    /// `error` rather than failing the writing of the request.
+    Chunks {
+        chunks: &'a mut mpsc::Receiver<ClientResult<Bytes>>,
+        error: &'a mut Option<ClientError>,
+    },
+}
+
+impl<'a, R: AsyncRead + Unpin> From<&'a mut R> for Body<'a> {
+    fn from(body: &'a mut R) -> Self {
+        Body::Read(body)
+    }
+}
+
+impl<'a> From<&'a mut (dyn AsyncRead + Unpin)> for Body<'a> {
+    fn from(body: &'a mut (dyn AsyncRead + Unpin)) -> Self {
+        Body::Read(body)
+    }
+}
+
+/// Params of a request, or their content already encoded by
+/// [`Params::encode`].
+#[derive(Clone, Copy)]
+enum RequestParams<'a, 'p> {
+    Params(&'a Params<'p>),
+    Encoded(&'a [u8]),
+}
+
+impl<'a, 'p> From<&'a Params<'p>> for RequestParams<'a, 'p> {
+    fn from(params: &'a Params<'p>) -> Self {
+        RequestParams::Params(params)
+    }
+}
+
+/// Write the records of request, the STDIN and DATA streams are skipped if
+/// they are `None`.
+#[allow(clippy::too_many_arguments)]
+async fn write_fastcgi_request(
+    write_stream: &mut (dyn AsyncWrite + Unpin),
+    role: Role,
+    flags: BeginRequestFlags,
+    id: u16,
+    params: RequestParams<'_, '_>,
+    stdin: Option<Body<'_>>,
+    data: Option<&mut (dyn AsyncRead + Unpin)>,
+    scratch: &mut RecordBuf,
+) -> ClientResult<RequestStats> {
+    write_request_records(write_stream, role, flags, id, params, stdin, data, scratch)
+        .await
+        .map_err(|err| err.into_connection_closed(id, Phase::Request))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn write_request_records(
+    write_stream: &mut (dyn AsyncWrite + Unpin),
+    role: Role,
+    flags: BeginRequestFlags,
+    id: u16,
+    params: RequestParams<'_, '_>,
+    stdin: Option<Body<'_>>,
+    data: Option<&mut (dyn AsyncRead + Unpin)>,
+    scratch: &mut RecordBuf,
+) -> ClientResult<RequestStats> {
     debug!("[id = {}] Start handle request.", id);
 
-    let begin_request_rec = BeginRequestRec::new(id, Role::Responder, keep_alive).await?;
-    debug!("[id = {}] Send to stream: {:?}.", id, &begin_request_rec);
-    begin_request_rec.write_to_stream(write_stream).await?;
+    // Validate the params before writing anything, so the stream isn't left
+    // with a partial request.
+    let encoded;
+    let params = match params {
+        RequestParams::Params(params) => {
+            let param_pairs = ParamPairs::new(params)?;
 
-    let param_pairs = ParamPairs::new(params);
-    debug!("[id = {}] Params will be sent: {:?}.", id, &param_pairs);
+            if stdin.is_some() && !params.contains_key("CONTENT_LENGTH") {
+                warn!(
+                    "[id = {}] CONTENT_LENGTH isn't set, the server may ignore the STDIN.",
+                    id
+                );
+            }
+
+            debug!("[id = {}] Params will be sent: {:?}.", id, &param_pairs);
+            encoded = param_pairs.to_content();
+            &encoded[..]
+        }
+        RequestParams::Encoded(content) => {
+            debug!("[id = {}] Encoded params of {} bytes will be sent.", id, content.len());
+            content
+        }
+    };
+
+    // The records before STDIN are small, so they are written at once.
+    let mut head = Vec::new();
+
+    let mut begin_request_rec = BeginRequestRec::new(id, role, flags).await?;
+    begin_request_rec.header = begin_request_rec.header.with_version(scratch.version);
+    debug!("[id = {}] Send to stream: {:?}.", id, &begin_request_rec);
+    begin_request_rec.write_to_stream(&mut head).await?;
 
     Header::write_to_stream_batches(
         RequestType::Params,
         id,
-        write_stream,
-        &mut &param_pairs.to_content().await?[..],
+        &mut head,
+        &mut &params[..],
+        scratch,
         Some(|header| {
             debug!("[id = {}] Send to stream for Params: {:?}.", id, &header);
             header
@@ -113,115 +2131,343 @@ pub async fn handle_fastcgi_request<'a>(
         .await?;
 
     // this empty record marks the end of the Params-stream
-    Header::write_to_stream_batches(
-        RequestType::Params,
-        id,
-        write_stream,
-        &mut tokio::io::empty(),
-        Some(|header| {
-            debug!("[id = {}] Send to stream for Params: {:?}.", id, &header);
-            header
-        }),
-    )
-        .await?;
+    let header = Header::new(RequestType::Params, id, &[]).with_version(scratch.version);
+    debug!("[id = {}] Send to stream for Params: {:?}.", id, &header);
+    header.write_to_stream(&mut head, &[]).await?;
 
-    Header::write_to_stream_batches(
-        RequestType::Stdin,
-        id,
-        write_stream,
-        body,
-        Some(|header| {
-            debug!("[id = {}] Send to stream for Stdin: {:?}.", id, &header);
-            header
-        }),
-    )
-        .await?;
+    write_stream.write_all(&head).await?;
+    if scratch.flush_interval.is_some() && (stdin.is_some() || data.is_some()) {
+        write_stream.flush().await?;
+        scratch.flushed();
+    }
 
-    // this empty record marks the end of the Stdin-stream
-    Header::write_to_stream_batches(
-        RequestType::Stdin,
-        id,
-        write_stream,
-        &mut tokio::io::empty(),
-        Some(|header| {
+    let stdin_bytes = Cell::new(0);
+    if let Some(body) = stdin {
+        let before_write = Some(|header: Header| {
             debug!("[id = {}] Send to stream for Stdin: {:?}.", id, &header);
+            stdin_bytes.set(stdin_bytes.get() + header.content_length as usize);
             header
-        }),
-    )
-        .await?;
+        });
+        match body {
+            Body::Read(body) => {
+                Header::write_to_stream_batches(RequestType::Stdin, id, write_stream, body, scratch, before_write)
+                    .await?
+            }
+            Body::BufRead(body) => {
+                Header::write_buf_to_stream_batches(RequestType::Stdin, id, write_stream, body, scratch, before_write)
+                    .await?
+            }
+            Body::Chunks { chunks, error } => {
+                while let Some(chunk) = chunks.recv().await {
+                    match chunk {
+                        Ok(chunk) => {
+                            Header::write_slice_to_stream_batches(
+                                RequestType::Stdin,
+                                id,
+                                write_stream,
+                                &chunk,
+                                scratch,
+                                before_write,
+                            )
+                            .await?
+                        }
+                        Err(err) => {
+                            debug!("[id = {}] Body failed: {}.", id, err);
+                            *error = Some(err);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        // this empty record marks the end of the Stdin-stream
+        let header = Header::new(RequestType::Stdin, id, &[]).with_version(scratch.version);
+        debug!("[id = {}] Send to stream for Stdin: {:?}.", id, &header);
+        header.write_to_stream(write_stream, &[]).await?;
+    }
+
+    if let Some(data) = data {
+        Header::write_to_stream_batches(
+            RequestType::Data,
+            id,
+            write_stream,
+            data,
+            scratch,
+            Some(|header| {
+                debug!("[id = {}] Send to stream for Data: {:?}.", id, &header);
+                header
+            }),
+        )
+            .await?;
 
-    write_stream.flush().await?;
+        // this empty record marks the end of the Data-stream
+        let header = Header::new(RequestType::Data, id, &[]).with_version(scratch.version);
+        debug!("[id = {}] Send to stream for Data: {:?}.", id, &header);
+        header.write_to_stream(write_stream, &[]).await?;
+    }
 
-    Ok(())
+    Ok(RequestStats {
+        stdin_bytes: stdin_bytes.get(),
+        params_bytes: params.len(),
+        ..Default::default()
+    })
 }
 
+/// Read the response of id from the stream until the `FCGI_END_REQUEST`,
+/// writing STDOUT and STDERR to the sinks.
+///
+/// The future is `Send` if the sinks are, so the reader can be spawned, such
+/// as on the read half of a stream split from the writer:
+///
+/// ```no_run
+/// use fastcgi_client::client::handle_fastcgi_response;
+/// use tokio::{io, net::TcpStream};
+///
+/// # async fn run() -> fastcgi_client::ClientResult<()> {
+/// let stream = TcpStream::connect(("127.0.0.1", 9000)).await?;
+/// let (mut read_stream, _write_stream) = io::split(stream);
+/// let reader = tokio::spawn(async move {
+///     let mut stdout = Vec::new();
+///     let complete = handle_fastcgi_response(&mut read_stream, 1, &mut stdout, &mut io::sink()).await?;
+///     Ok::<_, fastcgi_client::ClientError>((complete, stdout))
+/// });
+/// let (complete, stdout) = reader.await.unwrap()?;
+/// # Ok(())
+/// # }
+/// ```
 pub async fn handle_fastcgi_response(
     read_stream: &mut (impl AsyncRead + Unpin + Send),
     id: u16,
-    stdout: &mut (impl AsyncWrite + Unpin),
-    stderr: &mut (impl AsyncWrite + Unpin),
-) -> ClientResult<()> {
-    let global_end_request_rec = loop {
+    stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+    stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+) -> ClientResult<RequestComplete> {
+    loop {
+        match read_fastcgi_response_chunk(read_stream, id).await? {
+            ResponseChunk::Stdout(content) => stdout.write_all(&content).await?,
+            ResponseChunk::Stderr(content) => stderr.write_all(&content).await?,
+            ResponseChunk::End {
+                app_status,
+                protocol_status,
+            } => {
+                return Ok(RequestComplete {
+                    app_status,
+                    protocol_status,
+                })
+            }
+        }
+    }
+}
+
+/// Read the next record of the response of id, a non complete protocol
+/// status of `FCGI_END_REQUEST` is returned as error, and so is the
+/// connection closed by the server, as `ClientError::ConnectionClosed`.
+///
+/// A record of another request fails with `ClientError::UnexpectedRecord`.
+pub async fn read_fastcgi_response_chunk(
+    read_stream: &mut (impl AsyncRead + Unpin + Send),
+    id: u16,
+) -> ClientResult<ResponseChunk> {
+    read_response_chunk(read_stream, id, true, false).await
+}
+
+/// Like [`read_fastcgi_response_chunk`], but the records of other requests
+/// are discarded unless `strict`, and the records of other versions than 1
+/// fail if `strict_version`.
+async fn read_response_chunk(
+    read_stream: &mut (impl AsyncRead + Unpin + Send),
+    id: u16,
+    strict: bool,
+    strict_version: bool,
+) -> ClientResult<ResponseChunk> {
+    read_response_record(read_stream, id, strict, strict_version)
+        .await
+        .map_err(|err| err.into_connection_closed(id, Phase::Response))
+}
+
+async fn read_response_record(
+    read_stream: &mut (impl AsyncRead + Unpin + Send),
+    id: u16,
+    strict: bool,
+    strict_version: bool,
+) -> ClientResult<ResponseChunk> {
+    let header = loop {
         let header = Header::new_from_stream(read_stream).await?;
         debug!("[id = {}] Receive from stream: {:?}.", id, &header);
 
-        if header.request_id != id {
-            return Err(ClientError::ResponseNotFound { id }.into());
+        if header.version != VERSION_1 {
+            if strict_version {
+                return Err(ClientError::UnsupportedVersion {
+                    version: header.version,
+                });
+            }
+            warn!("[id = {}] Read record of version {} as version 1.", id, header.version);
+        }
+
+        if header.request_id == id {
+            break header;
+        }
+        if strict {
+            return Err(ClientError::UnexpectedRecord {
+                id: header.request_id,
+                request_type: header.r#type,
+            });
+        }
+        warn!(
+            "[id = {}] Discard record of type {:?} of request id {} not in progress.",
+            id, header.r#type, header.request_id
+        );
+        header.read_content_from_stream(read_stream).await?;
+    };
+
+    match header.r#type {
+        RequestType::Stdout => {
+            let content = header.read_content_from_stream(read_stream).await?;
+            debug!("PHP stdout: '{}'", content_preview(&content));
+            Ok(ResponseChunk::Stdout(content))
+        }
+        RequestType::Stderr => {
+            let content = header.read_content_from_stream(read_stream).await?;
+            debug!("PHP stderr: '{}'", content_preview(&content));
+            Ok(ResponseChunk::Stderr(content))
+        }
+        RequestType::EndRequest => {
+            let end_request_rec = EndRequestRec::from_header(&header, read_stream).await?;
+            debug!("[id = {}] Receive from stream: {:?}.", id, &end_request_rec);
+            let complete = end_request_rec
+                .end_request
+                .protocol_status
+                .convert_to_client_result(end_request_rec.end_request.app_status)?;
+            Ok(ResponseChunk::End {
+                app_status: complete.app_status,
+                protocol_status: complete.protocol_status,
+            })
+        }
+        r#type => Err(ClientError::UnknownRequestType {
+            id,
+            request_type: r#type,
+        }),
+    }
+}
+
+/// The content for logging, only the first and last 50 bytes of a longer one,
+/// by the length of the content received rather than of the header.
+fn content_preview(content: &[u8]) -> String {
+    const EDGE: usize = 50;
+
+    match (content.get(..EDGE), content.get(content.len().saturating_sub(EDGE)..)) {
+        (Some(head), Some(tail)) if content.len() > 2 * EDGE => format!(
+            "{}...{}",
+            String::from_utf8_lossy(head),
+            String::from_utf8_lossy(tail)
+        ),
+        _ => String::from_utf8_lossy(content).into_owned(),
+    }
+}
+
+pub async fn handle_fastcgi_abort(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    id: u16,
+) -> ClientResult<()> {
+    abort_request(stream, id, VERSION_1).await
+}
+
+async fn abort_request(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin + Send),
+    id: u16,
+    version: u8,
+) -> ClientResult<()> {
+    let header = Header::new(RequestType::AbortRequest, id, &[]).with_version(version);
+    debug!("[id = {}] Send to stream: {:?}.", id, &header);
+    header.write_to_stream(stream, &[]).await?;
+    stream.flush().await?;
+
+    match handle_fastcgi_response(stream, id, &mut io::sink(), &mut io::sink()).await {
+        Ok(_) => Ok(()),
+        Err(err) if !err.is_stream_error() => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Max length of the content of the `FCGI_GET_VALUES_RESULT` records of one
+/// `FCGI_GET_VALUES`.
+const MAX_VALUES_LENGTH: usize = 4 * MAX_LENGTH;
+
+pub async fn handle_fastcgi_get_values(
+    stream: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+    names: &[&str],
+) -> ClientResult<HashMap<String, String>> {
+    let get_values_rec = GetValuesRec::new(names).await?;
+    debug!("[id = 0] Send to stream: {:?}.", &get_values_rec);
+    get_values_rec.write_to_stream(stream).await?;
+    stream.flush().await?;
+
+    // The result may be split across records, even in the middle of a pair,
+    // so read until the content received decodes as whole pairs, and while a
+    // name is missing if another record is already received. The server omits
+    // the names it doesn't know, so it can't be waited for.
+    let mut content = Vec::new();
+    loop {
+        let header = Header::new_from_stream(stream).await?;
+        debug!("[id = 0] Receive from stream: {:?}.", &header);
+        let record = header.read_content_from_stream(stream).await?;
+
+        if header.request_id != 0 {
+            return Err(ClientError::ResponseNotFound { id: 0 });
         }
 
         match header.r#type {
-            RequestType::Stdout => {
-                let content = header.read_content_from_stream(read_stream).await?;
-                if header.content_length > 100 {
-                    debug!("PHP stdout: '{}...{}'", String::from_utf8_lossy(&content[0..50]), String::from_utf8_lossy(&content[(header.content_length-50) as usize..]));
-                }
-                else {
-                    debug!("PHP stdout: '{}'", String::from_utf8_lossy(&content));
-                }
-                let len = content.len();
-                let written_len = AsyncWriteExt::write(stdout, content.as_ref()).await?;
-                if len != written_len {
-                    return Err(ClientError::UnexpectedEndOfOutput{
-                        id,
-                        output_type: RequestType::Stdout,
-                        written: written_len,
-                        expected: len
-                    }.into())
-                }
-            }
-            RequestType::Stderr => {
-                let content = header.read_content_from_stream(read_stream).await?;
-                debug!("PHP stderr: '{}'", String::from_utf8_lossy(&content));
-                let len = content.len();
-                let written_len = AsyncWriteExt::write(stderr, content.as_ref()).await?;
-                if len != written_len {
-                    return Err(ClientError::UnexpectedEndOfOutput{
-                        id,
-                        output_type: RequestType::Stderr,
-                        written: written_len,
-                        expected: len
-                    }.into())
-                }
+            RequestType::GetValuesResult if content.len() + record.len() > MAX_VALUES_LENGTH => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "FCGI_GET_VALUES_RESULT is too long",
+                )
+                .into())
             }
-            RequestType::EndRequest => {
-                let end_request_rec = EndRequestRec::from_header(&header, read_stream).await?;
-                debug!("[id = {}] Receive from stream: {:?}.", id, &end_request_rec);
-                break Some(end_request_rec);
+            RequestType::GetValuesResult => content.extend_from_slice(&record),
+            // The type of the record rejected is the first byte of the body.
+            RequestType::UnknownType if !record.is_empty() => {
+                return Err(ClientError::UnknownTypeResponse {
+                    requested: record[0],
+                })
             }
             r#type => {
                 return Err(ClientError::UnknownRequestType {
+                    id: 0,
                     request_type: r#type,
-                }
-                    .into())
+                })
             }
         }
-    };
 
-    match global_end_request_rec {
-        Some(end_request_rec) => end_request_rec
-            .end_request
-            .protocol_status
-            .convert_to_client_result(end_request_rec.end_request.app_status),
-        None => unreachable!(),
+        if let Ok(pairs) = decode_name_value_pairs(&content) {
+            let values: HashMap<String, String> = pairs.into_iter().collect();
+            if stream.buffer().is_empty() || names.iter().all(|&name| values.contains_key(name)) {
+                return Ok(values);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_preview_bounds() {
+        for len in 0..=100 {
+            let content = vec![b'x'; len];
+            assert_eq!(content_preview(&content), "x".repeat(len));
+        }
+
+        let mut content = vec![b'a'; 50];
+        content.extend_from_slice(&[b'-'; 50]);
+        content.extend_from_slice(&[b'z'; 50]);
+        assert_eq!(content_preview(&content), format!("{}...{}", "a".repeat(50), "z".repeat(50)));
+
+        // The edges in the middle of a code point are lossy, not a panic.
+        let content = "é".repeat(60);
+        let preview = content_preview(content.as_bytes());
+        assert!(preview.starts_with(&"é".repeat(25)));
+        assert!(preview.ends_with(&"é".repeat(25)));
     }
-}
\ No newline at end of file
+}
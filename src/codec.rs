@@ -0,0 +1,225 @@
+//! Framing of fastcgi records, for using the protocol without `Client`.
+//!
+//! `FastCgiCodec::decode` and `FastCgiCodec::encode` have the signatures of
+//! `tokio_util::codec::Decoder` and `Encoder`, so they can back a `Framed`
+//! pipeline, or be driven by hand with a `BytesMut` buffer.
+
+use crate::meta::{ProtocolStatus, RequestType, Role, HEADER_LEN, MAX_LENGTH, VERSION_1};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+
+/// A fastcgi record, the `id` is the request id, the content of the stream
+/// records is kept raw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    BeginRequest {
+        id: u16,
+        role: Role,
+        keep_alive: bool,
+    },
+    AbortRequest {
+        id: u16,
+    },
+    EndRequest {
+        id: u16,
+        app_status: u32,
+        protocol_status: ProtocolStatus,
+    },
+    Params {
+        id: u16,
+        content: Bytes,
+    },
+    Stdin {
+        id: u16,
+        content: Bytes,
+    },
+    Stdout {
+        id: u16,
+        content: Bytes,
+    },
+    Stderr {
+        id: u16,
+        content: Bytes,
+    },
+    Data {
+        id: u16,
+        content: Bytes,
+    },
+    /// Management record, the content is the name-value pairs.
+    GetValues {
+        content: Bytes,
+    },
+    /// Management record, the content is the name-value pairs.
+    GetValuesResult {
+        content: Bytes,
+    },
+    /// Reply of a management record of type unknown to the server.
+    UnknownType {
+        r#type: u8,
+    },
+}
+
+impl Record {
+    /// The request id of the record, 0 for the management records.
+    pub fn request_id(&self) -> u16 {
+        match *self {
+            Record::BeginRequest { id, .. }
+            | Record::AbortRequest { id }
+            | Record::EndRequest { id, .. }
+            | Record::Params { id, .. }
+            | Record::Stdin { id, .. }
+            | Record::Stdout { id, .. }
+            | Record::Stderr { id, .. }
+            | Record::Data { id, .. } => id,
+            Record::GetValues { .. }
+            | Record::GetValuesResult { .. }
+            | Record::UnknownType { .. } => 0,
+        }
+    }
+
+    fn from_content(r#type: u8, id: u16, content: Bytes) -> io::Result<Self> {
+        let request_type = RequestType::try_from_u8(r#type)
+            .ok_or_else(|| invalid_record("unknown record type"))?;
+        let record = match request_type {
+            RequestType::BeginRequest => {
+                if content.len() < 3 {
+                    return Err(invalid_record("FCGI_BEGIN_REQUEST content too short"));
+                }
+                let role = Role::from_u16(u16::from_be_bytes([content[0], content[1]]))
+                    .ok_or_else(|| invalid_record("unknown role of FCGI_BEGIN_REQUEST"))?;
+                Record::BeginRequest {
+                    id,
+                    role,
+                    keep_alive: content[2] & 1 != 0,
+                }
+            }
+            RequestType::AbortRequest => Record::AbortRequest { id },
+            RequestType::EndRequest => {
+                if content.len() < 5 {
+                    return Err(invalid_record("FCGI_END_REQUEST content too short"));
+                }
+                Record::EndRequest {
+                    id,
+                    app_status: u32::from_be_bytes([content[0], content[1], content[2], content[3]]),
+                    protocol_status: ProtocolStatus::from_u8(content[4]),
+                }
+            }
+            RequestType::Params => Record::Params { id, content },
+            RequestType::Stdin => Record::Stdin { id, content },
+            RequestType::Stdout => Record::Stdout { id, content },
+            RequestType::Stderr => Record::Stderr { id, content },
+            RequestType::Data => Record::Data { id, content },
+            RequestType::GetValues => Record::GetValues { content },
+            RequestType::GetValuesResult => Record::GetValuesResult { content },
+            RequestType::UnknownType => match content.first() {
+                Some(&r#type) => Record::UnknownType { r#type },
+                None => return Err(invalid_record("FCGI_UNKNOWN_TYPE content too short")),
+            },
+        };
+        Ok(record)
+    }
+
+    fn into_parts(self) -> (RequestType, u16, Bytes) {
+        match self {
+            Record::BeginRequest {
+                id,
+                role,
+                keep_alive,
+            } => {
+                let mut content = BytesMut::with_capacity(8);
+                content.put_u16(role as u16);
+                content.put_u8(keep_alive as u8);
+                content.put_bytes(0, 5);
+                (RequestType::BeginRequest, id, content.freeze())
+            }
+            Record::AbortRequest { id } => (RequestType::AbortRequest, id, Bytes::new()),
+            Record::EndRequest {
+                id,
+                app_status,
+                protocol_status,
+            } => {
+                let mut content = BytesMut::with_capacity(8);
+                content.put_u32(app_status);
+                content.put_u8(protocol_status as u8);
+                content.put_bytes(0, 3);
+                (RequestType::EndRequest, id, content.freeze())
+            }
+            Record::Params { id, content } => (RequestType::Params, id, content),
+            Record::Stdin { id, content } => (RequestType::Stdin, id, content),
+            Record::Stdout { id, content } => (RequestType::Stdout, id, content),
+            Record::Stderr { id, content } => (RequestType::Stderr, id, content),
+            Record::Data { id, content } => (RequestType::Data, id, content),
+            Record::GetValues { content } => (RequestType::GetValues, 0, content),
+            Record::GetValuesResult { content } => (RequestType::GetValuesResult, 0, content),
+            Record::UnknownType { r#type } => {
+                let mut content = BytesMut::with_capacity(8);
+                content.put_u8(r#type);
+                content.put_bytes(0, 7);
+                (RequestType::UnknownType, 0, content.freeze())
+            }
+        }
+    }
+}
+
+/// Codec of fastcgi records.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FastCgiCodec;
+
+impl FastCgiCodec {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Decode a record from the front of `src`, `None` if the record isn't
+    /// complete yet, then more bytes should be appended to `src`.
+    pub fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Record>> {
+        if src.len() < HEADER_LEN {
+            src.reserve(HEADER_LEN - src.len());
+            return Ok(None);
+        }
+        if src[0] != VERSION_1 {
+            return Err(invalid_record("unsupported fastcgi version"));
+        }
+
+        let content_length = u16::from_be_bytes([src[4], src[5]]) as usize;
+        let record_length = HEADER_LEN + content_length + src[6] as usize;
+        if src.len() < record_length {
+            src.reserve(record_length - src.len());
+            return Ok(None);
+        }
+
+        let mut record = src.split_to(record_length);
+        let r#type = record[1];
+        let id = u16::from_be_bytes([record[2], record[3]]);
+        record.advance(HEADER_LEN);
+        record.truncate(content_length);
+        Record::from_content(r#type, id, record.freeze()).map(Some)
+    }
+
+    /// Encode the record with its padding to the end of `dst`.
+    pub fn encode(&mut self, item: Record, dst: &mut BytesMut) -> io::Result<()> {
+        let (r#type, id, content) = item.into_parts();
+        if content.len() > MAX_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "content exceeds one record",
+            ));
+        }
+
+        let padding_length = ((8 - content.len() % 8) % 8) as u8;
+        dst.reserve(HEADER_LEN + content.len() + padding_length as usize);
+        dst.put_u8(VERSION_1);
+        dst.put_u8(r#type as u8);
+        dst.put_u16(id);
+        dst.put_u16(content.len() as u16);
+        dst.put_u8(padding_length);
+        dst.put_u8(0);
+        dst.put_slice(&content);
+        dst.put_bytes(0, padding_length as usize);
+        Ok(())
+    }
+}
+
+fn invalid_record(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
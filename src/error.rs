@@ -1,43 +1,173 @@
-use crate::meta::{ProtocolStatus, RequestType};
+use crate::meta::{ProtocolStatus, RequestType, Role};
+use std::{
+    fmt::{self, Display},
+    io,
+};
 
 pub type ClientResult<T> = Result<T, ClientError>;
 
+/// Phase of the exchange with fastcgi server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Connecting to fastcgi server.
+    Connect,
+    /// Writing the request records.
+    Request,
+    /// Reading the response records.
+    Response,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Phase::Connect => f.write_str("connecting"),
+            Phase::Request => f.write_str("writing request"),
+            Phase::Response => f.write_str("reading response"),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
-    /// Wapper of `tokio::io::Error`
+    /// Wrapper of `tokio::io::Error`
     #[error(transparent)]
     Io(#[from] tokio::io::Error),
 
+    /// Failed to connect to fastcgi server.
+    #[error("Connect to `{address}` failed: {source}")]
+    Connect { address: String, source: io::Error },
+
     /// Usually not happen.
-    #[error("Response not found of request id `{id}`")]
+    #[error("Request id `{id}` not found")]
     RequestIdNotFound { id: u16 },
 
     /// Usually not happen.
     #[error("Response not found of request id `{id}`")]
     ResponseNotFound { id: u16 },
 
-    #[error("Writing to output `{output_type}` ended prematurely for id `{id}`. Written `{written}` instead of `{expected}` bytes")]
-    UnexpectedEndOfOutput { id: u16, output_type: RequestType, written: usize, expected: usize },
+    /// A record of another request than the one in progress was received,
+    /// such as a record of a request already ended.
+    #[error("Unexpected record of type `{request_type:?}` of request id `{id}` not in progress")]
+    UnexpectedRecord { id: u16, request_type: RequestType },
+
+    /// A record of another version than 1 was received, see
+    /// `Client::set_strict_version`.
+    #[error("Unsupported fastcgi version `{version}`")]
+    UnsupportedVersion { version: u8 },
 
-    /// Maybe unimplemented request type received fom response.
-    #[error("Response not found of request id `{request_type}`")]
-    UnknownRequestType { request_type: RequestType },
+    /// Maybe unimplemented request type received from response.
+    #[error("Unexpected record of type `{request_type:?}` ({request_type}) for request id `{id}`")]
+    UnknownRequestType { id: u16, request_type: RequestType },
 
-    /// Response not complete, first is protocol status and second is app status, see fastcgi protocol.
+    /// The server replied with `FCGI_UNKNOWN_TYPE` to the management record
+    /// of the requested type, such as `FCGI_GET_VALUES` (9), which it doesn't
+    /// support. The type is the byte of the reply, which may be unknown to the
+    /// protocol.
+    #[error("Management record of type `{requested}` not supported by server")]
+    UnknownTypeResponse { requested: u8 },
+
+    /// The request ended with the protocol status `FCGI_CANT_MPX_CONN`, the
+    /// app can't handle concurrent requests on a connection.
     #[error("This app can't multiplex [CantMpxConn]; AppStatus: {app_status}")]
     EndRequestCantMpxConn { app_status: u32 },
 
-    /// Response not complete, first is protocol status and second is app status, see fastcgi protocol.
+    /// The request ended with the protocol status `FCGI_OVERLOADED`, the app
+    /// ran out of resources and rejected it.
     #[error("New request rejected; too busy [OVERLOADED]; AppStatus: {app_status}")]
     EndRequestOverloaded { app_status: u32 },
 
-    /// Response not complete, first is protocol status and second is app status, see fastcgi protocol.
+    /// The request ended with the protocol status `FCGI_UNKNOWN_ROLE`, the app
+    /// doesn't support the role of the request. Unknown protocol statuses are
+    /// reported as this one too.
     #[error("Role value not known [UnknownRole]; AppStatus: {app_status}")]
     EndRequestUnknownRole { app_status: u32 },
 
-    /// Try to get lock of RequestIdGenerator timeout.
-    #[error("Try to get lock of RequestIdGenerator timeout")]
-    RequestIdGenerateTimeout,
+    /// The request id 0 is reserved for the management records, such as
+    /// `FCGI_GET_VALUES`.
+    #[error("Request id 0 is reserved for management records")]
+    ReservedRequestId,
+
+    /// All the request ids are in use until the timeout of allocating.
+    #[error("No available request id")]
+    NoAvailableRequestId,
+
+    /// The name or value of the param is too long to be encoded.
+    #[error("Param `{name}` is too long to be encoded")]
+    ParamTooLong { name: String },
+
+    /// The name of the param isn't made of ASCII letters, digits and
+    /// underscores, or its value contains a NUL, CR or LF, see
+    /// `Params::sanitize`.
+    #[error("Param `{name}` has an invalid name or value")]
+    InvalidParam { name: String },
+
+    /// The param required by the role of the request is missing, such as
+    /// `FCGI_DATA_LENGTH` of a filter.
+    #[error("Param `{name}` is required")]
+    MissingParam { name: String },
+
+    /// The flags of `FCGI_BEGIN_REQUEST` have a reserved bit set, see
+    /// `BeginRequestFlags`.
+    #[error("Invalid flags `{flags:#04x}` of begin request of role `{role:?}`")]
+    InvalidBeginRequest { role: Role, flags: u8 },
+
+    /// A line of the CGI headers in STDOUT can't be parsed.
+    #[error("Invalid header in response: `{line}`")]
+    InvalidResponseHeader { line: String },
+
+    /// The body of the response can't be decoded by `Response::decoded_body`,
+    /// its content encoding is unsupported, or it's empty or corrupt.
+    #[error("Can't decode body of content encoding `{encoding}`: {reason}")]
+    InvalidBody { encoding: String, reason: &'static str },
+
+    /// The response exceeds `max_response_bytes` of the client.
+    #[error("Response of request id `{id}` exceeds the limit of {limit} bytes")]
+    ResponseTooLarge { id: u16, limit: usize },
+
+    /// The response of `Client` exceeds `max_records_per_response`.
+    #[error("Response of request id `{id}` exceeds the limit of {limit} records")]
+    TooManyRecords { id: u16, limit: usize },
+
+    /// The server of the unix socket doesn't run as the uid and gid expected
+    /// by `ClientBuilder::expect_peer_cred`.
+    #[error("Peer of unix socket runs as uid {uid} gid {gid}, not the expected ones")]
+    PeerCredMismatch { uid: u32, gid: u32 },
+
+    /// The connection was closed or reset by fastcgi server.
+    #[error("Connection closed while {during} of request id `{id}`")]
+    ConnectionClosed { id: u16, during: Phase },
+
+    /// The server was still overloaded after the retries of
+    /// `Client::execute_with_backoff`.
+    #[error("Server still overloaded after {retries} retries")]
+    Overloaded { retries: usize },
+
+    /// The backend rejected the request of `MultiplexClient` with
+    /// `FCGI_CANT_MPX_CONN`, even once re-sent with the requests sent one at a
+    /// time.
+    #[error("Backend can't multiplex request of id `{id}`")]
+    CannotMultiplex { id: u16 },
+
+    /// The connection of `ReconnectingClient` was lost during the request,
+    /// which the server may have processed. The next request reconnects, and
+    /// may send it again if it's idempotent.
+    #[error("Connection lost, retry advised: {source}")]
+    RetryAdvised { source: Box<ClientError> },
+
+    /// The request of id is still in progress, its `execute` was cancelled
+    /// before the end of response.
+    #[error("Request of id `{id}` is still in progress")]
+    Busy { id: u16 },
+
+    /// The request of `execute_buffered` ended with a non-zero app status,
+    /// such as a PHP fatal error, with the STDERR of the response as lossy
+    /// UTF-8, truncated to 4096 bytes.
+    #[error("Request failed with app status {app_status}: {stderr}")]
+    RequestFailed { app_status: u32, stderr: String },
+
+    /// The exchange didn't complete in time, the id is 0 when connecting.
+    #[error("Request of id `{id}` timed out while {phase}")]
+    Timeout { id: u16, phase: Phase },
 }
 
 impl ClientError {
@@ -51,4 +181,99 @@ impl ClientError {
             _ => ClientError::EndRequestUnknownRole { app_status },
         }
     }
+
+    /// The protocol status of the `FCGI_END_REQUEST` record for the errors of
+    /// a request ended without completing, `None` for the other errors.
+    pub fn protocol_status(&self) -> Option<ProtocolStatus> {
+        match self {
+            ClientError::EndRequestCantMpxConn { .. } => Some(ProtocolStatus::CantMpxConn),
+            ClientError::EndRequestOverloaded { .. } => Some(ProtocolStatus::Overloaded),
+            ClientError::EndRequestUnknownRole { .. } => Some(ProtocolStatus::UnknownRole),
+            _ => None,
+        }
+    }
+
+    /// The app status of the `FCGI_END_REQUEST` record for the errors of a
+    /// request ended without completing, `None` for the other errors.
+    pub fn app_status(&self) -> Option<u32> {
+        match self {
+            ClientError::EndRequestCantMpxConn { app_status }
+            | ClientError::EndRequestOverloaded { app_status }
+            | ClientError::EndRequestUnknownRole { app_status } => Some(*app_status),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn new_request_failed(app_status: u32, stderr: &[u8]) -> Self {
+        const MAX_STDERR_LEN: usize = 4096;
+
+        let mut stderr = String::from_utf8_lossy(stderr).into_owned();
+        if stderr.len() > MAX_STDERR_LEN {
+            let mut end = MAX_STDERR_LEN;
+            while !stderr.is_char_boundary(end) {
+                end -= 1;
+            }
+            stderr.truncate(end);
+        }
+        ClientError::RequestFailed { app_status, stderr }
+    }
+
+    /// Whether the error is caused by the connection being closed or reset.
+    pub(crate) fn is_connection_lost(&self) -> bool {
+        match self {
+            ClientError::ConnectionClosed { .. } => true,
+            ClientError::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+
+    /// Map the io error of the connection being closed or reset to
+    /// `ClientError::ConnectionClosed`, other errors are kept.
+    pub(crate) fn into_connection_closed(self, id: u16, during: Phase) -> Self {
+        match &self {
+            // Writing to a closed stream never reports an unexpected EOF, it
+            // comes from the body being read in the request phase.
+            ClientError::Io(err)
+                if during == Phase::Request && err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                self
+            }
+            ClientError::Io(_) if self.is_connection_lost() => {
+                ClientError::ConnectionClosed { id, during }
+            }
+            _ => self,
+        }
+    }
+
+    /// Whether the error leaves the stream in an unknown state, so the
+    /// connection can't be used for another request.
+    pub(crate) fn is_stream_error(&self) -> bool {
+        !matches!(
+            self,
+            ClientError::EndRequestCantMpxConn { .. }
+                | ClientError::EndRequestOverloaded { .. }
+                | ClientError::EndRequestUnknownRole { .. }
+                | ClientError::NoAvailableRequestId
+                | ClientError::ReservedRequestId
+                | ClientError::ResponseTooLarge { .. }
+                | ClientError::ParamTooLong { .. }
+                | ClientError::InvalidParam { .. }
+                | ClientError::MissingParam { .. }
+                | ClientError::InvalidBeginRequest { .. }
+                | ClientError::InvalidResponseHeader { .. }
+                | ClientError::InvalidBody { .. }
+                | ClientError::UnknownTypeResponse { .. }
+                | ClientError::Busy { .. }
+                | ClientError::CannotMultiplex { .. }
+                | ClientError::Overloaded { .. }
+                | ClientError::RequestFailed { .. }
+        )
+    }
 }
@@ -1,55 +1,320 @@
 use crate::{ClientError, ClientResult};
-use std::{collections::HashSet, time::Duration};
-use tokio::{
-    sync::Mutex,
-    time::{sleep, timeout},
+use log::debug;
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
+use tokio::{sync::Notify, time::timeout};
 
-const MAX_REQUEST_ID: u16 = u16::max_value() - 1;
+const MAX_REQUEST_ID: u16 = u16::MAX - 1;
 
-pub(crate) struct RequestIdGenerator {
-    id: Mutex<u16>,
-    ids: Mutex<HashSet<u16>>,
+/// Strategy of allocating the request ids of [`Client`](crate::Client), such
+/// as random ids, or ids coordinated across the clients of a cluster, see
+/// `Client::with_id_generator`. [`RequestIdGenerator`] is the default,
+/// allocating the ids sequentially.
+pub trait IdAllocator: Send + Sync {
+    /// Allocate an id not in use, which is never 0, the id of the management
+    /// records. It may wait for an id to be released, and fail with
+    /// `ClientError::NoAvailableRequestId`.
+    fn alloc(&self) -> Pin<Box<dyn Future<Output = ClientResult<u16>> + Send + '_>>;
+
+    /// Make the id available again, releasing an id not in use does nothing.
+    fn release(&self, id: u16);
+
+    /// Count of the ids allocated and not released yet.
+    fn in_use(&self) -> usize;
+
+    /// Max count of the ids in use at once.
+    fn pool_size(&self) -> u16;
+}
+
+/// Allocator of request ids, ids are allocated sequentially from 1 to the
+/// pool size, skipping the ids in use.
+///
+/// If all the ids are in use, `alloc` waits for one to be released, and fails
+/// with `ClientError::NoAvailableRequestId` after the timeout.
+///
+/// The methods take `&self`, so a generator in an `Arc` can be shared by the
+/// clients connected to the same backend, see `Client::with_id_generator`.
+pub struct RequestIdGenerator {
+    state: Mutex<State>,
+    /// Count of the ids in use, read without locking the state.
+    in_use: AtomicUsize,
+    released: Notify,
     timeout: Duration,
+    pool_size: u16,
+}
+
+struct State {
+    id: u16,
+    ids: HashSet<u16>,
 }
 
 impl RequestIdGenerator {
-    pub(crate) fn new(timeout: Duration) -> Self {
+    /// Generator of the ids from 1 to 65534, waiting up to `timeout` for an id.
+    pub fn new(timeout: Duration) -> Self {
         Self {
-            id: Mutex::new(0),
-            ids: Default::default(),
+            state: Mutex::new(State {
+                id: 0,
+                ids: Default::default(),
+            }),
+            in_use: AtomicUsize::new(0),
+            released: Notify::new(),
             timeout,
+            pool_size: MAX_REQUEST_ID,
         }
     }
 
-    pub(crate) async fn alloc(&self) -> ClientResult<u16> {
+    /// Allocate the ids from 1 to `pool_size` only, to limit the requests in
+    /// progress at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pool_size` is 0 or greater than 65534.
+    pub fn set_pool_size(mut self, pool_size: u16) -> Self {
+        assert!(
+            (1..=MAX_REQUEST_ID).contains(&pool_size),
+            "pool size must be from 1 to {}",
+            MAX_REQUEST_ID
+        );
+        self.pool_size = pool_size;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn pool_size(&self) -> u16 {
+        self.pool_size
+    }
+
+    /// Count of the ids allocated and not released yet.
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Allocate an unused id, it must be released once the request ends.
+    pub async fn alloc(&self) -> ClientResult<u16> {
         timeout(self.timeout, self.inner_alloc())
             .await
-            .map_err(|_| ClientError::RequestIdGenerateTimeout)
+            .map_err(|_| ClientError::NoAvailableRequestId)
     }
 
     async fn inner_alloc(&self) -> u16 {
-        let mut id = self.id.lock().await;
+        loop {
+            // Created before checking, so a release in between isn't missed.
+            let released = self.released.notified();
+
+            if let Some(id) = self.try_alloc() {
+                return id;
+            }
+
+            released.await;
+        }
+    }
+
+    fn try_alloc(&self) -> Option<u16> {
+        let mut state = self.state.lock().unwrap();
+        if state.ids.len() >= self.pool_size as usize {
+            return None;
+        }
 
         loop {
-            if *id >= MAX_REQUEST_ID {
-                *id = 0;
+            if state.id >= self.pool_size {
+                state.id = 0;
             }
-            *id += 1;
-
-            let ids = self.ids.lock().await;
-            if ids.contains(&id) {
-                drop(ids);
-                sleep(Duration::from_millis(10)).await;
-            } else {
-                break;
+            state.id += 1;
+
+            let id = state.id;
+            // 0 is the id of the management records.
+            debug_assert_ne!(id, 0);
+            if state.ids.insert(id) {
+                self.in_use.store(state.ids.len(), Ordering::Relaxed);
+                return Some(id);
             }
         }
+    }
+
+    /// Make the id available again, waking a pending `alloc`. Releasing an id
+    /// not in use, such as an id released twice, does nothing.
+    pub fn release(&self, id: u16) {
+        let mut state = self.state.lock().unwrap();
+        if !state.ids.remove(&id) {
+            debug!("[id = {}] Release of id not in use.", id);
+            return;
+        }
+        self.in_use.store(state.ids.len(), Ordering::Relaxed);
+        drop(state);
+        self.released.notify_waiters();
+    }
+}
+
+impl IdAllocator for RequestIdGenerator {
+    fn alloc(&self) -> Pin<Box<dyn Future<Output = ClientResult<u16>> + Send + '_>> {
+        Box::pin(RequestIdGenerator::alloc(self))
+    }
+
+    fn release(&self, id: u16) {
+        RequestIdGenerator::release(self, id);
+    }
+
+    fn in_use(&self) -> usize {
+        RequestIdGenerator::in_use(self)
+    }
+
+    fn pool_size(&self) -> u16 {
+        self.pool_size
+    }
+}
+
+/// Allocated request id, released when the guard is dropped, so the id isn't
+/// left in use if the request returns early or panics.
+pub(crate) struct RequestIdGuard {
+    generator: Arc<dyn IdAllocator>,
+    id: Option<u16>,
+}
+
+impl RequestIdGuard {
+    pub(crate) async fn alloc(generator: &Arc<dyn IdAllocator>) -> ClientResult<Self> {
+        let id = generator.alloc().await?;
+        Ok(Self::new(generator.clone(), id))
+    }
+
+    /// Guard of the id already allocated from the generator.
+    pub(crate) fn new(generator: Arc<dyn IdAllocator>, id: u16) -> Self {
+        Self {
+            generator,
+            id: Some(id),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u16 {
+        self.id.unwrap()
+    }
+
+    /// Keep the id in use after the guard, to be released by hand.
+    pub(crate) fn keep(mut self) -> u16 {
+        self.id.take().unwrap()
+    }
+}
+
+impl Drop for RequestIdGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.generator.release(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exhausted() {
+        let generator = RequestIdGenerator::new(Duration::from_millis(50));
+        for id in 1..=MAX_REQUEST_ID {
+            assert_eq!(generator.alloc().await.unwrap(), id);
+        }
+
+        assert!(matches!(
+            generator.alloc().await,
+            Err(ClientError::NoAvailableRequestId)
+        ));
+
+        generator.release(100);
+        assert_eq!(generator.alloc().await.unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn wait_for_release() {
+        let generator = std::sync::Arc::new(RequestIdGenerator::new(Duration::from_secs(5)));
+        for _ in 1..=MAX_REQUEST_ID {
+            generator.alloc().await.unwrap();
+        }
 
-        *id
+        let releaser = generator.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            releaser.release(7);
+        });
+        assert_eq!(generator.alloc().await.unwrap(), 7);
     }
 
-    pub(crate) async fn release(&self, id: u16) {
-        self.ids.lock().await.remove(&id);
+    #[tokio::test]
+    async fn never_zero() {
+        let generator = RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(2);
+        assert_eq!(generator.alloc().await.unwrap(), 1);
+        for _ in 0..10 {
+            let id = generator.alloc().await.unwrap();
+            assert!(id >= 1);
+            generator.release(id);
+        }
+    }
+
+    #[tokio::test]
+    async fn guard() {
+        let generator = Arc::new(RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(1));
+        let allocator: Arc<dyn IdAllocator> = generator.clone();
+        let guard = RequestIdGuard::alloc(&allocator).await.unwrap();
+        assert_eq!(guard.id(), 1);
+        drop(guard);
+
+        let id = RequestIdGuard::alloc(&allocator).await.unwrap().keep();
+        assert!(generator.alloc().await.is_err());
+        generator.release(id);
+        assert_eq!(generator.alloc().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn pool_size() {
+        let generator = RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(2);
+        assert_eq!(generator.alloc().await.unwrap(), 1);
+        assert_eq!(generator.alloc().await.unwrap(), 2);
+        assert!(matches!(
+            generator.alloc().await,
+            Err(ClientError::NoAvailableRequestId)
+        ));
+
+        generator.release(1);
+        assert_eq!(generator.alloc().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn in_use() {
+        let generator = RequestIdGenerator::new(Duration::from_millis(50));
+        assert_eq!(generator.in_use(), 0);
+        let first = generator.alloc().await.unwrap();
+        generator.alloc().await.unwrap();
+        assert_eq!(generator.in_use(), 2);
+
+        generator.release(first);
+        assert_eq!(generator.in_use(), 1);
+        // Releasing an id not in use changes nothing.
+        generator.release(first);
+        assert_eq!(generator.in_use(), 1);
+    }
+
+    #[tokio::test]
+    async fn double_release() {
+        let generator = RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(2);
+        let id = generator.alloc().await.unwrap();
+        generator.release(id);
+        generator.release(id);
+        assert_eq!(generator.in_use(), 0);
+
+        // The ids allocated after are still distinct, and the pool is full.
+        let first = generator.alloc().await.unwrap();
+        let second = generator.alloc().await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(generator.in_use(), 2);
+        assert!(generator.alloc().await.is_err());
     }
 }
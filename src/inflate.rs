@@ -0,0 +1,373 @@
+//! Decoder of the DEFLATE format (RFC 1951) and of its gzip (RFC 1952) and
+//! zlib (RFC 1950) wrappers, for [`Response::decoded_body`].
+//!
+//! [`Response::decoded_body`]: crate::Response::decoded_body
+
+/// Result of decoding, the error is the reason the data is invalid.
+pub(crate) type InflateResult<T> = Result<T, &'static str>;
+
+const MAX_BITS: usize = 15;
+
+/// Order of the code lengths of the code length alphabet.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const GZIP_FHCRC: u8 = 0x02;
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+
+/// Decode the gzip members of `input`.
+pub(crate) fn gunzip(input: &[u8]) -> InflateResult<Vec<u8>> {
+    if input.is_empty() {
+        return Err("empty body");
+    }
+
+    let mut output = Vec::new();
+    let mut rest = input;
+    // Concatenated members are decoded one after the other.
+    while !rest.is_empty() {
+        if rest.len() < 18 || rest[..3] != [0x1f, 0x8b, 8] {
+            return Err("invalid gzip header");
+        }
+        let flags = rest[3];
+        if flags & 0xe0 != 0 {
+            return Err("reserved gzip flags set");
+        }
+
+        let mut pos = 10;
+        if flags & GZIP_FEXTRA != 0 {
+            let len = usize::from(u16::from_le_bytes([
+                *rest.get(pos).ok_or(UNEXPECTED_END)?,
+                *rest.get(pos + 1).ok_or(UNEXPECTED_END)?,
+            ]));
+            pos += 2 + len;
+        }
+        for flag in [GZIP_FNAME, GZIP_FCOMMENT].iter() {
+            if flags & flag != 0 {
+                let end = rest
+                    .get(pos..)
+                    .and_then(|field| field.iter().position(|&b| b == 0));
+                pos += end.ok_or(UNEXPECTED_END)? + 1;
+            }
+        }
+        if flags & GZIP_FHCRC != 0 {
+            pos += 2;
+        }
+
+        let start = output.len();
+        let consumed = inflate_into(rest.get(pos..).ok_or(UNEXPECTED_END)?, &mut output)?;
+        let trailer = rest
+            .get(pos + consumed..pos + consumed + 8)
+            .ok_or(UNEXPECTED_END)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc32(&output[start..]) != crc {
+            return Err("gzip checksum mismatch");
+        }
+        if (output.len() - start) as u32 != size {
+            return Err("gzip size mismatch");
+        }
+        rest = &rest[pos + consumed + 8..];
+    }
+    Ok(output)
+}
+
+/// Decode the zlib stream of `input`, or the raw DEFLATE data without the
+/// zlib header, which some servers send for the `deflate` encoding.
+pub(crate) fn inflate_zlib(input: &[u8]) -> InflateResult<Vec<u8>> {
+    if input.is_empty() {
+        return Err("empty body");
+    }
+
+    let is_zlib = input.len() >= 2
+        && input[0] & 0x0f == 8
+        && input[0] >> 4 <= 7
+        && (u16::from(input[0]) << 8 | u16::from(input[1])) % 31 == 0;
+    let mut output = Vec::new();
+    if !is_zlib {
+        inflate_into(input, &mut output)?;
+        return Ok(output);
+    }
+
+    if input[1] & 0x20 != 0 {
+        return Err("zlib preset dictionary not supported");
+    }
+    let consumed = inflate_into(&input[2..], &mut output)?;
+    let trailer = input
+        .get(2 + consumed..2 + consumed + 4)
+        .ok_or(UNEXPECTED_END)?;
+    if adler32(&output) != u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) {
+        return Err("zlib checksum mismatch");
+    }
+    Ok(output)
+}
+
+const UNEXPECTED_END: &str = "unexpected end of data";
+
+/// Decode the DEFLATE blocks at the start of `input` to the end of `output`,
+/// returns the count of bytes consumed.
+fn inflate_into(input: &[u8], output: &mut Vec<u8>) -> InflateResult<usize> {
+    let mut bits = Bits {
+        input,
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let header = input.get(bits.pos..bits.pos + 4).ok_or(UNEXPECTED_END)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("invalid length of stored block");
+                }
+                bits.pos += 4;
+                let content = input
+                    .get(bits.pos..bits.pos + usize::from(len))
+                    .ok_or(UNEXPECTED_END)?;
+                output.extend_from_slice(content);
+                bits.pos += usize::from(len);
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].iter_mut().for_each(|len| *len = 8);
+                lengths[144..256].iter_mut().for_each(|len| *len = 9);
+                lengths[256..280].iter_mut().for_each(|len| *len = 7);
+                lengths[280..].iter_mut().for_each(|len| *len = 8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(&mut bits, output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, output, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type"),
+        }
+        if last {
+            break;
+        }
+    }
+
+    bits.align();
+    Ok(bits.pos)
+}
+
+fn dynamic_codes(bits: &mut Bits<'_>) -> InflateResult<(Huffman, Huffman)> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_length_count = bits.take(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("too many length or distance codes");
+    }
+
+    let mut code_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.take(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = [0; 286 + 30];
+    let count = literal_count + distance_count;
+    let mut index = 0;
+    while index < count {
+        let symbol = code_lengths.decode(bits)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *index
+                    .checked_sub(1)
+                    .and_then(|previous| lengths.get(previous))
+                    .ok_or("repeated length without a previous one")?;
+                (previous, 3 + bits.take(2)? as usize)
+            }
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        if index + repeat > count {
+            return Err("too many code lengths");
+        }
+        lengths[index..index + repeat]
+            .iter_mut()
+            .for_each(|length| *length = len);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err("missing end of block code");
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..count])?,
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits<'_>,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> InflateResult<()> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = usize::from(symbol - 257);
+                if index >= LENGTH_BASE.len() {
+                    return Err("invalid length code");
+                }
+                let len =
+                    usize::from(LENGTH_BASE[index]) + bits.take(LENGTH_EXTRA[index])? as usize;
+
+                let index = usize::from(distances.decode(bits)?);
+                if index >= DISTANCE_BASE.len() {
+                    return Err("invalid distance code");
+                }
+                let distance =
+                    usize::from(DISTANCE_BASE[index]) + bits.take(DISTANCE_EXTRA[index])? as usize;
+                if distance > output.len() {
+                    return Err("distance too far back");
+                }
+
+                // The copy may overlap the bytes it writes.
+                let start = output.len() - distance;
+                for i in 0..len {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
+
+/// Reader of the bits of the input, from the least significant bit of each
+/// byte.
+struct Bits<'a> {
+    input: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u8,
+}
+
+impl Bits<'_> {
+    /// Take `count` bits, at most 16.
+    fn take(&mut self, count: u8) -> InflateResult<u32> {
+        while self.count < count {
+            let byte = *self.input.get(self.pos).ok_or(UNEXPECTED_END)?;
+            self.buf |= u32::from(byte) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << count) - 1);
+        self.buf >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Skip the bits left of the current byte.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, by the count of codes of each length and the
+/// symbols sorted by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> InflateResult<Self> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err("over-subscribed code lengths");
+            }
+        }
+
+        let mut offsets = [0; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> InflateResult<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code")
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -50,7 +50,7 @@
 //!         .set_content_length("0");
 //!
 //!     // Fetch fastcgi server(php-fpm) response.
-//!     let output = client.execute(Request::new(params, &mut io::empty())).await.unwrap();
+//!     let output = client.execute_buffered(Request::new(params, &mut io::empty())).await.unwrap();
 //!
 //!     // "Content-type: text/html; charset=UTF-8\r\n\r\nhello"
 //!     let stdout = String::from_utf8(output.get_stdout().unwrap()).unwrap();
@@ -66,11 +66,35 @@
 //!
 
 pub mod client;
+#[cfg(feature = "codec")]
+pub mod codec;
 mod error;
 mod id;
+#[cfg(feature = "compression")]
+mod inflate;
 mod meta;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod multiplex;
 pub mod params;
+pub mod pool;
+pub mod reconnect;
 pub mod request;
 pub mod response;
+pub mod stream;
+#[cfg(feature = "testutil")]
+pub mod testutil;
 
-pub use crate::{client::Client, error::*, params::Params, request::Request, response::Response};
+pub use crate::{
+    client::{request, BackoffPolicy, Client, ClientBuilder},
+    error::*,
+    id::{IdAllocator, RequestIdGenerator},
+    meta::{BeginRequestFlags, ProtocolStatus, RecordHeader, RequestType, Role},
+    params::Params,
+    reconnect::ReconnectingClient,
+    request::{OwnedRequest, Request, RequestBuilder},
+    response::{RequestComplete, RequestStats, Response, ResponseChunk},
+    stream::{Address, ClientStream, Halves, Stream, TcpOptions},
+};
+#[cfg(feature = "http")]
+pub use crate::response::AuthResult;
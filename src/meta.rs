@@ -1,16 +1,19 @@
 use crate::{
     error::{ClientError, ClientResult},
+    response::RequestComplete,
     Params,
 };
+use bytes::Bytes;
 use std::{
     cmp::min,
     fmt::{self, Debug, Display},
-    mem::size_of,
-    ops::{Deref, DerefMut},
+    mem::{self, size_of},
+    ops::{BitOr, BitOrAssign, Deref, DerefMut},
 };
-use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use log::debug;
 
+/// Version of the records, the only one of the protocol.
 pub(crate) const VERSION_1: u8 = 1;
 pub(crate) const MAX_LENGTH: usize = 0xffff;
 pub(crate) const HEADER_LEN: usize = size_of::<Header>();
@@ -32,8 +35,15 @@ pub enum RequestType {
 }
 
 impl RequestType {
-    fn from_u8(u: u8) -> Self {
-        match u {
+    /// The type of the byte, `UnknownType` for the types unknown to the
+    /// protocol too, see [`RequestType::try_from_u8`] to tell them apart.
+    pub(crate) fn from_u8(u: u8) -> Self {
+        Self::try_from_u8(u).unwrap_or(RequestType::UnknownType)
+    }
+
+    /// The type of the byte, `None` for the types unknown to the protocol.
+    pub(crate) fn try_from_u8(u: u8) -> Option<Self> {
+        Some(match u {
             1 => RequestType::BeginRequest,
             2 => RequestType::AbortRequest,
             3 => RequestType::EndRequest,
@@ -44,8 +54,9 @@ impl RequestType {
             8 => RequestType::Data,
             9 => RequestType::GetValues,
             10 => RequestType::GetValuesResult,
-            _ => RequestType::UnknownType,
-        }
+            11 => RequestType::UnknownType,
+            _ => return None,
+        })
     }
 }
 
@@ -55,6 +66,7 @@ impl Display for RequestType {
     }
 }
 
+/// Header of a fastcgi record.
 #[derive(Debug, Clone)]
 pub(crate) struct Header {
     pub(crate) version: u8,
@@ -65,73 +77,235 @@ pub(crate) struct Header {
     pub(crate) reserved: u8,
 }
 
+/// Header of a record read by [`Client::read_record`](crate::Client::read_record).
+#[derive(Debug, Clone)]
+pub struct RecordHeader {
+    pub version: u8,
+    pub request_type: RequestType,
+    pub request_id: u16,
+    pub content_length: u16,
+    pub padding_length: u8,
+}
+
+impl From<&Header> for RecordHeader {
+    fn from(header: &Header) -> Self {
+        Self {
+            version: header.version,
+            request_type: header.r#type.clone(),
+            request_id: header.request_id,
+            content_length: header.content_length,
+            padding_length: header.padding_length,
+        }
+    }
+}
+
+/// Buffer for reading the content of a stream to be split into records,
+/// reused across the streams so it's only allocated once.
+pub(crate) struct RecordBuf {
+    buf: Vec<u8>,
+    record_size: usize,
+    /// Version of the records written, `VERSION_1` by default.
+    pub(crate) version: u8,
+    /// Records written between the flushes of the writer, `None` for no
+    /// flush, see `Client::set_flush_interval`.
+    pub(crate) flush_interval: Option<usize>,
+    /// Records written since the last flush.
+    unflushed: usize,
+}
+
+impl RecordBuf {
+    /// Buffer of records of at most `record_size`, clamped to `1..=MAX_LENGTH`.
+    pub(crate) fn new(record_size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            record_size: record_size.clamp(1, MAX_LENGTH),
+            version: VERSION_1,
+            flush_interval: None,
+            unflushed: 0,
+        }
+    }
+
+    /// Count a record written to `writer`, which is flushed once
+    /// `flush_interval` records were written since the last flush.
+    async fn record_written(&mut self, writer: &mut (dyn AsyncWrite + Unpin)) -> io::Result<()> {
+        let interval = match self.flush_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        self.unflushed += 1;
+        if self.unflushed >= interval {
+            self.unflushed = 0;
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Restart counting the records written, after the writer was flushed.
+    pub(crate) fn flushed(&mut self) {
+        self.unflushed = 0;
+    }
+}
+
 impl Header {
+    /// Write the content as records of at most the record size of `scratch`.
+    /// An empty content writes no record, the empty record ending a stream is
+    /// up to the caller.
     pub(crate) async fn write_to_stream_batches<F>(
         r#type: RequestType,
         request_id: u16,
         writer: &mut (dyn AsyncWrite + Unpin),
         content: &mut (dyn AsyncRead + Unpin),
+        scratch: &mut RecordBuf,
         before_write: Option<F>,
     ) -> io::Result<()>
     where
         F: Fn(Header) -> Header,
     {
-        let mut buf: [u8; MAX_LENGTH] = [0; MAX_LENGTH];
-        let mut had_writen = false;
+        let version = scratch.version;
+        // Resized to the record size, so it never grows beyond `MAX_LENGTH`.
+        // Taken out of `scratch` while the records are counted, it's only
+        // allocated again after an error.
+        let mut buf = mem::take(&mut scratch.buf);
+        buf.resize(scratch.record_size, 0);
 
         loop {
             let read = content.read(&mut buf).await?;
-            if had_writen && read == 0 {
+            if read == 0 {
                 break;
             }
 
             let buf = &buf[..read];
-            let mut header = Self::new(r#type.clone(), request_id, buf);
+            let mut header = Self::new(r#type.clone(), request_id, buf).with_version(version);
             if let Some(ref f) = before_write {
                 header = f(header);
             }
             header.write_to_stream(writer, buf).await?;
-            debug!("Written {} to php",String::from_utf8_lossy(&buf));
+            debug!("[id = {}] Written record of {} bytes.", request_id, read);
+            scratch.record_written(writer).await?;
+        }
+        scratch.buf = buf;
+        Ok(())
+    }
+
+    /// Like [`Header::write_to_stream_batches`], but the records are written
+    /// from the buffer of `content`, without copying it into `scratch`.
+    pub(crate) async fn write_buf_to_stream_batches<F>(
+        r#type: RequestType,
+        request_id: u16,
+        writer: &mut (dyn AsyncWrite + Unpin),
+        content: &mut (dyn AsyncBufRead + Unpin),
+        scratch: &mut RecordBuf,
+        before_write: Option<F>,
+    ) -> io::Result<()>
+    where
+        F: Fn(Header) -> Header,
+    {
+        loop {
+            let buf = content.fill_buf().await?;
+            if buf.is_empty() {
+                break;
+            }
 
-            had_writen = true;
+            let len = buf.len().min(scratch.record_size);
+            let buf = &buf[..len];
+            let mut header = Self::new(r#type.clone(), request_id, buf).with_version(scratch.version);
+            if let Some(ref f) = before_write {
+                header = f(header);
+            }
+            header.write_parts_to_stream(writer, buf).await?;
+            content.consume(len);
+            scratch.record_written(writer).await?;
         }
         Ok(())
     }
 
-    fn new(r#type: RequestType, request_id: u16, content: &[u8]) -> Self {
+    /// Like `Header::write_to_stream_batches`, but the records are written
+    /// straight from the content, such as a chunk of a body already in memory.
+    pub(crate) async fn write_slice_to_stream_batches<F>(
+        r#type: RequestType,
+        request_id: u16,
+        writer: &mut (dyn AsyncWrite + Unpin),
+        content: &[u8],
+        scratch: &mut RecordBuf,
+        before_write: Option<F>,
+    ) -> io::Result<()>
+    where
+        F: Fn(Header) -> Header,
+    {
+        for buf in content.chunks(scratch.record_size) {
+            let mut header = Self::new(r#type.clone(), request_id, buf).with_version(scratch.version);
+            if let Some(ref f) = before_write {
+                header = f(header);
+            }
+            header.write_parts_to_stream(writer, buf).await?;
+            scratch.record_written(writer).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn new(r#type: RequestType, request_id: u16, content: &[u8]) -> Self {
         let content_length = min(content.len(), MAX_LENGTH) as u16;
         Self {
             version: VERSION_1,
             r#type,
             request_id,
             content_length,
-            padding_length: (-(content_length as i16) & 7) as u8,
+            padding_length: ((8 - content_length % 8) % 8) as u8,
             reserved: 0,
         }
     }
 
-    async fn write_to_stream(
+    /// The header with another version than `VERSION_1`.
+    pub(crate) fn with_version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub(crate) async fn write_to_stream(
         self,
         writer: &mut (dyn AsyncWrite + Unpin),
         content: &[u8],
     ) -> io::Result<()> {
-        let mut buf: Vec<u8> = Vec::new();
+        // The whole record in one write, rather than one for the header, the
+        // content and the padding each.
+        let mut buf: Vec<u8> =
+            Vec::with_capacity(HEADER_LEN + content.len() + self.padding_length as usize);
         buf.push(self.version);
         buf.push(self.r#type as u8);
         buf.write_u16(self.request_id).await?;
         buf.write_u16(self.content_length).await?;
         buf.push(self.padding_length);
         buf.push(self.reserved);
+        buf.extend_from_slice(content);
+        buf.resize(buf.len() + self.padding_length as usize, 0);
 
         writer.write_all(&buf).await?;
-        writer.write_all(content).await?;
-        writer
-            .write_all(&vec![0; self.padding_length as usize])
-            .await?;
 
         Ok(())
     }
 
+    /// Write the record with the header, the content and the padding written
+    /// apart, so the content isn't copied.
+    async fn write_parts_to_stream(
+        self,
+        writer: &mut (dyn AsyncWrite + Unpin),
+        content: &[u8],
+    ) -> io::Result<()> {
+        let mut head = [0; HEADER_LEN];
+        head[0] = self.version;
+        head[1] = self.r#type as u8;
+        head[2..4].copy_from_slice(&self.request_id.to_be_bytes());
+        head[4..6].copy_from_slice(&self.content_length.to_be_bytes());
+        head[6] = self.padding_length;
+        head[7] = self.reserved;
+
+        writer.write_all(&head).await?;
+        writer.write_all(content).await?;
+        writer
+            .write_all(&[0; u8::MAX as usize][..self.padding_length as usize])
+            .await
+    }
+
     pub(crate) async fn new_from_stream(reader: &mut (impl AsyncRead + Unpin + Send)) -> io::Result<Self> {
         let mut buf: [u8; HEADER_LEN] = [0; HEADER_LEN];
         reader.read_exact(&mut buf).await?;
@@ -146,27 +320,116 @@ impl Header {
         })
     }
 
+    /// Read the content into a buffer of its own, which is handed out as
+    /// `Bytes` without copying.
+    ///
+    /// The buffer is at most `MAX_LENGTH` bytes, since the content length is a
+    /// `u16`, whatever the server claims.
     pub(crate) async fn read_content_from_stream(
         &self,
         reader: &mut (impl AsyncRead + Unpin + Send),
-    ) -> io::Result<Vec<u8>> {
+    ) -> io::Result<Bytes> {
         let mut buf = vec![0; self.content_length as usize];
         reader.read_exact(&mut buf).await?;
-        let mut padding_buf = vec![0; self.padding_length as usize];
-        reader.read_exact(&mut padding_buf).await?;
-        Ok(buf)
+        self.skip_padding_from_stream(reader).await?;
+        Ok(buf.into())
+    }
+
+    /// Read and discard the padding after the content.
+    pub(crate) async fn skip_padding_from_stream(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin + Send),
+    ) -> io::Result<()> {
+        let mut padding_buf = [0; u8::MAX as usize];
+        reader
+            .read_exact(&mut padding_buf[..self.padding_length as usize])
+            .await?;
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
-#[allow(dead_code)]
 pub enum Role {
     Responder = 1,
     Authorizer = 2,
     Filter = 3,
 }
 
+impl Role {
+    #[cfg_attr(not(any(feature = "codec", feature = "testutil")), allow(dead_code))]
+    pub(crate) fn from_u16(u: u16) -> Option<Self> {
+        match u {
+            1 => Some(Role::Responder),
+            2 => Some(Role::Authorizer),
+            3 => Some(Role::Filter),
+            _ => None,
+        }
+    }
+}
+
+/// Flags of `FCGI_BEGIN_REQUEST`, only `KEEP_CONN` is defined by the
+/// protocol, the other bits are reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BeginRequestFlags(u8);
+
+impl BeginRequestFlags {
+    /// `FCGI_KEEP_CONN`, the server keeps the connection open after the
+    /// response.
+    pub const KEEP_CONN: Self = Self(1);
+
+    const ALL: u8 = Self::KEEP_CONN.0;
+
+    /// No flag set, the server closes the connection after the response.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The flags from their bits, `None` if a reserved bit is set.
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !Self::ALL == 0 {
+            Some(Self(bits))
+        } else {
+            None
+        }
+    }
+
+    /// The bits of the flags, as sent in `FCGI_BEGIN_REQUEST`.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all the flags of `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl From<bool> for BeginRequestFlags {
+    /// `KEEP_CONN` if `keep_alive`, otherwise no flag.
+    fn from(keep_alive: bool) -> Self {
+        if keep_alive {
+            Self::KEEP_CONN
+        } else {
+            Self::empty()
+        }
+    }
+}
+
+impl BitOr for BeginRequestFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for BeginRequestFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct BeginRequest {
     pub(crate) role: Role,
@@ -175,12 +438,18 @@ pub(crate) struct BeginRequest {
 }
 
 impl BeginRequest {
-    pub(crate) fn new(role: Role, keep_alive: bool) -> Self {
-        Self {
+    /// Failing with `ClientError::InvalidBeginRequest` if a reserved bit of
+    /// the flags is set. Every role may keep the connection, so only the flags
+    /// are checked.
+    pub(crate) fn with_flags(role: Role, flags: u8) -> ClientResult<Self> {
+        if BeginRequestFlags::from_bits(flags).is_none() {
+            return Err(ClientError::InvalidBeginRequest { role, flags });
+        }
+        Ok(Self {
             role,
-            flags: keep_alive as u8,
+            flags,
             reserved: [0; 5],
-        }
+        })
     }
 
     pub(crate) async fn to_content(&self) -> io::Result<Vec<u8>> {
@@ -199,8 +468,18 @@ pub(crate) struct BeginRequestRec {
 }
 
 impl BeginRequestRec {
-    pub(crate) async fn new(request_id: u16, role: Role, keep_alive: bool) -> io::Result<Self> {
-        let begin_request = BeginRequest::new(role, keep_alive);
+    pub(crate) async fn new(
+        request_id: u16,
+        role: Role,
+        flags: BeginRequestFlags,
+    ) -> ClientResult<Self> {
+        Self::with_flags(request_id, role, flags.bits()).await
+    }
+
+    /// Like `BeginRequestRec::new`, but with the raw flags, see
+    /// `BeginRequest::with_flags`.
+    pub(crate) async fn with_flags(request_id: u16, role: Role, flags: u8) -> ClientResult<Self> {
+        let begin_request = BeginRequest::with_flags(role, flags)?;
         let content = begin_request.to_content().await?;
         let header = Header::new(RequestType::BeginRequest, request_id, &content);
         Ok(Self {
@@ -237,23 +516,43 @@ pub enum ParamLength {
 }
 
 impl ParamLength {
-    pub fn new(length: usize) -> Self {
+    /// The max length can be encoded, the high bit of the four bytes encoding
+    /// is the flag of long length.
+    pub(crate) const MAX: usize = 0x7fff_ffff;
+
+    /// Encode the length, `None` if it exceeds `ParamLength::MAX`.
+    pub fn new(length: usize) -> Option<Self> {
         if length < 128 {
-            ParamLength::Short(length as u8)
+            Some(ParamLength::Short(length as u8))
+        } else if length <= Self::MAX {
+            Some(ParamLength::Long(length as u32 | 1 << 31))
         } else {
-            let mut length = length;
-            length |= 1 << 31;
-            ParamLength::Long(length as u32)
+            None
         }
     }
 
-    pub async fn content(self) -> io::Result<Vec<u8>> {
-        let mut buf: Vec<u8> = Vec::new();
+    /// Decode the length from the front of content, and advance the content.
+    pub(crate) fn decode(content: &mut &[u8]) -> io::Result<usize> {
+        let length = match content.first() {
+            Some(&l) if l >> 7 == 0 => {
+                *content = &content[1..];
+                l as usize
+            }
+            Some(_) if content.len() >= 4 => {
+                let l = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+                *content = &content[4..];
+                (l & 0x7fff_ffff) as usize
+            }
+            _ => return Err(invalid_name_value_pairs()),
+        };
+        Ok(length)
+    }
+
+    fn encode_to(self, buf: &mut Vec<u8>) {
         match self {
             ParamLength::Short(l) => buf.push(l),
-            ParamLength::Long(l) => buf.write_u32(l).await?,
+            ParamLength::Long(l) => buf.extend_from_slice(&l.to_be_bytes()),
         }
-        Ok(buf)
     }
 }
 
@@ -265,26 +564,43 @@ pub struct ParamPair<'a> {
     value_data: &'a str,
 }
 
+/// Whether the name is made of ASCII letters, digits and underscores only, like
+/// the names of CGI variables.
+pub(crate) fn is_valid_param_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Whether the value is free of NUL, CR and LF, which backends may mishandle,
+/// e.g. a CR or LF of a value passed on as a header.
+pub(crate) fn is_valid_param_value(value: &str) -> bool {
+    !value.bytes().any(|b| matches!(b, b'\0' | b'\r' | b'\n'))
+}
+
 impl<'a> ParamPair<'a> {
-    fn new(name: &'a str, value: &'a str) -> Self {
-        let name_length = ParamLength::new(name.len());
-        let value_length = ParamLength::new(value.len());
-        Self {
-            name_length,
-            value_length,
-            name_data: name,
-            value_data: value,
+    fn new(name: &'a str, value: &'a str) -> ClientResult<Self> {
+        if !is_valid_param_name(name) || !is_valid_param_value(value) {
+            return Err(ClientError::InvalidParam {
+                name: name.to_owned(),
+            });
+        }
+        match (ParamLength::new(name.len()), ParamLength::new(value.len())) {
+            (Some(name_length), Some(value_length)) => Ok(Self {
+                name_length,
+                value_length,
+                name_data: name,
+                value_data: value,
+            }),
+            _ => Err(ClientError::ParamTooLong {
+                name: name.to_owned(),
+            }),
         }
     }
 
-    async fn write_to_stream(&self, writer: &mut (dyn AsyncWrite + Unpin)) -> io::Result<()> {
-        writer.write_all(&self.name_length.content().await?).await?;
-        writer
-            .write_all(&self.value_length.content().await?)
-            .await?;
-        writer.write_all(self.name_data.as_bytes()).await?;
-        writer.write_all(self.value_data.as_bytes()).await?;
-        Ok(())
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.name_length.encode_to(buf);
+        self.value_length.encode_to(buf);
+        buf.extend_from_slice(self.name_data.as_bytes());
+        buf.extend_from_slice(self.value_data.as_bytes());
     }
 }
 
@@ -292,24 +608,83 @@ impl<'a> ParamPair<'a> {
 pub(crate) struct ParamPairs<'a>(Vec<ParamPair<'a>>);
 
 impl<'a> ParamPairs<'a> {
-    pub(crate) fn new(params: &'a Params<'a>) -> Self {
+    pub(crate) fn new(params: &'a Params<'a>) -> ClientResult<Self> {
         let mut param_pairs = Vec::new();
         for (name, value) in params.iter() {
-            let param_pair = ParamPair::new(name, value);
+            let param_pair = ParamPair::new(name, value)?;
             param_pairs.push(param_pair);
         }
 
-        Self(param_pairs)
+        Ok(Self(param_pairs))
     }
 
-    pub(crate) async fn to_content(&self) -> io::Result<Vec<u8>> {
+    pub(crate) fn to_content(&self) -> Vec<u8> {
         let mut buf: Vec<u8> = Vec::new();
 
         for param_pair in self.iter() {
-            param_pair.write_to_stream(&mut buf).await?;
+            param_pair.encode_to(&mut buf);
         }
 
-        Ok(buf)
+        buf
+    }
+}
+
+/// Decode the name-value pairs content, such as the content of
+/// `FCGI_GET_VALUES_RESULT`.
+pub(crate) fn decode_name_value_pairs(mut content: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+
+    while !content.is_empty() {
+        let name_length = ParamLength::decode(&mut content)?;
+        let value_length = ParamLength::decode(&mut content)?;
+        if content.len() < name_length + value_length {
+            return Err(invalid_name_value_pairs());
+        }
+        let (name, rest) = content.split_at(name_length);
+        let (value, rest) = rest.split_at(value_length);
+        pairs.push((
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        ));
+        content = rest;
+    }
+
+    Ok(pairs)
+}
+
+fn invalid_name_value_pairs() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed name-value pairs")
+}
+
+/// `FCGI_GET_VALUES` management record, the names are sent with empty values.
+#[derive(Debug)]
+pub(crate) struct GetValuesRec {
+    header: Header,
+    content: Vec<u8>,
+}
+
+impl GetValuesRec {
+    pub(crate) async fn new(names: &[&str]) -> ClientResult<Self> {
+        let mut content: Vec<u8> = Vec::new();
+        for name in names {
+            ParamPair::new(name, "")?.encode_to(&mut content);
+        }
+        if content.len() > MAX_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "names of management record exceed one record",
+            )
+            .into());
+        }
+        let header = Header::new(RequestType::GetValues, 0, &content);
+        Ok(Self { header, content })
+    }
+
+    pub(crate) async fn write_to_stream(
+        self,
+        writer: &mut (dyn AsyncWrite + Unpin),
+    ) -> io::Result<()> {
+        self.header.write_to_stream(writer, &self.content).await
     }
 }
 
@@ -327,7 +702,7 @@ impl<'a> DerefMut for ParamPairs<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ProtocolStatus {
     RequestComplete = 0,
@@ -346,10 +721,13 @@ impl ProtocolStatus {
         }
     }
 
-    pub(crate) fn convert_to_client_result(self, app_status: u32) -> ClientResult<()> {
+    pub(crate) fn convert_to_client_result(self, app_status: u32) -> ClientResult<RequestComplete> {
         match self {
-            ProtocolStatus::RequestComplete => Ok(()),
-            _ => Err(ClientError::new_end_request_with_protocol_status(self, app_status).into()),
+            ProtocolStatus::RequestComplete => Ok(RequestComplete {
+                app_status,
+                protocol_status: self,
+            }),
+            _ => Err(ClientError::new_end_request_with_protocol_status(self, app_status)),
         }
     }
 }
@@ -358,34 +736,171 @@ impl ProtocolStatus {
 pub struct EndRequest {
     pub(crate) app_status: u32,
     pub(crate) protocol_status: ProtocolStatus,
+    #[allow(dead_code)]
     reserved: [u8; 3],
 }
 
+impl EndRequest {
+    /// Length of the content of `FCGI_END_REQUEST`.
+    pub(crate) const LEN: usize = 8;
+
+    #[cfg_attr(not(any(test, feature = "testutil")), allow(dead_code))]
+    pub(crate) fn new(app_status: u32, protocol_status: ProtocolStatus) -> Self {
+        Self {
+            app_status,
+            protocol_status,
+            reserved: [0; 3],
+        }
+    }
+
+    /// Decode the big-endian `appStatus`, the `protocolStatus` and the 3
+    /// reserved bytes. Content after them is ignored.
+    pub(crate) fn decode(content: &[u8]) -> io::Result<Self> {
+        if content.len() < Self::LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "FCGI_END_REQUEST content too short",
+            ));
+        }
+        Ok(Self {
+            app_status: u32::from_be_bytes([content[0], content[1], content[2], content[3]]),
+            protocol_status: ProtocolStatus::from_u8(content[4]),
+            reserved: [content[5], content[6], content[7]],
+        })
+    }
+
+    #[cfg_attr(not(any(test, feature = "testutil")), allow(dead_code))]
+    pub(crate) fn encode(&self) -> [u8; Self::LEN] {
+        let mut content = [0; Self::LEN];
+        content[..4].copy_from_slice(&self.app_status.to_be_bytes());
+        content[4] = self.protocol_status as u8;
+        content[5..].copy_from_slice(&self.reserved);
+        content
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct EndRequestRec {
+    #[allow(dead_code)]
     header: Header,
     pub(crate) end_request: EndRequest,
 }
 
 impl EndRequestRec {
+    /// Read the content of the record, with its padding, so the stream is at
+    /// the next record even if the content is longer than expected.
+    ///
+    /// The content is read whole, a stream ending before fails with
+    /// `UnexpectedEof`, and a content shorter than `EndRequest::LEN` with
+    /// `InvalidData`, rather than being decoded partially.
     pub(crate) async fn from_header(
         header: &Header,
         reader: &mut (impl AsyncRead + Unpin + Send),
     ) -> io::Result<Self> {
         let header = header.clone();
-        let mut content = &*header.read_content_from_stream(reader).await?;
-        let app_status = content.read_u32().await?;
-        let protocol_status = ProtocolStatus::from_u8(content.read_u8().await?);
-        let mut reserved: [u8; 3] = [0; 3];
-        AsyncReadExt::read_exact(&mut content, &mut reserved).await?;
+        let content = header.read_content_from_stream(reader).await?;
+        let end_request = EndRequest::decode(&content)?;
+        Ok(Self { header, end_request })
+    }
+}
 
-        Ok(Self {
-            header,
-            end_request: EndRequest {
-                app_status,
-                protocol_status,
-                reserved,
-            },
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn param_length() {
+        let encode = |length: ParamLength| {
+            let mut buf = Vec::new();
+            length.encode_to(&mut buf);
+            buf
+        };
+
+        let short = ParamLength::new(127).unwrap();
+        assert_eq!(encode(short), [127]);
+
+        let long = ParamLength::new(128).unwrap();
+        assert_eq!(encode(long), [0x80, 0, 0, 128]);
+        assert_eq!(ParamLength::decode(&mut &[0x80, 0, 0, 128][..]).unwrap(), 128);
+
+        let max = ParamLength::new(ParamLength::MAX).unwrap();
+        assert_eq!(encode(max), [0xff, 0xff, 0xff, 0xff]);
+
+        assert!(ParamLength::new(ParamLength::MAX + 1).is_none());
+        #[cfg(target_pointer_width = "64")]
+        assert!(ParamLength::new(5 * 1024 * 1024 * 1024).is_none());
+    }
+
+    #[test]
+    fn header_padding() {
+        let padding = |length: usize| Header::new(RequestType::Stdin, 1, &vec![0; length]).padding_length;
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(5), 3);
+        assert_eq!(padding(32767), 1);
+        assert_eq!(padding(32768), 0);
+        assert_eq!(padding(32769), 7);
+        assert_eq!(padding(MAX_LENGTH), 1);
+    }
+
+    #[test]
+    fn begin_request_flags() {
+        assert_eq!(BeginRequestFlags::KEEP_CONN.bits(), 1);
+        assert_eq!(BeginRequestFlags::empty().bits(), 0);
+        assert_eq!(BeginRequestFlags::from(true), BeginRequestFlags::KEEP_CONN);
+        assert_eq!(BeginRequestFlags::from(false), BeginRequestFlags::empty());
+        assert_eq!(BeginRequestFlags::from_bits(1), Some(BeginRequestFlags::KEEP_CONN));
+        assert_eq!(BeginRequestFlags::from_bits(2), None);
+
+        let mut flags = BeginRequestFlags::empty();
+        assert!(!flags.contains(BeginRequestFlags::KEEP_CONN));
+        flags |= BeginRequestFlags::KEEP_CONN;
+        assert!(flags.contains(BeginRequestFlags::KEEP_CONN));
+        assert_eq!(BeginRequestFlags::empty() | BeginRequestFlags::KEEP_CONN, flags);
+    }
+
+    #[tokio::test]
+    async fn begin_request_roles() {
+        for &role in [Role::Responder, Role::Authorizer, Role::Filter].iter() {
+            for &keep_alive in [false, true].iter() {
+                let rec = BeginRequestRec::new(1, role, keep_alive.into()).await.unwrap();
+                assert_eq!(rec.header.content_length, 8);
+                assert_eq!(rec.content, [0, role as u8, keep_alive as u8, 0, 0, 0, 0, 0]);
+
+                let flags = keep_alive as u8;
+                let rec = BeginRequestRec::with_flags(1, role, flags).await.unwrap();
+                assert_eq!(rec.content, [0, role as u8, flags, 0, 0, 0, 0, 0]);
+            }
+
+            for &flags in [2, 0x80, 0xff].iter() {
+                match BeginRequestRec::with_flags(1, role, flags).await {
+                    Err(ClientError::InvalidBeginRequest { role: r, flags: f }) => {
+                        assert_eq!((r, f), (role, flags))
+                    }
+                    _ => panic!("expect invalid flags {:#04x}", flags),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn end_request_round_trip() {
+        let end_request = EndRequest::new(0x0102_0304, ProtocolStatus::Overloaded);
+        let content = end_request.encode();
+        assert_eq!(content, [1, 2, 3, 4, 2, 0, 0, 0]);
+
+        // Followed by the padding and the next record.
+        let header = Header::new(RequestType::EndRequest, 1, &content);
+        let mut stream = Vec::new();
+        header.write_to_stream(&mut stream, &content).await.unwrap();
+        stream.extend_from_slice(&[VERSION_1, RequestType::Stdout as u8]);
+
+        let mut reader = &stream[..];
+        let header = Header::new_from_stream(&mut reader).await.unwrap();
+        let rec = EndRequestRec::from_header(&header, &mut reader).await.unwrap();
+        assert_eq!(rec.end_request.app_status, 0x0102_0304);
+        assert_eq!(rec.end_request.protocol_status, ProtocolStatus::Overloaded);
+        assert_eq!(reader, [VERSION_1, RequestType::Stdout as u8]);
+
+        assert!(EndRequest::decode(&content[..7]).is_err());
     }
 }
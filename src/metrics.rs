@@ -0,0 +1,94 @@
+//! Process wide counters of the requests handled by `Client`, read by
+//! [`snapshot`] to be exported to a metrics system.
+//!
+//! A request is counted when its response ends, so requests cancelled by a
+//! timeout or dropped before the end aren't counted.
+
+use crate::{response::RequestComplete, ClientError, ClientResult};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+const ERROR_KINDS: [&str; 8] = [
+    "io",
+    "connection_closed",
+    "cant_mpx_conn",
+    "overloaded",
+    "unknown_role",
+    "response_too_large",
+    "unknown_request_type",
+    "other",
+];
+
+static REQUESTS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static REQUEST_DURATION_MICROS: AtomicU64 = AtomicU64::new(0);
+static STDOUT_BYTES: AtomicU64 = AtomicU64::new(0);
+static STDERR_BYTES: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static ERRORS_TOTAL: [AtomicU64; ERROR_KINDS.len()] = [ZERO; ERROR_KINDS.len()];
+
+/// Values of the counters at the time of [`snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// Requests whose response ended, successfully or not.
+    pub requests_total: u64,
+    /// Sum of the durations of the requests, from the start of writing the
+    /// request to the end of response.
+    pub request_duration: Duration,
+    /// Bytes of STDOUT received.
+    pub stdout_bytes: u64,
+    /// Bytes of STDERR received.
+    pub stderr_bytes: u64,
+    /// Failed requests by kind of error, such as `connection_closed` or
+    /// `overloaded`.
+    pub errors_total: HashMap<&'static str, u64>,
+}
+
+/// Read the current values of the counters.
+pub fn snapshot() -> Metrics {
+    Metrics {
+        requests_total: REQUESTS_TOTAL.load(Ordering::Relaxed),
+        request_duration: Duration::from_micros(REQUEST_DURATION_MICROS.load(Ordering::Relaxed)),
+        stdout_bytes: STDOUT_BYTES.load(Ordering::Relaxed),
+        stderr_bytes: STDERR_BYTES.load(Ordering::Relaxed),
+        errors_total: ERROR_KINDS
+            .iter()
+            .zip(ERRORS_TOTAL.iter())
+            .map(|(kind, count)| (*kind, count.load(Ordering::Relaxed)))
+            .collect(),
+    }
+}
+
+pub(crate) fn record(
+    rv: &ClientResult<RequestComplete>,
+    elapsed: Option<Duration>,
+    stdout_bytes: usize,
+    stderr_bytes: usize,
+) {
+    REQUESTS_TOTAL.fetch_add(1, Ordering::Relaxed);
+    if let Some(elapsed) = elapsed {
+        REQUEST_DURATION_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+    STDOUT_BYTES.fetch_add(stdout_bytes as u64, Ordering::Relaxed);
+    STDERR_BYTES.fetch_add(stderr_bytes as u64, Ordering::Relaxed);
+    if let Err(err) = rv {
+        ERRORS_TOTAL[error_kind(err)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Index of the kind of error in `ERROR_KINDS`.
+fn error_kind(err: &ClientError) -> usize {
+    match err {
+        ClientError::Io(_) => 0,
+        ClientError::ConnectionClosed { .. } => 1,
+        ClientError::EndRequestCantMpxConn { .. } => 2,
+        ClientError::EndRequestOverloaded { .. } => 3,
+        ClientError::EndRequestUnknownRole { .. } => 4,
+        ClientError::ResponseTooLarge { .. } => 5,
+        ClientError::UnknownRequestType { .. } => 6,
+        _ => 7,
+    }
+}
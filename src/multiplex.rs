@@ -0,0 +1,404 @@
+use crate::{
+    client::handle_fastcgi_request,
+    id::{IdAllocator, RequestIdGenerator, RequestIdGuard},
+    meta::{EndRequestRec, Header, RequestType},
+    params::Params,
+    request::Request,
+    response::{RequestComplete, Response},
+    ClientError, ClientResult, ClientStream, Phase,
+};
+use bytes::Bytes;
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf},
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+};
+
+/// Default count of outputs buffered for one request before the reader waits
+/// for it to be consumed.
+const OUTPUT_CHANNEL_CAPACITY: usize = 16;
+
+/// Output of a request routed by the reader task.
+enum Output {
+    Stdout(Bytes),
+    Stderr(Bytes),
+    End(EndRequestRec),
+    Error(io::Error),
+}
+
+/// In flight requests waiting for outputs, by request id, `None` once the
+/// reader stopped.
+type OutputMap = Arc<StdMutex<Option<HashMap<u16, mpsc::Sender<Output>>>>>;
+
+/// Registration of a request in the `OutputMap`, removed when dropped, so a
+/// cancelled request doesn't leave its sender behind.
+struct Registration<'a> {
+    outputs: &'a OutputMap,
+    id: u16,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        if let Some(outputs) = &mut *self.outputs.lock().unwrap() {
+            outputs.remove(&self.id);
+        }
+    }
+}
+
+/// STDIN of a request kept as it's read, so the request can be re-sent once
+/// rejected with `FCGI_CANT_MPX_CONN`.
+struct Replay<'a, I> {
+    inner: &'a mut I,
+    read: Vec<u8>,
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Replay<'_, I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let rv = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = rv {
+            self.read.extend_from_slice(&buf.filled()[filled..]);
+        }
+        rv
+    }
+}
+
+/// Async client sending many concurrent requests over one keep alive
+/// connection, the responses are demultiplexed by request id.
+///
+/// A background task owns the read side of the stream and routes records to
+/// the in flight requests, so it must be constructed within a tokio runtime.
+/// The backend should support multiplexing (`FCGI_MPXS_CONNS`), which php-fpm
+/// doesn't. Once the backend rejects a request with `FCGI_CANT_MPX_CONN`, the
+/// client falls back to sending the requests one at a time, starting with the
+/// rejected request, which is re-sent. Until then the STDIN of each request is
+/// kept in memory for the re-sending.
+///
+/// The outputs of a request are routed through a bounded channel, see
+/// [`MultiplexClient::set_output_capacity`], so a slow sink holds back the
+/// reader, and the server by the backpressure of the connection.
+pub struct MultiplexClient<S: ClientStream + 'static> {
+    writer: Mutex<WriteHalf<S>>,
+    outputs: OutputMap,
+    request_id_generator: Arc<dyn IdAllocator>,
+    reader: JoinHandle<()>,
+    /// Whether the requests are sent one at a time, holding `serial` for the
+    /// whole exchange.
+    serialized: AtomicBool,
+    serial: Mutex<()>,
+    output_capacity: usize,
+    /// Whether a request was partially written, so the stream is corrupted
+    /// and the client stopped.
+    poisoned: AtomicBool,
+}
+
+/// Poisons the client if dropped while armed, such as when the writing of a
+/// request fails or is cancelled partway through.
+struct PoisonOnDrop<'a, S: ClientStream + 'static> {
+    client: Option<&'a MultiplexClient<S>>,
+}
+
+impl<S: ClientStream + 'static> PoisonOnDrop<'_, S> {
+    fn disarm(mut self) {
+        self.client = None;
+    }
+}
+
+impl<S: ClientStream + 'static> Drop for PoisonOnDrop<'_, S> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client {
+            client.poison();
+        }
+    }
+}
+
+impl<S: ClientStream + 'static> MultiplexClient<S> {
+    /// Construct a `MultiplexClient` Object with stream, such as
+    /// `tokio::net::TcpStream` or `tokio::net::UnixStream`.
+    pub fn new(stream: S) -> Self {
+        let (read_stream, write_stream) = split(stream);
+        let outputs = Arc::new(StdMutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(read_outputs(read_stream, outputs.clone()));
+
+        Self {
+            writer: Mutex::new(write_stream),
+            outputs,
+            request_id_generator: Arc::new(RequestIdGenerator::new(Duration::from_millis(1500))),
+            reader,
+            serialized: AtomicBool::new(false),
+            serial: Mutex::new(()),
+            output_capacity: OUTPUT_CHANNEL_CAPACITY,
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    /// Set the timeout of allocating a request id when all the ids are in
+    /// use, after which the request fails with
+    /// `ClientError::NoAvailableRequestId`. Defaults to 1500 milliseconds.
+    pub fn set_request_id_timeout(mut self, timeout: Duration) -> Self {
+        self.request_id_generator = Arc::new(RequestIdGenerator::new(timeout));
+        self
+    }
+
+    /// Set the count of STDOUT and STDERR records buffered for a request
+    /// before the reader waits for them to be written to its sinks, at least
+    /// 1. Defaults to 16.
+    ///
+    /// While the reader waits, the records of the other requests aren't read
+    /// either, so a slow sink holds back every request of the connection.
+    pub fn set_output_capacity(mut self, capacity: usize) -> Self {
+        self.output_capacity = capacity.max(1);
+        self
+    }
+
+    /// Count of the requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.request_id_generator.in_use()
+    }
+
+    /// Max count of the requests in flight, the pool size of the id generator.
+    pub fn capacity(&self) -> usize {
+        self.request_id_generator.pool_size() as usize
+    }
+
+    /// Whether the client fell back to sending the requests one at a time,
+    /// because the backend can't multiplex.
+    pub fn is_serialized(&self) -> bool {
+        self.serialized.load(Ordering::SeqCst)
+    }
+
+    /// Whether a request was partially written, because the writing failed or
+    /// was cancelled, which corrupts the stream for every request. The reader
+    /// is stopped, the requests in flight fail with
+    /// `ClientError::ConnectionClosed` and the later ones fail too, so the
+    /// client has to be dropped.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::SeqCst)
+    }
+
+    /// Stop the reader and fail the requests in flight, whose senders are
+    /// dropped.
+    fn poison(&self) {
+        warn!("Request partially written, stop the multiplexed connection.");
+        self.poisoned.store(true, Ordering::SeqCst);
+        self.reader.abort();
+        self.outputs.lock().unwrap().take();
+    }
+
+    /// Send request and receive response from fastcgi server, can be called
+    /// concurrently.
+    ///
+    /// A request rejected with `FCGI_CANT_MPX_CONN` is re-sent once the
+    /// requests are sent one at a time, and fails with
+    /// `ClientError::CannotMultiplex` if rejected again. A request whose writing
+    /// fails or is cancelled partway through poisons the client, see
+    /// [`MultiplexClient::is_poisoned`].
+    pub async fn execute<I: AsyncRead + Unpin>(
+        &self,
+        mut request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        if self.is_serialized() {
+            let _serial = self.serial.lock().await;
+            return self
+                .execute_once(&request.params, &mut request.stdin, stdout, stderr)
+                .await;
+        }
+
+        let mut stdin = Replay {
+            inner: &mut request.stdin,
+            read: Vec::new(),
+        };
+        match self.execute_once(&request.params, &mut stdin, stdout, stderr).await {
+            Err(ClientError::CannotMultiplex { id }) => {
+                debug!("[id = {}] Backend can't multiplex, send requests one at a time.", id);
+                self.serialized.store(true, Ordering::SeqCst);
+
+                let _serial = self.serial.lock().await;
+                let Replay { inner, read } = stdin;
+                let mut stdin = read.as_slice().chain(inner);
+                self.execute_once(&request.params, &mut stdin, stdout, stderr)
+                    .await
+            }
+            rv => rv,
+        }
+    }
+
+    /// Send the request with a new id, a rejection with `FCGI_CANT_MPX_CONN`
+    /// fails with `ClientError::CannotMultiplex`.
+    async fn execute_once(
+        &self,
+        params: &Params<'_>,
+        stdin: &mut (dyn AsyncRead + Unpin),
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        // Dropped in the reverse order, so the id is released once the
+        // request is no longer registered, even if the request is cancelled.
+        let guard = RequestIdGuard::alloc(&self.request_id_generator).await?;
+        let id = guard.id();
+        let (sender, receiver) = mpsc::channel(self.output_capacity);
+        let registration = match &mut *self.outputs.lock().unwrap() {
+            Some(outputs) => {
+                outputs.insert(id, sender);
+                Some(Registration {
+                    outputs: &self.outputs,
+                    id,
+                })
+            }
+            None => None,
+        };
+
+        let rv = match registration {
+            Some(_) => self.handle(id, params, stdin, receiver, stdout, stderr).await,
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "stream reader stopped").into()),
+        };
+        drop(registration);
+        drop(guard);
+
+        match rv {
+            Err(ClientError::EndRequestCantMpxConn { .. }) => Err(ClientError::CannotMultiplex { id }),
+            rv => rv,
+        }
+    }
+
+    /// Send request and receive response from fastcgi server, collecting
+    /// STDOUT and STDERR of the response into a [`Response`].
+    ///
+    /// A non-zero app status fails with `ClientError::RequestFailed`, with the
+    /// STDERR of the response.
+    pub async fn execute_buffered<I: AsyncRead + Unpin>(
+        &self,
+        request: Request<'_, I>,
+    ) -> ClientResult<Response> {
+        let mut response = Response::default();
+        let complete = self
+            .execute(request, &mut response.stdout, &mut response.stderr)
+            .await?;
+        response.into_result(complete.app_status)
+    }
+
+    async fn handle(
+        &self,
+        id: u16,
+        params: &Params<'_>,
+        stdin: &mut (dyn AsyncRead + Unpin),
+        mut receiver: mpsc::Receiver<Output>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        {
+            let mut writer = self.writer.lock().await;
+            let poison = PoisonOnDrop { client: Some(self) };
+            match handle_fastcgi_request(&mut *writer, true, id, params, stdin).await {
+                Ok(()) => poison.disarm(),
+                // Nothing was written, such as for invalid params.
+                Err(err) if !err.is_stream_error() => {
+                    poison.disarm();
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        loop {
+            match receiver.recv().await {
+                Some(Output::Stdout(content)) => stdout.write_all(&content).await?,
+                Some(Output::Stderr(content)) => stderr.write_all(&content).await?,
+                Some(Output::End(end_request_rec)) => {
+                    return end_request_rec
+                        .end_request
+                        .protocol_status
+                        .convert_to_client_result(end_request_rec.end_request.app_status)
+                }
+                Some(Output::Error(err)) => {
+                    return Err(ClientError::from(err).into_connection_closed(id, Phase::Response))
+                }
+                None if self.is_poisoned() => {
+                    return Err(ClientError::ConnectionClosed {
+                        id,
+                        during: Phase::Response,
+                    })
+                }
+                None => return Err(ClientError::ResponseNotFound { id }),
+            }
+        }
+    }
+}
+
+impl<S: ClientStream + 'static> Drop for MultiplexClient<S> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Read records from the stream until it fails, routing them to the in flight
+/// requests.
+async fn read_outputs<S: AsyncRead + Send>(mut read_stream: ReadHalf<S>, outputs: OutputMap) {
+    let err = loop {
+        let header = match Header::new_from_stream(&mut read_stream).await {
+            Ok(header) => header,
+            Err(err) => break err,
+        };
+        debug!("[id = {}] Receive from stream: {:?}.", header.request_id, &header);
+
+        let id = header.request_id;
+        let (output, end) = match header.r#type {
+            RequestType::EndRequest => match EndRequestRec::from_header(&header, &mut read_stream).await {
+                Ok(end_request_rec) => (Output::End(end_request_rec), true),
+                Err(err) => break err,
+            },
+            ref r#type => {
+                let content = match header.read_content_from_stream(&mut read_stream).await {
+                    Ok(content) => content,
+                    Err(err) => break err,
+                };
+                match r#type {
+                    RequestType::Stdout => (Output::Stdout(content), false),
+                    RequestType::Stderr => (Output::Stderr(content), false),
+                    r#type => {
+                        debug!("[id = {}] Discard record of type {}.", id, r#type);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let sender = match &mut *outputs.lock().unwrap() {
+            Some(outputs) if end => outputs.remove(&id),
+            Some(outputs) => outputs.get(&id).cloned(),
+            None => None,
+        };
+        match sender {
+            // The request may have been dropped, nothing to do.
+            Some(sender) => {
+                let _ = sender.send(output).await;
+            }
+            None => warn!("[id = {}] Discard record of request not in flight.", id),
+        }
+    };
+
+    debug!("Stop reading stream: {}.", err);
+
+    let senders = outputs.lock().unwrap().take().unwrap_or_default();
+    for (_, sender) in senders {
+        let _ = sender
+            .send(Output::Error(io::Error::new(err.kind(), err.to_string())))
+            .await;
+    }
+}
@@ -1,122 +1,496 @@
+use crate::{
+    meta::{is_valid_param_name, is_valid_param_value, ParamPairs},
+    ClientResult,
+};
 use std::{
+    borrow::Cow,
     collections::HashMap,
-    ops::{Deref, DerefMut},
+    iter::FromIterator,
+    ops::{Deref, Index},
 };
 
 /// Fastcgi params, please reference to nginx-php-fpm fastcgi_params.
+///
+/// The params are kept in the order of insertion, which is the order they are
+/// sent to the server. The names and values are borrowed or owned strings, so
+/// values built for the request, such as `CONTENT_LENGTH`, can be inserted.
 #[derive(Debug, Clone)]
-pub struct Params<'a>(HashMap<String, &'a str>);
+pub struct Params<'a>(Vec<(Cow<'a, str>, Cow<'a, str>)>);
 
 impl<'a> Params<'a> {
-    pub fn insert<K: Into<String>>(&mut self, key: K, val: &'a str) {
-        self.0.insert(key.into(), val);
+    /// Insert the param, if the key is present the value is replaced in place,
+    /// keeping the position of the key.
+    pub fn insert<K, V>(&mut self, key: K, val: V)
+    where
+        K: Into<Cow<'a, str>>,
+        V: Into<Cow<'a, str>>,
+    {
+        let key = key.into();
+        let val = val.into();
+        match self.0.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, value)) => *value = val,
+            None => self.0.push((key, val)),
+        }
+    }
+
+    /// Get the value of the param, `None` if the key isn't present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| &**value)
+    }
+
+    /// Whether the key is present.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Remove the param, returns its value, `None` if the key isn't present.
+    /// The other params keep their order.
+    pub fn remove(&mut self, key: &str) -> Option<Cow<'a, str>> {
+        let index = self.0.iter().position(|(name, _)| name == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    /// The params as a map of names to values, for the code written against
+    /// the former `Deref<Target = HashMap>` of `Params`. The map doesn't keep
+    /// the order of the params, and changing it doesn't change them, use
+    /// [`Params::insert`] and [`Params::remove`] instead.
+    pub fn to_hash_map(&self) -> HashMap<&str, &str> {
+        self.0.iter().map(|(key, value)| (&**key, &**value)).collect()
+    }
+
+    /// Convert the borrowed names and values to owned ones, so the params
+    /// are `'static`, such as for a request sent to another task.
+    pub fn into_owned(self) -> Params<'static> {
+        Params(
+            self.0
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        Cow::Owned(name.into_owned()),
+                        Cow::Owned(value.into_owned()),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Make the invalid params valid rather than failing the request with
+    /// `ClientError::InvalidParam`, the characters of the names other than
+    /// ASCII letters, digits and underscores are replaced with underscores,
+    /// and the NUL, CR and LF of the values are removed. The params of an
+    /// empty name are removed.
+    pub fn sanitize(mut self) -> Self {
+        self.0.retain(|(name, _)| !name.is_empty());
+        for (name, value) in self.0.iter_mut() {
+            if !is_valid_param_name(name) {
+                let sanitized = name
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect::<String>();
+                *name = sanitized.into();
+            }
+            if !is_valid_param_value(value) {
+                let sanitized = value
+                    .chars()
+                    .filter(|c| !matches!(c, '\0' | '\r' | '\n'))
+                    .collect::<String>();
+                *value = sanitized.into();
+            }
+        }
+        self
+    }
+
+    /// Encode the params as the content of the `FCGI_PARAMS` stream, to be
+    /// sent as is by [`Client::execute_with_encoded_params`], for params sent
+    /// with many requests.
+    ///
+    /// [`Client::execute_with_encoded_params`]: crate::Client::execute_with_encoded_params
+    pub fn encode(&self) -> ClientResult<Vec<u8>> {
+        Ok(ParamPairs::new(self)?.to_content())
     }
 
-    pub fn set_gateway_interface(mut self, gateway_interface: &'a str) -> Self {
+    pub fn set_gateway_interface<V: Into<Cow<'a, str>>>(mut self, gateway_interface: V) -> Self {
         self.insert("GATEWAY_INTERFACE", gateway_interface);
         self
     }
 
-    pub fn set_server_software(mut self, server_software: &'a str) -> Self {
+    pub fn set_server_software<V: Into<Cow<'a, str>>>(mut self, server_software: V) -> Self {
         self.insert("SERVER_SOFTWARE", server_software);
         self
     }
 
-    pub fn set_server_protocol(mut self, server_protocol: &'a str) -> Self {
+    pub fn set_server_protocol<V: Into<Cow<'a, str>>>(mut self, server_protocol: V) -> Self {
         self.insert("SERVER_PROTOCOL", server_protocol);
         self
     }
 
-    pub fn set_request_method(mut self, request_method: &'a str) -> Self {
+    pub fn set_request_method<V: Into<Cow<'a, str>>>(mut self, request_method: V) -> Self {
         self.insert("REQUEST_METHOD", request_method);
         self
     }
 
-    pub fn set_script_filename(mut self, script_filename: &'a str) -> Self {
+    pub fn set_script_filename<V: Into<Cow<'a, str>>>(mut self, script_filename: V) -> Self {
         self.insert("SCRIPT_FILENAME", script_filename);
         self
     }
 
-    pub fn set_script_name(mut self, script_name: &'a str) -> Self {
+    pub fn set_script_name<V: Into<Cow<'a, str>>>(mut self, script_name: V) -> Self {
         self.insert("SCRIPT_NAME", script_name);
         self
     }
 
-    pub fn set_query_string(mut self, query_string: &'a str) -> Self {
+    pub fn set_query_string<V: Into<Cow<'a, str>>>(mut self, query_string: V) -> Self {
         self.insert("QUERY_STRING", query_string);
         self
     }
 
-    pub fn set_request_uri(mut self, request_uri: &'a str) -> Self {
+    pub fn set_request_uri<V: Into<Cow<'a, str>>>(mut self, request_uri: V) -> Self {
         self.insert("REQUEST_URI", request_uri);
         self
     }
 
-    pub fn set_document_root(mut self, document_root: &'a str) -> Self {
+    pub fn set_document_root<V: Into<Cow<'a, str>>>(mut self, document_root: V) -> Self {
         self.insert("DOCUMENT_ROOT", document_root);
         self
     }
 
-    pub fn set_document_uri(mut self, document_uri: &'a str) -> Self {
+    pub fn set_document_uri<V: Into<Cow<'a, str>>>(mut self, document_uri: V) -> Self {
         self.insert("DOCUMENT_URI", document_uri);
         self
     }
 
-    pub fn set_remote_addr(mut self, remote_addr: &'a str) -> Self {
+    pub fn set_remote_addr<V: Into<Cow<'a, str>>>(mut self, remote_addr: V) -> Self {
         self.insert("REMOTE_ADDR", remote_addr);
         self
     }
 
-    pub fn set_remote_port(mut self, remote_port: &'a str) -> Self {
+    pub fn set_remote_port<V: Into<Cow<'a, str>>>(mut self, remote_port: V) -> Self {
         self.insert("REMOTE_PORT", remote_port);
         self
     }
 
-    pub fn set_server_addr(mut self, server_addr: &'a str) -> Self {
+    pub fn set_server_addr<V: Into<Cow<'a, str>>>(mut self, server_addr: V) -> Self {
         self.insert("SERVER_ADDR", server_addr);
         self
     }
 
-    pub fn set_server_port(mut self, server_port: &'a str) -> Self {
+    pub fn set_server_port<V: Into<Cow<'a, str>>>(mut self, server_port: V) -> Self {
         self.insert("SERVER_PORT", server_port);
         self
     }
 
-    pub fn set_server_name(mut self, server_name: &'a str) -> Self {
+    pub fn set_server_name<V: Into<Cow<'a, str>>>(mut self, server_name: V) -> Self {
         self.insert("SERVER_NAME", server_name);
         self
     }
 
-    pub fn set_content_type(mut self, content_type: &'a str) -> Self {
+    pub fn set_content_type<V: Into<Cow<'a, str>>>(mut self, content_type: V) -> Self {
         self.insert("CONTENT_TYPE", content_type);
         self
     }
 
-    pub fn set_content_length(mut self, content_length: &'a str) -> Self {
+    pub fn set_content_length<V: Into<Cow<'a, str>>>(mut self, content_length: V) -> Self {
         self.insert("CONTENT_LENGTH", content_length);
         self
     }
+
+    pub fn set_path_info<V: Into<Cow<'a, str>>>(mut self, path_info: V) -> Self {
+        self.insert("PATH_INFO", path_info);
+        self
+    }
+
+    pub fn set_path_translated<V: Into<Cow<'a, str>>>(mut self, path_translated: V) -> Self {
+        self.insert("PATH_TRANSLATED", path_translated);
+        self
+    }
+
+    pub fn set_remote_host<V: Into<Cow<'a, str>>>(mut self, remote_host: V) -> Self {
+        self.insert("REMOTE_HOST", remote_host);
+        self
+    }
+
+    pub fn set_remote_user<V: Into<Cow<'a, str>>>(mut self, remote_user: V) -> Self {
+        self.insert("REMOTE_USER", remote_user);
+        self
+    }
+
+    pub fn set_auth_type<V: Into<Cow<'a, str>>>(mut self, auth_type: V) -> Self {
+        self.insert("AUTH_TYPE", auth_type);
+        self
+    }
+
+    pub fn set_https<V: Into<Cow<'a, str>>>(mut self, https: V) -> Self {
+        self.insert("HTTPS", https);
+        self
+    }
+
+    pub fn set_redirect_status<V: Into<Cow<'a, str>>>(mut self, redirect_status: V) -> Self {
+        self.insert("REDIRECT_STATUS", redirect_status);
+        self
+    }
+
+    /// Set the param of custom name, chainable version of [`Params::insert`].
+    pub fn param<K, V>(mut self, key: K, val: V) -> Self
+    where
+        K: Into<Cow<'a, str>>,
+        V: Into<Cow<'a, str>>,
+    {
+        self.insert(key, val);
+        self
+    }
+
+    /// Set `GATEWAY_INTERFACE`, same as [`Params::set_gateway_interface`].
+    pub fn gateway_interface<V: Into<Cow<'a, str>>>(self, gateway_interface: V) -> Self {
+        self.set_gateway_interface(gateway_interface)
+    }
+
+    /// Set `SERVER_SOFTWARE`, same as [`Params::set_server_software`].
+    pub fn server_software<V: Into<Cow<'a, str>>>(self, server_software: V) -> Self {
+        self.set_server_software(server_software)
+    }
+
+    /// Set `SERVER_PROTOCOL`, same as [`Params::set_server_protocol`].
+    pub fn server_protocol<V: Into<Cow<'a, str>>>(self, server_protocol: V) -> Self {
+        self.set_server_protocol(server_protocol)
+    }
+
+    /// Set `REQUEST_METHOD`, same as [`Params::set_request_method`].
+    pub fn request_method<V: Into<Cow<'a, str>>>(self, request_method: V) -> Self {
+        self.set_request_method(request_method)
+    }
+
+    /// Set `SCRIPT_FILENAME`, same as [`Params::set_script_filename`].
+    pub fn script_filename<V: Into<Cow<'a, str>>>(self, script_filename: V) -> Self {
+        self.set_script_filename(script_filename)
+    }
+
+    /// Set `SCRIPT_NAME`, same as [`Params::set_script_name`].
+    pub fn script_name<V: Into<Cow<'a, str>>>(self, script_name: V) -> Self {
+        self.set_script_name(script_name)
+    }
+
+    /// Set `QUERY_STRING`, same as [`Params::set_query_string`].
+    pub fn query_string<V: Into<Cow<'a, str>>>(self, query_string: V) -> Self {
+        self.set_query_string(query_string)
+    }
+
+    /// Set `REQUEST_URI`, same as [`Params::set_request_uri`].
+    pub fn request_uri<V: Into<Cow<'a, str>>>(self, request_uri: V) -> Self {
+        self.set_request_uri(request_uri)
+    }
+
+    /// Set `DOCUMENT_ROOT`, same as [`Params::set_document_root`].
+    pub fn document_root<V: Into<Cow<'a, str>>>(self, document_root: V) -> Self {
+        self.set_document_root(document_root)
+    }
+
+    /// Set `DOCUMENT_URI`, same as [`Params::set_document_uri`].
+    pub fn document_uri<V: Into<Cow<'a, str>>>(self, document_uri: V) -> Self {
+        self.set_document_uri(document_uri)
+    }
+
+    /// Set `REMOTE_ADDR`, same as [`Params::set_remote_addr`].
+    pub fn remote_addr<V: Into<Cow<'a, str>>>(self, remote_addr: V) -> Self {
+        self.set_remote_addr(remote_addr)
+    }
+
+    /// Set `REMOTE_PORT`, same as [`Params::set_remote_port`].
+    pub fn remote_port<V: Into<Cow<'a, str>>>(self, remote_port: V) -> Self {
+        self.set_remote_port(remote_port)
+    }
+
+    /// Set `SERVER_ADDR`, same as [`Params::set_server_addr`].
+    pub fn server_addr<V: Into<Cow<'a, str>>>(self, server_addr: V) -> Self {
+        self.set_server_addr(server_addr)
+    }
+
+    /// Set `SERVER_PORT`, same as [`Params::set_server_port`].
+    pub fn server_port<V: Into<Cow<'a, str>>>(self, server_port: V) -> Self {
+        self.set_server_port(server_port)
+    }
+
+    /// Set `SERVER_NAME`, same as [`Params::set_server_name`].
+    pub fn server_name<V: Into<Cow<'a, str>>>(self, server_name: V) -> Self {
+        self.set_server_name(server_name)
+    }
+
+    /// Set `CONTENT_TYPE`, same as [`Params::set_content_type`].
+    pub fn content_type<V: Into<Cow<'a, str>>>(self, content_type: V) -> Self {
+        self.set_content_type(content_type)
+    }
+
+    /// Set `CONTENT_LENGTH`, same as [`Params::set_content_length`].
+    pub fn content_length<V: Into<Cow<'a, str>>>(self, content_length: V) -> Self {
+        self.set_content_length(content_length)
+    }
+
+    /// Set `PATH_INFO`, same as [`Params::set_path_info`].
+    pub fn path_info<V: Into<Cow<'a, str>>>(self, path_info: V) -> Self {
+        self.set_path_info(path_info)
+    }
+
+    /// Set `PATH_TRANSLATED`, same as [`Params::set_path_translated`].
+    pub fn path_translated<V: Into<Cow<'a, str>>>(self, path_translated: V) -> Self {
+        self.set_path_translated(path_translated)
+    }
+
+    /// Set `REMOTE_HOST`, same as [`Params::set_remote_host`].
+    pub fn remote_host<V: Into<Cow<'a, str>>>(self, remote_host: V) -> Self {
+        self.set_remote_host(remote_host)
+    }
+
+    /// Set `REMOTE_USER`, same as [`Params::set_remote_user`].
+    pub fn remote_user<V: Into<Cow<'a, str>>>(self, remote_user: V) -> Self {
+        self.set_remote_user(remote_user)
+    }
+
+    /// Set `AUTH_TYPE`, same as [`Params::set_auth_type`].
+    pub fn auth_type<V: Into<Cow<'a, str>>>(self, auth_type: V) -> Self {
+        self.set_auth_type(auth_type)
+    }
+
+    /// Set `HTTPS`, same as [`Params::set_https`].
+    pub fn https<V: Into<Cow<'a, str>>>(self, https: V) -> Self {
+        self.set_https(https)
+    }
+
+    /// Set `REDIRECT_STATUS`, same as [`Params::set_redirect_status`].
+    pub fn redirect_status<V: Into<Cow<'a, str>>>(self, redirect_status: V) -> Self {
+        self.set_redirect_status(redirect_status)
+    }
 }
 
 impl<'a> Default for Params<'a> {
     fn default() -> Self {
-        Params(HashMap::new())
+        Params(Vec::new())
             .set_gateway_interface("FastCGI/1.0")
             .set_server_software("fastcgi-client-rs")
             .set_server_protocol("HTTP/1.1")
     }
 }
 
+/// Map the http request to CGI params: the method to `REQUEST_METHOD`, the
+/// uri to `REQUEST_URI`, `SCRIPT_NAME`, `DOCUMENT_URI`, `PATH_INFO` and
+/// `QUERY_STRING`, `Content-Type` and `Content-Length` to `CONTENT_TYPE` and
+/// `CONTENT_LENGTH`, and other headers like `Foo-Bar` to `HTTP_FOO_BAR`, with
+/// any character other than ASCII letters and digits replaced with an
+/// underscore.
+///
+/// `PATH_INFO` is the whole path, as for a front controller, since splitting
+/// it from the script path depends on the server, set `SCRIPT_NAME` and
+/// `PATH_INFO` afterwards for another split.
+///
+/// The values of a repeated header are joined by `", "`, or `"; "` for
+/// `Cookie`, like nginx does. Headers with non visible ASCII value are
+/// skipped, and so are header names with an underscore, which would be
+/// mistaken for the header with a dash in its place, and `Proxy`, whose
+/// `HTTP_PROXY` would be taken for the proxy of the app (httpoxy).
+#[cfg(feature = "http")]
+impl<'a> From<&'a http::request::Parts> for Params<'a> {
+    fn from(parts: &'a http::request::Parts) -> Self {
+        let path = parts.uri.path();
+        let mut params = Params::default()
+            .set_request_method(parts.method.as_str())
+            .set_request_uri(
+                parts
+                    .uri
+                    .path_and_query()
+                    .map(|path_and_query| path_and_query.as_str())
+                    .unwrap_or(path),
+            )
+            .set_script_name(path)
+            .set_document_uri(path)
+            .set_path_info(path)
+            .set_query_string(parts.uri.query().unwrap_or(""));
+
+        let server_protocol = match parts.version {
+            http::Version::HTTP_09 => "HTTP/0.9",
+            http::Version::HTTP_10 => "HTTP/1.0",
+            http::Version::HTTP_2 => "HTTP/2.0",
+            http::Version::HTTP_3 => "HTTP/3.0",
+            _ => "HTTP/1.1",
+        };
+        params = params.set_server_protocol(server_protocol);
+
+        for name in parts.headers.keys() {
+            if name.as_str().contains('_') || name == "proxy" {
+                continue;
+            }
+            let values: Vec<&str> = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            let separator = if name == http::header::COOKIE { "; " } else { ", " };
+            let value: Cow<'a, str> = match values[..] {
+                [] => continue,
+                [value] => value.into(),
+                _ => values.join(separator).into(),
+            };
+
+            let key = match *name {
+                http::header::CONTENT_TYPE => "CONTENT_TYPE".to_owned(),
+                http::header::CONTENT_LENGTH => "CONTENT_LENGTH".to_owned(),
+                _ => format!(
+                    "HTTP_{}",
+                    name.as_str()
+                        .chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+                        .collect::<String>()
+                ),
+            };
+            params.insert(key, value);
+        }
+
+        params
+    }
+}
+
+/// Collect the params in the order of the iterator, without the defaults of
+/// `Params::default`. A repeated key keeps its first position and the last
+/// value.
+impl<'a, K, V> FromIterator<(K, V)> for Params<'a>
+where
+    K: Into<Cow<'a, str>>,
+    V: Into<Cow<'a, str>>,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut params = Params(Vec::new());
+        for (key, val) in iter {
+            params.insert(key, val);
+        }
+        params
+    }
+}
+
+/// Same as collecting the map, in the iteration order of the map.
+impl<'a> From<HashMap<String, String>> for Params<'a> {
+    fn from(map: HashMap<String, String>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
 impl<'a> Deref for Params<'a> {
-    type Target = HashMap<String, &'a str>;
+    type Target = [(Cow<'a, str>, Cow<'a, str>)];
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl<'a> DerefMut for Params<'a> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl<'a> Index<&str> for Params<'a> {
+    type Output = Cow<'a, str>;
+
+    /// Panics if the key isn't present.
+    fn index(&self, key: &str) -> &Self::Output {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .unwrap_or_else(|| panic!("param `{}` not found", key))
     }
 }
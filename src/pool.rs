@@ -0,0 +1,121 @@
+use crate::{Client, ClientResult, ClientStream};
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+type Idle<S> = Arc<Mutex<Vec<Client<S>>>>;
+
+/// Bounded pool of [`Client`]s, connections are created by the factory on
+/// demand and reused if they are keep alive.
+pub struct Pool<S, F>
+where
+    S: ClientStream,
+{
+    factory: F,
+    keep_alive: bool,
+    ping: bool,
+    idle: Idle<S>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<S, F, Fut> Pool<S, F>
+where
+    S: ClientStream,
+    F: Fn() -> Fut,
+    Fut: Future<Output = ClientResult<S>>,
+{
+    /// Construct a `Pool` holding at most `max_size` connections, which are
+    /// created by `factory`, such as a closure returning
+    /// `tokio::net::TcpStream` or `tokio::net::UnixStream`.
+    pub fn new(factory: F, keep_alive: bool, max_size: usize) -> Self {
+        Self {
+            factory,
+            keep_alive,
+            ping: false,
+            idle: Default::default(),
+            semaphore: Arc::new(Semaphore::new(max_size)),
+        }
+    }
+
+    /// Set whether an idle connection is checked by [`Client::ping`] before
+    /// it's reused, a stale one is discarded. Defaults to `false`, since it
+    /// adds a round trip to every `get`.
+    pub fn set_ping(mut self, ping: bool) -> Self {
+        self.ping = ping;
+        self
+    }
+
+    /// Get a client from the pool, waits if all the connections are in use.
+    ///
+    /// An idle connection is reused if there is one, otherwise a new one is
+    /// created by the factory. See [`Pool::set_ping`] to check the idle
+    /// connection first.
+    pub async fn get(&self) -> ClientResult<PooledClient<S>> {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+
+        let client = loop {
+            let idle = self.idle.lock().unwrap().pop();
+            match idle {
+                Some(mut client) => {
+                    if !self.ping || client.ping().await.is_ok() {
+                        break client;
+                    }
+                }
+                None => break Client::new((self.factory)().await?, self.keep_alive),
+            }
+        };
+
+        Ok(PooledClient {
+            client: Some(client),
+            idle: self.idle.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Count of the idle connections in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// Client borrowed from [`Pool`], returned to the pool on drop if the
+/// connection is keep alive and no exchange failed, otherwise it's discarded.
+pub struct PooledClient<S: ClientStream> {
+    client: Option<Client<S>>,
+    idle: Idle<S>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S: ClientStream> Deref for PooledClient<S> {
+    type Target = Client<S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl<S: ClientStream> DerefMut for PooledClient<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl<S: ClientStream> Drop for PooledClient<S> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if client.is_reusable() {
+                if let Ok(mut idle) = self.idle.lock() {
+                    idle.push(client);
+                }
+            }
+        }
+    }
+}
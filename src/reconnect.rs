@@ -0,0 +1,101 @@
+use crate::{
+    request::Request,
+    response::{RequestComplete, Response},
+    Client, ClientError, ClientResult, ClientStream,
+};
+use log::debug;
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// [`Client`] over a connection created by the factory on demand, and created
+/// again for the next request once it's lost or can't be reused.
+///
+/// A request whose connection is lost fails with
+/// `ClientError::RetryAdvised` rather than being retried, since the server
+/// may have processed it. Sending it again is up to the caller, who knows
+/// whether it's idempotent.
+pub struct ReconnectingClient<S, F>
+where
+    S: ClientStream,
+{
+    factory: F,
+    keep_alive: bool,
+    client: Option<Client<S>>,
+}
+
+impl<S, F, Fut> ReconnectingClient<S, F>
+where
+    S: ClientStream,
+    F: Fn() -> Fut,
+    Fut: Future<Output = ClientResult<S>>,
+{
+    /// Construct a `ReconnectingClient` whose connections are created by
+    /// `factory`, such as a closure returning `tokio::net::TcpStream` or
+    /// `tokio::net::UnixStream`. Nothing is connected until the first request.
+    pub fn new(factory: F, keep_alive: bool) -> Self {
+        Self {
+            factory,
+            keep_alive,
+            client: None,
+        }
+    }
+
+    /// Whether there is a connection which can be used for the next request.
+    pub fn is_connected(&self) -> bool {
+        self.client.as_ref().is_some_and(Client::is_reusable)
+    }
+
+    /// Send request and receive response from fastcgi server like
+    /// [`Client::execute`], connecting first if there is no reusable
+    /// connection.
+    ///
+    /// If the connection is lost, the request fails with
+    /// `ClientError::RetryAdvised`, and the next request reconnects. After any
+    /// other error leaving the connection in an unknown state, the next
+    /// request reconnects too.
+    pub async fn execute<I: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I>,
+        stdout: &mut (impl AsyncWrite + Unpin + ?Sized),
+        stderr: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> ClientResult<RequestComplete> {
+        let client = self.client().await?;
+        let rv = client.execute(request, stdout, stderr).await;
+        if !client.is_reusable() {
+            self.client = None;
+        }
+
+        match rv {
+            Err(err) if err.is_connection_lost() => {
+                debug!("Connection lost ({}), reconnect for the next request.", err);
+                Err(ClientError::RetryAdvised {
+                    source: Box::new(err),
+                })
+            }
+            rv => rv,
+        }
+    }
+
+    /// Send request and receive response from fastcgi server like
+    /// [`Client::execute_buffered`], see [`ReconnectingClient::execute`].
+    pub async fn execute_buffered<I: AsyncRead + Unpin>(
+        &mut self,
+        request: Request<'_, I>,
+    ) -> ClientResult<Response> {
+        let mut response = Response::default();
+        let complete = self
+            .execute(request, &mut response.stdout, &mut response.stderr)
+            .await?;
+        response.into_result(complete.app_status)
+    }
+
+    /// The client of the current connection, connecting by the factory if
+    /// there is none or it can't be reused.
+    async fn client(&mut self) -> ClientResult<&mut Client<S>> {
+        if !self.is_connected() {
+            debug!("Connect with the factory.");
+            self.client = Some(Client::new((self.factory)().await?, self.keep_alive));
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+}
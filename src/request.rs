@@ -1,5 +1,10 @@
-use crate::Params;
-use tokio::io::AsyncRead;
+use crate::{Client, ClientResult, ClientStream, Params, Response, Role};
+use std::io::Cursor;
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+
+/// Request owning its params, which can be `'static` and `Send`, to be
+/// stored in a queue or sent to another task, see [`Request::into_owned`].
+pub type OwnedRequest<I> = Request<'static, I>;
 
 /// fastcgi request.
 pub struct Request<'a, I: AsyncRead + Unpin> {
@@ -35,4 +40,98 @@ impl<'a, I: AsyncRead + Unpin> Request<'a, I> {
     pub fn stdin_mut(&mut self) -> &mut I {
         &mut self.stdin
     }
+
+    /// Convert the borrowed params to owned ones, see [`Params::into_owned`].
+    pub fn into_owned(self) -> OwnedRequest<I> {
+        Request::new(self.params.into_owned(), self.stdin)
+    }
+
+    /// Read the whole STDIN into memory and set `CONTENT_LENGTH` to its
+    /// length, for a body of unknown length, since the server relies on
+    /// `CONTENT_LENGTH` to read the body.
+    pub async fn buffer_content_length(mut self) -> io::Result<Request<'a, Cursor<Vec<u8>>>> {
+        let mut body = Vec::new();
+        self.stdin.read_to_end(&mut body).await?;
+        self.params.insert("CONTENT_LENGTH", body.len().to_string());
+        Ok(Request::new_with_body(self.params, body))
+    }
+}
+
+impl<'a> Request<'a, Cursor<Vec<u8>>> {
+    /// Request with the body in memory as STDIN.
+    pub fn new_with_body(params: Params<'a>, body: Vec<u8>) -> Self {
+        Self::new(params, Cursor::new(body))
+    }
+}
+
+impl<'a> Request<'a, io::Empty> {
+    /// Request without body, such as `GET`.
+    pub fn empty_body(params: Params<'a>) -> Self {
+        Self::new(params, io::empty())
+    }
+}
+
+/// Builder of a request of any role, sent by [`Client::execute_request`] or
+/// [`RequestBuilder::send`] with the records of its role: the authorizer
+/// receives no STDIN, and the filter receives the DATA stream after STDIN.
+pub struct RequestBuilder<'a> {
+    pub(crate) role: Role,
+    pub(crate) params: &'a Params<'a>,
+    pub(crate) stdin: Option<&'a mut (dyn AsyncRead + Unpin)>,
+    pub(crate) data: Option<&'a mut (dyn AsyncRead + Unpin)>,
+    pub(crate) keep_alive: Option<bool>,
+}
+
+impl<'a> RequestBuilder<'a> {
+    /// Request of the Responder role with params, and an empty STDIN.
+    pub fn new(params: &'a Params<'a>) -> Self {
+        Self {
+            role: Role::Responder,
+            params,
+            stdin: None,
+            data: None,
+            keep_alive: None,
+        }
+    }
+
+    /// Set the role of the request. Defaults to `Role::Responder`.
+    pub fn set_role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Set the STDIN of the request, empty if not set. It isn't sent for the
+    /// Authorizer role.
+    pub fn set_stdin(mut self, stdin: &'a mut (dyn AsyncRead + Unpin)) -> Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    /// Set the DATA stream of the request, the file to filter, empty if not
+    /// set. It's only sent for the Filter role.
+    pub fn set_data(mut self, data: &'a mut (dyn AsyncRead + Unpin)) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Override the keep alive of the client for this request, if the
+    /// connection isn't kept, it can't be used for another request after the
+    /// response.
+    pub fn set_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Send the request by [`Client::execute_request`], collecting STDOUT and
+    /// STDERR of the response into a [`Response`].
+    ///
+    /// A non-zero app status fails with `ClientError::RequestFailed`, with the
+    /// STDERR of the response, like [`Client::execute_buffered`].
+    pub async fn send<S: ClientStream>(self, client: &mut Client<S>) -> ClientResult<Response> {
+        let mut response = Response::default();
+        let complete = client
+            .execute_request(self, &mut response.stdout, &mut response.stderr)
+            .await?;
+        response.into_result(complete.app_status)
+    }
 }
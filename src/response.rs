@@ -1,5 +1,60 @@
+use crate::{meta::ProtocolStatus, ClientError, ClientResult};
+use bytes::Bytes;
+#[cfg(feature = "http")]
+use std::{borrow::Cow, collections::HashMap};
 use std::{fmt, fmt::Debug};
 
+/// Chunk of response received from fastcgi server.
+///
+/// The content of a record is the buffer it was read into, so it can be
+/// forwarded, or kept, without copying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseChunk {
+    /// Content of a `FCGI_STDOUT` record.
+    Stdout(Bytes),
+    /// Content of a `FCGI_STDERR` record.
+    Stderr(Bytes),
+    /// The request completed, the last chunk of response.
+    End {
+        app_status: u32,
+        protocol_status: ProtocolStatus,
+    },
+}
+
+/// Status of a completed fastcgi request, taken from the `FCGI_END_REQUEST` record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestComplete {
+    /// The application-level exit status, e.g. the exit code of the php script.
+    pub app_status: u32,
+    /// The protocol-level status, always `ProtocolStatus::RequestComplete` on success.
+    pub protocol_status: ProtocolStatus,
+}
+
+/// Bytes and records exchanged for a request, see
+/// [`Client::last_request_stats`](crate::Client::last_request_stats).
+///
+/// The bytes are the content of the records, without the headers and the
+/// padding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RequestStats {
+    /// Bytes of the STDIN written.
+    pub stdin_bytes: usize,
+    /// Bytes of the encoded params written.
+    pub params_bytes: usize,
+    /// Bytes of the STDOUT read.
+    pub stdout_bytes: usize,
+    /// Bytes of the STDERR read.
+    pub stderr_bytes: usize,
+    /// Whether a `FCGI_STDOUT` record was read, even an empty one, so an
+    /// empty body can be told apart from a response without STDOUT, such as
+    /// a PHP fatal error with only STDERR.
+    pub had_stdout: bool,
+    /// Whether a `FCGI_STDERR` record was read, even an empty one.
+    pub had_stderr: bool,
+    /// Records of the request read, including the `FCGI_END_REQUEST`.
+    pub records_read: usize,
+}
+
 /// Output of fastcgi request, contains STDOUT and STDERR.
 #[derive(Default, Clone)]
 pub struct Response {
@@ -7,6 +62,248 @@ pub struct Response {
     pub stderr: Vec<u8>,
 }
 
+impl Response {
+    /// The response of a request ended with `app_status`, or
+    /// `ClientError::RequestFailed` with the STDERR of the response if the
+    /// status isn't 0.
+    pub(crate) fn into_result(self, app_status: u32) -> ClientResult<Self> {
+        if app_status != 0 {
+            return Err(ClientError::new_request_failed(app_status, &self.stderr));
+        }
+        Ok(self)
+    }
+
+    /// Get the STDOUT of response, `None` if nothing was written.
+    pub fn get_stdout(&self) -> Option<Vec<u8>> {
+        if self.stdout.is_empty() {
+            None
+        } else {
+            Some(self.stdout.clone())
+        }
+    }
+
+    /// Get the STDERR of response, `None` if nothing was written.
+    pub fn get_stderr(&self) -> Option<Vec<u8>> {
+        if self.stderr.is_empty() {
+            None
+        } else {
+            Some(self.stderr.clone())
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Response {
+    /// Split the STDOUT into the CGI headers and the body, the status is taken
+    /// from the `Status` header, 200 if it's absent, and isn't kept in the
+    /// headers.
+    ///
+    /// The headers end at the first empty line. If there is none, or the first
+    /// line isn't a header, the whole STDOUT is the body. Repeated headers,
+    /// such as `Set-Cookie`, are all kept, and the obsolete continuation lines
+    /// starting with a space or a tab are unfolded into the header before.
+    pub fn parse_http(&self) -> ClientResult<(http::StatusCode, http::HeaderMap, &[u8])> {
+        let no_head = || (http::StatusCode::OK, http::HeaderMap::new(), &self.stdout[..]);
+        let (head, body) = match split_head(&self.stdout) {
+            Some(parts) => parts,
+            None => return Ok(no_head()),
+        };
+
+        let mut status = http::StatusCode::OK;
+        let mut headers = http::HeaderMap::new();
+        for (i, line) in header_lines(head).iter().enumerate() {
+            let line = &line[..];
+            match parse_header(line) {
+                Some((name, value)) if name == http::HeaderName::from_static("status") => {
+                    let code = value.split(|&b| b == b' ').next().unwrap_or_default();
+                    status = http::StatusCode::from_bytes(code)
+                        .map_err(|_| invalid_header(line))?;
+                }
+                Some((name, value)) => {
+                    let value =
+                        http::HeaderValue::from_bytes(value).map_err(|_| invalid_header(line))?;
+                    headers.append(name, value);
+                }
+                None if i == 0 => return Ok(no_head()),
+                None => return Err(invalid_header(line)),
+            }
+        }
+
+        Ok((status, headers, body))
+    }
+
+    /// Parse the STDOUT of an Authorizer response into an [`AuthResult`],
+    /// like [`Response::parse_http`].
+    pub fn parse_auth(&self) -> ClientResult<AuthResult> {
+        let (status, all_headers, body) = self.parse_http()?;
+
+        // The names of the variables keep their case, unlike in `HeaderMap`.
+        let head = &self.stdout[..self.stdout.len() - body.len()];
+        let mut variables = HashMap::new();
+        for line in header_lines(head) {
+            let colon = match line.iter().position(|&b| b == b':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            let name = &line[..colon];
+            if name.len() > VARIABLE_PREFIX.len()
+                && name[..VARIABLE_PREFIX.len()].eq_ignore_ascii_case(VARIABLE_PREFIX)
+            {
+                variables.insert(
+                    String::from_utf8_lossy(&name[VARIABLE_PREFIX.len()..]).into_owned(),
+                    String::from_utf8_lossy(trim(&line[colon + 1..])).into_owned(),
+                );
+            }
+        }
+
+        let mut headers = http::HeaderMap::new();
+        for (name, value) in &all_headers {
+            if !name.as_str().as_bytes().starts_with(b"variable-") {
+                headers.append(name, value.clone());
+            }
+        }
+
+        Ok(AuthResult {
+            status,
+            variables,
+            headers,
+            body: body.to_vec(),
+        })
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Response {
+    /// The body of the response parsed by [`Response::parse_http`], decoded
+    /// by its `Content-Encoding` header: the `gzip` and `deflate` bodies are
+    /// inflated, and the `identity` ones, or the ones without the header, are
+    /// returned as they are. The encodings listed in one header, or in
+    /// repeated ones, are decoded in the reverse order they were applied.
+    ///
+    /// An unsupported encoding, or an empty or corrupt body, fails with
+    /// `ClientError::InvalidBody`.
+    pub fn decoded_body(&self) -> ClientResult<Vec<u8>> {
+        let (_, headers, body) = self.parse_http()?;
+
+        let mut encodings = Vec::new();
+        for value in headers.get_all(http::header::CONTENT_ENCODING) {
+            let value = value.to_str().map_err(|_| ClientError::InvalidBody {
+                encoding: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                reason: "invalid header value",
+            })?;
+            encodings.extend(value.split(',').map(str::trim).filter(|encoding| !encoding.is_empty()));
+        }
+
+        let mut body = body.to_vec();
+        for encoding in encodings.iter().rev() {
+            let decoded = match &*encoding.to_ascii_lowercase() {
+                "identity" => continue,
+                "gzip" | "x-gzip" => crate::inflate::gunzip(&body),
+                "deflate" => crate::inflate::inflate_zlib(&body),
+                _ => Err("unsupported encoding"),
+            };
+            body = decoded.map_err(|reason| ClientError::InvalidBody {
+                encoding: (*encoding).to_owned(),
+                reason,
+            })?;
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(feature = "http")]
+const VARIABLE_PREFIX: &[u8] = b"Variable-";
+
+/// Response of a request of the Authorizer role, parsed from its STDOUT by
+/// [`Response::parse_auth`].
+///
+/// The request is authorized if the status is 200, then the variables are
+/// passed to the authorized request and the body, if any, is ignored by the
+/// server. Otherwise the response is sent to the client as is, so the body is
+/// kept.
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub struct AuthResult {
+    /// The status of the `Status` header, 200 if it's absent.
+    pub status: http::StatusCode,
+    /// The `Variable-*` headers, by their names without the prefix, such as
+    /// `AUTH_USER` for `Variable-AUTH_USER`.
+    pub variables: HashMap<String, String>,
+    /// The other headers.
+    pub headers: http::HeaderMap,
+    /// The body after the headers.
+    pub body: Vec<u8>,
+}
+
+#[cfg(feature = "http")]
+impl AuthResult {
+    /// Whether the request is authorized, the status is 200.
+    pub fn is_authorized(&self) -> bool {
+        self.status == http::StatusCode::OK
+    }
+}
+
+#[cfg(feature = "http")]
+fn parse_header(line: &[u8]) -> Option<(http::HeaderName, &[u8])> {
+    let colon = line.iter().position(|&b| b == b':')?;
+    let name = http::HeaderName::from_bytes(&line[..colon]).ok()?;
+    Some((name, trim(&line[colon + 1..])))
+}
+
+/// Lines of the head, the continuation lines are unfolded into the line
+/// before, joined by a space.
+#[cfg(feature = "http")]
+fn header_lines(head: &[u8]) -> Vec<Cow<'_, [u8]>> {
+    let mut lines: Vec<Cow<'_, [u8]>> = Vec::new();
+    for line in head.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match lines.last_mut() {
+            Some(last) if line.starts_with(b" ") || line.starts_with(b"\t") => {
+                let last = last.to_mut();
+                while last.last().is_some_and(u8::is_ascii_whitespace) {
+                    last.pop();
+                }
+                last.push(b' ');
+                last.extend_from_slice(trim(line));
+            }
+            _ => lines.push(Cow::Borrowed(line)),
+        }
+    }
+    lines
+}
+
+#[cfg(feature = "http")]
+fn invalid_header(line: &[u8]) -> ClientError {
+    ClientError::InvalidResponseHeader {
+        line: String::from_utf8_lossy(line).into_owned(),
+    }
+}
+
+/// Split at the first empty line, ended by `\r\n` or `\n`.
+#[cfg(feature = "http")]
+fn split_head(stdout: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut start = 0;
+    while let Some(end) = stdout[start..].iter().position(|&b| b == b'\n') {
+        let end = start + end;
+        let line = &stdout[start..end];
+        if line.is_empty() || line == b"\r" {
+            // The head without the line break before the empty line.
+            let head = stdout[..start].strip_suffix(b"\n").unwrap_or(&stdout[..start]);
+            let head = head.strip_suffix(b"\r").unwrap_or(head);
+            return Some((head, &stdout[end + 1..]));
+        }
+        start = end + 1;
+    }
+    None
+}
+
+#[cfg(feature = "http")]
+fn trim(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(value.len());
+    let end = value.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |end| end + 1);
+    &value[start..end]
+}
+
 impl Debug for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         Debug::fmt(r#"Output { stdout: "...", stderr: "..." }"#, f)
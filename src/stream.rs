@@ -0,0 +1,284 @@
+use crate::{ClientError, ClientResult};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    fmt::{self, Display},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+/// Address of fastcgi server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address<'a> {
+    /// Host and port of tcp socket, the host is a name, or an IPv4 or IPv6
+    /// address, which may be in brackets like `[::1]`. All the addresses the
+    /// name resolves to are tried in order until one connects.
+    Tcp(&'a str, u16),
+    /// Address of tcp socket.
+    TcpSocket(SocketAddr),
+    /// Path of unix socket.
+    #[cfg(unix)]
+    UnixSock(&'a str),
+    /// Name of unix socket in the abstract namespace, without the leading
+    /// NUL byte.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    UnixAbstract(&'a str),
+}
+
+/// Size of `sun_path` of `sockaddr_un`, including the NUL byte ending a path,
+/// or leading an abstract name.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SUN_PATH_LEN: usize = 108;
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const SUN_PATH_LEN: usize = 104;
+
+/// Check the path, or abstract name, fits in `sun_path`, since the os
+/// rejects it with an unclear error, or truncates it.
+#[cfg(unix)]
+fn check_unix_path(path: &str) -> io::Result<()> {
+    if path.len() >= SUN_PATH_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "unix socket path is {} bytes, longer than the limit of {} bytes",
+                path.len(),
+                SUN_PATH_LEN - 1
+            ),
+        ));
+    }
+    Ok(())
+}
+
+impl Display for Address<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Address::Tcp(host, port) if host.contains(':') && !host.starts_with('[') => {
+                write!(f, "[{}]:{}", host, port)
+            }
+            Address::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            Address::TcpSocket(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Address::UnixSock(path) => write!(f, "unix:{}", path),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Address::UnixAbstract(name) => write!(f, "unix:@{}", name),
+        }
+    }
+}
+
+/// Stream of the clients to fastcgi server, implemented for the tokio io
+/// streams, such as `TcpStream`, `UnixStream` or [`Stream`].
+///
+/// The streams of other runtimes, such as the `futures::io` streams of
+/// async-std, can be used through a wrapper implementing the tokio io traits.
+pub trait ClientStream: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin> ClientStream for S {}
+
+/// Options of the tcp sockets of [`Stream::connect_with_options`], they don't
+/// apply to unix sockets.
+///
+/// `TCP_NODELAY` is on by default instead of the os default, since a request
+/// is written as several small records before waiting for the response, which
+/// Nagle's algorithm would delay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    linger: Option<Duration>,
+}
+
+impl Default for TcpOptions {
+    /// `TCP_NODELAY` on, the os defaults for keepalive and linger.
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+            linger: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    pub fn set_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Enable `SO_KEEPALIVE`, with the idle time before the first keepalive
+    /// probe, `None` for the os default.
+    pub fn set_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Set `SO_LINGER`, `None` for the os default.
+    pub fn set_linger(mut self, linger: Option<Duration>) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        if self.linger.is_some() {
+            socket.set_linger(self.linger)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stream connected to fastcgi server, by tcp or unix socket.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Connect to fastcgi server of the address, with the default
+    /// [`TcpOptions`].
+    pub async fn connect(addr: Address<'_>) -> ClientResult<Self> {
+        Self::connect_with_options(addr, &TcpOptions::default()).await
+    }
+
+    /// Connect to fastcgi server of the address, setting the options of a tcp
+    /// socket.
+    pub async fn connect_with_options(addr: Address<'_>, options: &TcpOptions) -> ClientResult<Self> {
+        let connect_tcp = |rv: io::Result<TcpStream>| {
+            let stream = rv?;
+            options.apply(&stream)?;
+            Ok(Stream::Tcp(stream))
+        };
+        let rv = match addr {
+            Address::Tcp(host, port) => {
+                let host = host
+                    .strip_prefix('[')
+                    .and_then(|host| host.strip_suffix(']'))
+                    .unwrap_or(host);
+                connect_tcp(TcpStream::connect((host, port)).await)
+            }
+            Address::TcpSocket(addr) => connect_tcp(TcpStream::connect(addr).await),
+            #[cfg(unix)]
+            Address::UnixSock(path) => match check_unix_path(path) {
+                Ok(()) => UnixStream::connect(path).await.map(Stream::Unix),
+                Err(err) => Err(err),
+            },
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Address::UnixAbstract(name) => match check_unix_path(name) {
+                // Tokio connects to the abstract namespace for a leading NUL byte.
+                Ok(()) => UnixStream::connect(format!("\0{}", name)).await.map(Stream::Unix),
+                Err(err) => Err(err),
+            },
+        };
+        rv.map_err(|source| ClientError::Connect {
+            address: addr.to_string(),
+            source,
+        })
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Stream made of the read and write halves of a connection split beforehand,
+/// such as `OwnedReadHalf` and `OwnedWriteHalf` of `TcpStream::into_split`,
+/// see [`Client::from_halves`](crate::Client::from_halves).
+///
+/// The records are read from `read` and written to `write`.
+#[derive(Debug)]
+pub struct Halves<R, W> {
+    read: R,
+    write: W,
+}
+
+impl<R, W> Halves<R, W> {
+    pub fn new(read: R, write: W) -> Self {
+        Self { read, write }
+    }
+
+    /// Get back the read and write halves.
+    pub fn into_inner(self) -> (R, W) {
+        (self.read, self.write)
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: Unpin> AsyncRead for Halves<R, W> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().read).poll_read(cx, buf)
+    }
+}
+
+impl<R: Unpin, W: AsyncWrite + Unpin> AsyncWrite for Halves<R, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().write).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_shutdown(cx)
+    }
+}
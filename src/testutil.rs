@@ -0,0 +1,225 @@
+//! In-process fastcgi server for testing without php-fpm.
+//!
+//! ```rust
+//! use fastcgi_client::{testutil::MockServer, Client, Params, Request};
+//! use tokio::io;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let (stream, server) = MockServer::new()
+//!     .set_stdout("Content-type: text/plain\r\n\r\nhello")
+//!     .start();
+//! let mut client = Client::new(stream, false);
+//!
+//! let params = Params::default().set_request_method("GET");
+//! let response = client
+//!     .execute_buffered(Request::new(params, &mut io::empty()))
+//!     .await
+//!     .unwrap();
+//! assert!(response.stdout.ends_with(b"hello"));
+//!
+//! drop(client);
+//! let requests = server.await.unwrap().unwrap();
+//! assert_eq!(requests[0].param("REQUEST_METHOD"), Some("GET"));
+//! # }
+//! ```
+
+use crate::meta::{
+    decode_name_value_pairs, EndRequest, Header, ProtocolStatus, RequestType, Role, MAX_LENGTH, VERSION_1,
+};
+use std::collections::HashMap;
+use tokio::{
+    io::{self, AsyncWrite, AsyncWriteExt, DuplexStream},
+    task::JoinHandle,
+};
+
+/// A request received by [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub id: u16,
+    pub role: Role,
+    pub keep_alive: bool,
+    /// The params in the order they were received.
+    pub params: Vec<(String, String)>,
+    pub stdin: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl MockRequest {
+    /// Get the value of the param.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Fastcgi server over a `tokio::io::duplex` pair, replying every request with
+/// the same STDOUT, STDERR and end of request.
+///
+/// The response is sent when the STDIN of the request ends, or the DATA for
+/// the filter role. The server stops when the client closes the stream, or
+/// after a response to a request without keep alive.
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    app_status: u32,
+    protocol_status: ProtocolStatus,
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            app_status: 0,
+            protocol_status: ProtocolStatus::RequestComplete,
+        }
+    }
+
+    pub fn set_stdout(mut self, stdout: impl Into<Vec<u8>>) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+
+    pub fn set_stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    pub fn set_app_status(mut self, app_status: u32) -> Self {
+        self.app_status = app_status;
+        self
+    }
+
+    pub fn set_protocol_status(mut self, protocol_status: ProtocolStatus) -> Self {
+        self.protocol_status = protocol_status;
+        self
+    }
+
+    /// Spawn the server, returns the stream for the client, and the handle
+    /// resolving to the requests received once the server stops.
+    pub fn start(self) -> (DuplexStream, JoinHandle<io::Result<Vec<MockRequest>>>) {
+        let (client_stream, server_stream) = io::duplex(1024 * 1024);
+        let handle = tokio::spawn(self.serve(server_stream));
+        (client_stream, handle)
+    }
+
+    async fn serve(self, mut stream: DuplexStream) -> io::Result<Vec<MockRequest>> {
+        let mut in_progress: HashMap<u16, (MockRequest, Vec<u8>)> = HashMap::new();
+        let mut received = Vec::new();
+
+        loop {
+            let header = match Header::new_from_stream(&mut stream).await {
+                Ok(header) => header,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(received),
+                Err(err) => return Err(err),
+            };
+            let content = header.read_content_from_stream(&mut stream).await?;
+            let id = header.request_id;
+
+            if let RequestType::BeginRequest = header.r#type {
+                let role = content
+                    .get(..3)
+                    .and_then(|body| Role::from_u16(u16::from_be_bytes([body[0], body[1]])))
+                    .ok_or_else(|| invalid_data("invalid FCGI_BEGIN_REQUEST"))?;
+                let request = MockRequest {
+                    id,
+                    role,
+                    keep_alive: content[2] & 1 != 0,
+                    params: Vec::new(),
+                    stdin: Vec::new(),
+                    data: Vec::new(),
+                };
+                in_progress.insert(id, (request, Vec::new()));
+                continue;
+            }
+
+            // An abort may cross the response, other records of requests not
+            // in progress are unexpected, such as a second end of STDIN.
+            let (request, params) = match in_progress.get_mut(&id) {
+                Some(request) => request,
+                None if matches!(header.r#type, RequestType::AbortRequest) => continue,
+                None => return Err(invalid_data("record of request not in progress")),
+            };
+            let end = content.is_empty();
+            let respond = match header.r#type {
+                RequestType::Params if end => {
+                    request.params = decode_name_value_pairs(params)?;
+                    false
+                }
+                RequestType::Params => {
+                    params.extend(content);
+                    false
+                }
+                RequestType::Stdin => {
+                    request.stdin.extend(content);
+                    end && request.role != Role::Filter
+                }
+                RequestType::Data => {
+                    request.data.extend(content);
+                    end
+                }
+                RequestType::AbortRequest => true,
+                _ => false,
+            };
+            if !respond {
+                continue;
+            }
+
+            let (request, _) = in_progress.remove(&id).unwrap();
+            self.respond(&mut stream, id).await?;
+            let keep_alive = request.keep_alive;
+            received.push(request);
+            if !keep_alive {
+                return Ok(received);
+            }
+        }
+    }
+
+    async fn respond(&self, stream: &mut (impl AsyncWrite + Unpin), id: u16) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_stream(&mut buf, RequestType::Stdout, id, &self.stdout);
+        encode_stream(&mut buf, RequestType::Stderr, id, &self.stderr);
+
+        let content = EndRequest::new(self.app_status, self.protocol_status).encode();
+        encode_record(&mut buf, RequestType::EndRequest, id, &content);
+
+        stream.write_all(&buf).await?;
+        stream.flush().await
+    }
+}
+
+/// Encode the content in records, without the empty record ending the stream
+/// if there is no content.
+fn encode_stream(buf: &mut Vec<u8>, r#type: RequestType, id: u16, content: &[u8]) {
+    if content.is_empty() {
+        return;
+    }
+    for chunk in content.chunks(MAX_LENGTH) {
+        encode_record(buf, r#type.clone(), id, chunk);
+    }
+    encode_record(buf, r#type, id, &[]);
+}
+
+fn encode_record(buf: &mut Vec<u8>, r#type: RequestType, id: u16, content: &[u8]) {
+    let header = Header::new(r#type, id, content);
+    buf.extend_from_slice(&[VERSION_1, header.r#type as u8]);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&header.content_length.to_be_bytes());
+    buf.extend_from_slice(&[header.padding_length, 0]);
+    buf.extend_from_slice(content);
+    buf.resize(buf.len() + header.padding_length as usize, 0);
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
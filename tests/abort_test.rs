@@ -0,0 +1,32 @@
+use fastcgi_client::{Client, Params};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn abort() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        loop {
+            let (r#type, id, _) = common::read_record(&mut server_stream).await;
+            if r#type == 2 {
+                let mut reply = common::record(6, id, b"partial");
+                reply.extend(common::end_request(id, 0, 0));
+                server_stream.write_all(&reply).await.unwrap();
+                return server_stream;
+            }
+        }
+    });
+
+    let id = client
+        .handle_new_request(&Params::default(), &mut io::empty())
+        .await
+        .unwrap();
+    client.abort(id).await.unwrap();
+
+    let _server_stream = server.await.unwrap();
+}
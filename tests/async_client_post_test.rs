@@ -40,11 +40,11 @@ async fn test() {
         .set_content_length(&len);
 
     let _ = client
-        .execute(Request::new(params.clone(), &mut &body[..]))
+        .execute_buffered(Request::new(params.clone(), &mut &body[..]))
         .await
         .unwrap();
     let output = client
-        .execute(Request::new(params, &mut &body[..]))
+        .execute_buffered(Request::new(params, &mut &body[..]))
         .await
         .unwrap();
 
@@ -95,7 +95,7 @@ async fn post_big_body() {
 
     let output = timeout(
         Duration::from_secs(3),
-        client.execute(Request::new(params.clone(), &mut &body[..])),
+        client.execute_buffered(Request::new(params.clone(), &mut &body[..])),
     )
     .await
     .unwrap()
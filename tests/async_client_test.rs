@@ -41,7 +41,7 @@ async fn test_client<S: AsyncRead + AsyncWrite + Send + Sync + Unpin>(client: &m
         .set_content_length("0");
 
     let output = client
-        .execute(Request::new(params, &mut io::empty()))
+        .execute_buffered(Request::new(params, &mut io::empty()))
         .await
         .unwrap();
 
@@ -0,0 +1,74 @@
+use fastcgi_client::{BackoffPolicy, Client, ClientError, Params, Request};
+use std::time::Duration;
+use tokio::io::{self, AsyncWriteExt, DuplexStream};
+
+mod common;
+
+/// Reply `FCGI_OVERLOADED` to the first `overloaded` requests, then succeed.
+async fn serve(mut stream: DuplexStream, overloaded: usize, total: usize) -> DuplexStream {
+    let mut answered = Vec::new();
+    while answered.len() < total {
+        let (r#type, id, content) = common::read_record(&mut stream).await;
+        if r#type != 5 || !content.is_empty() || answered.contains(&id) {
+            continue;
+        }
+        answered.push(id);
+
+        let reply = if answered.len() <= overloaded {
+            common::end_request(id, 0, 2)
+        } else {
+            let mut reply = common::record(6, id, b"hello");
+            reply.extend(common::end_request(id, 0, 0));
+            reply
+        };
+        stream.write_all(&reply).await.unwrap();
+    }
+    stream
+}
+
+fn policy() -> BackoffPolicy {
+    BackoffPolicy::default()
+        .set_max_retries(2)
+        .set_initial_delay(Duration::from_millis(1))
+        .set_max_delay(Duration::from_millis(5))
+}
+
+#[tokio::test]
+async fn retry_when_overloaded() {
+    common::setup();
+
+    let (client_stream, server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+    let server = tokio::spawn(serve(server_stream, 2, 3));
+
+    let request = Request::new(Params::default(), &b""[..]);
+    let mut stdout = Vec::new();
+    client
+        .execute_with_backoff(&request, &mut stdout, &mut io::sink(), &policy())
+        .await
+        .unwrap();
+    assert_eq!(stdout, b"hello");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn still_overloaded() {
+    common::setup();
+
+    let (client_stream, server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+    let server = tokio::spawn(serve(server_stream, 3, 3));
+
+    let request = Request::new(Params::default(), &b""[..]);
+    let result = client
+        .execute_with_backoff(&request, &mut io::sink(), &mut io::sink(), &policy())
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Overloaded { retries: 2 })
+    ));
+    assert!(client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
@@ -0,0 +1,210 @@
+use fastcgi_client::{Client, ClientError, Params, Request, RequestIdGenerator};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    time::{sleep, timeout},
+};
+
+mod common;
+
+#[tokio::test]
+async fn cancelled_while_reading_response() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let cancelled = common::read_request(&mut server_stream).await;
+
+        // Reply nothing until the abort.
+        loop {
+            let (r#type, id, _) = common::read_record(&mut server_stream).await;
+            if r#type == 2 {
+                assert_eq!(id, cancelled);
+                let reply = common::end_request(id, 0, 0);
+                server_stream.write_all(&reply).await.unwrap();
+                break;
+            }
+        }
+
+        let id = loop {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type == 5 && content.is_empty() && id != cancelled {
+                break id;
+            }
+        };
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let result = timeout(
+        Duration::from_millis(50),
+        client.execute_buffered(Request::new(Params::default(), &mut io::empty())),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!client.is_reusable());
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(result, Err(ClientError::Busy { id: 1 })));
+
+    client.abort(1).await.unwrap();
+    assert!(client.is_reusable());
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_while_writing_request() {
+    common::setup();
+
+    // The server never reads, so the writes are blocked once the buffer is full.
+    let (client_stream, _server_stream) = io::duplex(64);
+    let mut client = Client::new(client_stream, true);
+
+    let result = timeout(
+        Duration::from_millis(50),
+        client.execute_buffered(Request::new(Params::default(), &mut &[0u8; 1024][..])),
+    )
+    .await;
+    assert!(result.is_err());
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(result, Err(ClientError::Busy { id: 1 })));
+    assert!(matches!(
+        client.abort(1).await,
+        Err(ClientError::Busy { id: 1 })
+    ));
+}
+
+#[tokio::test]
+async fn abort_after_cancel_releases_once() {
+    common::setup();
+
+    let generator = Arc::new(RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(1));
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::with_id_generator(client_stream, true, generator.clone());
+
+    let server = tokio::spawn(async move {
+        common::read_request(&mut server_stream).await;
+        // End the requests once aborted.
+        for _ in 0..2 {
+            let (r#type, id, _) = common::read_record(&mut server_stream).await;
+            assert_eq!(r#type, 2);
+            let reply = common::end_request(id, 0, 0);
+            server_stream.write_all(&reply).await.unwrap();
+        }
+    });
+
+    let result = timeout(
+        Duration::from_millis(50),
+        client.execute_buffered(Request::new(Params::default(), &mut io::empty())),
+    )
+    .await;
+    assert!(result.is_err());
+
+    // The id released by the cancelled request is allocated to another one,
+    // which the abort of the cancelled request doesn't release.
+    let other = generator.alloc().await.unwrap();
+    assert_eq!(other, 1);
+    client.abort(1).await.unwrap();
+    assert_eq!(generator.in_use(), 1);
+    assert!(generator.alloc().await.is_err());
+
+    // An id allocated for the caller is released by the abort.
+    generator.release(other);
+    let id = client.generate_id().await.unwrap();
+    client.abort(id).await.unwrap();
+    assert_eq!(generator.in_use(), 0);
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_on_cancel() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_abort_on_cancel(true);
+
+    let server = tokio::spawn(async move {
+        let cancelled = common::read_request(&mut server_stream).await;
+        let reply = common::record(6, cancelled, b"partial");
+        server_stream.write_all(&reply).await.unwrap();
+
+        // Reply the rest once aborted.
+        let (r#type, id, _) = common::read_record(&mut server_stream).await;
+        assert_eq!((r#type, id), (2, cancelled));
+        let mut reply = common::record(6, id, b"rest");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let mut body = io::empty();
+    tokio::select! {
+        _ = client.execute_buffered(Request::new(Params::default(), &mut body)) => {
+            panic!("response not ended");
+        }
+        _ = sleep(Duration::from_millis(50)) => {}
+    }
+    assert!(client.is_reusable());
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert!(client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_on_cancel_partial_record() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_abort_on_cancel(true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        // Only the start of the content of the record.
+        let reply = common::record(6, id, b"partial");
+        server_stream.write_all(&reply[..10]).await.unwrap();
+        server_stream
+    });
+
+    let result = timeout(
+        Duration::from_millis(50),
+        client.execute_buffered(Request::new(Params::default(), &mut io::empty())),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!client.is_reusable());
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(result, Err(ClientError::Busy { id: 1 })));
+
+    let _server_stream = server.await.unwrap();
+}
@@ -0,0 +1,159 @@
+#![cfg(feature = "codec")]
+
+use bytes::{Bytes, BytesMut};
+use fastcgi_client::{
+    codec::{FastCgiCodec, Record},
+    ProtocolStatus, Role,
+};
+
+mod common;
+
+#[test]
+fn round_trip() {
+    let records = vec![
+        Record::BeginRequest {
+            id: 1,
+            role: Role::Filter,
+            keep_alive: true,
+        },
+        Record::AbortRequest { id: 1 },
+        Record::EndRequest {
+            id: 1,
+            app_status: 255,
+            protocol_status: ProtocolStatus::Overloaded,
+        },
+        Record::Params {
+            id: 1,
+            content: Bytes::from_static(b"\x0e\x03REQUEST_METHODGET"),
+        },
+        Record::Stdin {
+            id: 2,
+            content: Bytes::new(),
+        },
+        Record::Stdout {
+            id: 2,
+            content: Bytes::from_static(b"hello"),
+        },
+        Record::Stderr {
+            id: 2,
+            content: Bytes::from_static(b"warning"),
+        },
+        Record::Data {
+            id: 2,
+            content: Bytes::from_static(b"data"),
+        },
+        Record::GetValues {
+            content: Bytes::from_static(b"\x0e\x00FCGI_MAX_CONNS"),
+        },
+        Record::GetValuesResult {
+            content: Bytes::from_static(b"\x0e\x02FCGI_MAX_CONNS10"),
+        },
+        Record::UnknownType { r#type: 42 },
+    ];
+
+    let mut codec = FastCgiCodec::new();
+    let mut buf = BytesMut::new();
+    for record in records.clone() {
+        codec.encode(record, &mut buf).unwrap();
+    }
+
+    let mut decoded = Vec::new();
+    while let Some(record) = codec.decode(&mut buf).unwrap() {
+        decoded.push(record);
+    }
+    assert_eq!(decoded, records);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn padding() {
+    let mut codec = FastCgiCodec::new();
+    let mut buf = BytesMut::new();
+    codec
+        .encode(
+            Record::Stdout {
+                id: 1,
+                content: Bytes::from_static(b"hello"),
+            },
+            &mut buf,
+        )
+        .unwrap();
+    assert_eq!(&buf[..13], &common::padded_record(6, 1, b"hello", 3)[..13]);
+    assert_eq!(buf.len(), 16);
+    assert_eq!(&buf[13..], &[0, 0, 0]);
+}
+
+#[test]
+fn large_content() {
+    let mut codec = FastCgiCodec::new();
+    for length in [32767, 32768, 32769, 0xffff] {
+        let record = Record::Stdout {
+            id: 1,
+            content: vec![b'a'; length].into(),
+        };
+        let mut buf = BytesMut::new();
+        codec.encode(record.clone(), &mut buf).unwrap();
+        assert_eq!(buf.len() % 8, 0);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(record));
+        assert!(buf.is_empty());
+    }
+}
+
+#[test]
+fn partial_reads() {
+    let mut input = common::padded_record(6, 1, b"hello", 3);
+    input.extend(common::end_request(1, 0, 0));
+
+    let mut codec = FastCgiCodec::new();
+    let mut buf = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in input {
+        buf.extend_from_slice(&[byte]);
+        if let Some(record) = codec.decode(&mut buf).unwrap() {
+            decoded.push(record);
+        }
+    }
+    assert_eq!(
+        decoded,
+        vec![
+            Record::Stdout {
+                id: 1,
+                content: Bytes::from_static(b"hello"),
+            },
+            Record::EndRequest {
+                id: 1,
+                app_status: 0,
+                protocol_status: ProtocolStatus::RequestComplete,
+            },
+        ]
+    );
+}
+
+#[test]
+fn unknown_types() {
+    let mut codec = FastCgiCodec::new();
+
+    // The type rejected by the server is kept as is.
+    let mut buf = BytesMut::from(&common::record(11, 0, &[12, 0, 0, 0, 0, 0, 0, 0])[..]);
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(Record::UnknownType { r#type: 12 })
+    );
+
+    // A record of a type unknown to the protocol.
+    let mut buf = BytesMut::from(&common::record(12, 1, b"")[..]);
+    assert!(codec.decode(&mut buf).is_err());
+}
+
+#[test]
+fn too_large() {
+    let mut codec = FastCgiCodec::new();
+    let result = codec.encode(
+        Record::Stdin {
+            id: 1,
+            content: vec![0; 0x10000].into(),
+        },
+        &mut BytesMut::new(),
+    );
+    assert!(result.is_err());
+}
@@ -1,4 +1,7 @@
+#![allow(dead_code)]
+
 use std::sync::Once;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 static START: Once = Once::new();
 
@@ -8,3 +11,52 @@ pub fn setup() {
         env_logger::init();
     });
 }
+
+/// Encode a fastcgi record without padding.
+pub fn record(r#type: u8, id: u16, content: &[u8]) -> Vec<u8> {
+    let mut buf = vec![1, r#type];
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(content);
+    buf
+}
+
+/// Encode a `FCGI_END_REQUEST` record.
+pub fn end_request(id: u16, app_status: u32, protocol_status: u8) -> Vec<u8> {
+    let mut content = app_status.to_be_bytes().to_vec();
+    content.extend_from_slice(&[protocol_status, 0, 0, 0]);
+    record(3, id, &content)
+}
+
+/// Read a fastcgi record from the stream, returns the type, request id and
+/// content without padding.
+pub async fn read_record(stream: &mut (impl AsyncRead + Unpin)) -> (u8, u16, Vec<u8>) {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await.unwrap();
+    let id = u16::from_be_bytes([header[2], header[3]]);
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0; content_length + header[6] as usize];
+    stream.read_exact(&mut content).await.unwrap();
+    content.truncate(content_length);
+    (header[1], id, content)
+}
+
+/// Read the records of a request until the end of its STDIN, returns the
+/// request id.
+pub async fn read_request(stream: &mut (impl AsyncRead + Unpin)) -> u16 {
+    loop {
+        let (r#type, id, content) = read_record(stream).await;
+        if r#type == 5 && content.is_empty() {
+            return id;
+        }
+    }
+}
+
+/// Encode a fastcgi record with `padding_length` bytes of padding.
+pub fn padded_record(r#type: u8, id: u16, content: &[u8], padding_length: u8) -> Vec<u8> {
+    let mut buf = record(r#type, id, content);
+    buf[6] = padding_length;
+    buf.extend(vec![0xff; padding_length as usize]);
+    buf
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "compression")]
+
+use fastcgi_client::{ClientError, Response};
+
+/// `text()` compressed by gzip, with the file name `index.html`.
+const GZIP: &[u8] = &[
+    0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x69, 0x6e, 0x64, 0x65, 0x78, 0x2e,
+    0x68, 0x74, 0x6d, 0x6c, 0x00, 0x5d, 0xc9, 0x4b, 0x0e, 0x80, 0x20, 0x10, 0x04, 0xd1, 0xbd, 0xa7,
+    0xe8, 0x1b, 0xf8, 0xd7, 0xa4, 0x43, 0xbc, 0x8b, 0xc0, 0x28, 0x28, 0xec, 0xbc, 0x7f, 0x44, 0x8d,
+    0x9b, 0x59, 0xd6, 0x2b, 0x93, 0xe2, 0x12, 0x2f, 0xc9, 0x68, 0x88, 0xd5, 0xd4, 0xa5, 0x2a, 0xf3,
+    0x53, 0x4b, 0x58, 0xa7, 0xac, 0x23, 0x9c, 0x17, 0x85, 0x3d, 0xe1, 0x65, 0xdb, 0x95, 0x0e, 0x44,
+    0xc1, 0x10, 0x15, 0x8f, 0xc4, 0xa3, 0xc7, 0xa9, 0x7c, 0x22, 0x5e, 0x4e, 0x59, 0x8d, 0x99, 0x08,
+    0x1f, 0xdd, 0xc5, 0xb9, 0x7e, 0x59, 0xad, 0x00, 0x00, 0x00,
+];
+
+/// The raw DEFLATE data of `GZIP`, a block of dynamic Huffman codes.
+fn raw_deflate() -> &'static [u8] {
+    &GZIP[21..GZIP.len() - 8]
+}
+
+/// `text()` compressed by zlib, the same data as `GZIP`.
+fn zlib() -> Vec<u8> {
+    let mut zlib = vec![0x78, 0xda];
+    zlib.extend_from_slice(raw_deflate());
+    zlib.extend_from_slice(&[0x0c, 0x29, 0x35, 0x49]);
+    zlib
+}
+
+fn text() -> Vec<u8> {
+    let alphabet = "abcdefghijklmnopqrstuvwxyz";
+    (0..8)
+        .map(|i| {
+            format!(
+                "<li>item {}: {}</li>\n",
+                i,
+                &alphabet[i % 26..i % 26 + i % 7 + 1]
+            )
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn encoded_response(encoding: &str, body: &[u8]) -> Response {
+    let mut stdout = format!(
+        "Content-Type: text/html\r\nContent-Encoding: {}\r\n\r\n",
+        encoding
+    )
+    .into_bytes();
+    stdout.extend_from_slice(body);
+    Response {
+        stdout,
+        stderr: Vec::new(),
+    }
+}
+
+#[test]
+fn gzip() {
+    assert_eq!(
+        encoded_response("gzip", GZIP).decoded_body().unwrap(),
+        text()
+    );
+    assert_eq!(
+        encoded_response("X-Gzip", GZIP).decoded_body().unwrap(),
+        text()
+    );
+
+    // Concatenated members.
+    let twice = [GZIP, GZIP].concat();
+    assert_eq!(
+        encoded_response("gzip", &twice).decoded_body().unwrap(),
+        [text(), text()].concat()
+    );
+}
+
+#[test]
+fn deflate() {
+    assert_eq!(
+        encoded_response("deflate", &zlib()).decoded_body().unwrap(),
+        text()
+    );
+    // Without the zlib header.
+    assert_eq!(
+        encoded_response("deflate", raw_deflate())
+            .decoded_body()
+            .unwrap(),
+        text()
+    );
+
+    // Blocks of fixed Huffman codes and stored blocks.
+    let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+    assert_eq!(
+        encoded_response("deflate", &fixed).decoded_body().unwrap(),
+        b"hello hello hello"
+    );
+    let mut stored = vec![0x01, 0x11, 0x00, 0xee, 0xff];
+    stored.extend_from_slice(b"hello hello hello");
+    assert_eq!(
+        encoded_response("deflate", &stored).decoded_body().unwrap(),
+        b"hello hello hello"
+    );
+}
+
+#[test]
+fn identity() {
+    let response = Response {
+        stdout: b"Content-Type: text/plain\r\n\r\nhello".to_vec(),
+        stderr: Vec::new(),
+    };
+    assert_eq!(response.decoded_body().unwrap(), b"hello");
+    assert_eq!(
+        encoded_response("identity", b"hello")
+            .decoded_body()
+            .unwrap(),
+        b"hello"
+    );
+}
+
+#[test]
+fn encodings_in_order() {
+    // Compressed by deflate, then by identity, decoded in the reverse order.
+    let response = encoded_response("deflate, identity", &zlib());
+    assert_eq!(response.decoded_body().unwrap(), text());
+}
+
+#[test]
+fn invalid_body() {
+    let invalid =
+        |encoding: &str, body: &[u8]| match encoded_response(encoding, body).decoded_body() {
+            Err(ClientError::InvalidBody { encoding, reason }) => (encoding, reason),
+            rv => panic!("unexpected {:?}", rv),
+        };
+
+    assert_eq!(invalid("gzip", b""), ("gzip".to_owned(), "empty body"));
+    assert_eq!(
+        invalid("deflate", b""),
+        ("deflate".to_owned(), "empty body")
+    );
+    assert_eq!(
+        invalid("br", b"hello"),
+        ("br".to_owned(), "unsupported encoding")
+    );
+    assert_eq!(
+        invalid("gzip", b"hello world, not gzip"),
+        ("gzip".to_owned(), "invalid gzip header")
+    );
+    assert_eq!(
+        invalid("gzip", &GZIP[..60]),
+        ("gzip".to_owned(), "unexpected end of data")
+    );
+
+    let mut corrupt = GZIP.to_vec();
+    let crc = corrupt.len() - 8;
+    corrupt[crc] ^= 0xff;
+    assert_eq!(
+        invalid("gzip", &corrupt),
+        ("gzip".to_owned(), "gzip checksum mismatch")
+    );
+
+    let mut corrupt = zlib();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xff;
+    assert_eq!(
+        invalid("deflate", &corrupt),
+        ("deflate".to_owned(), "zlib checksum mismatch")
+    );
+
+    // Random bytes are never decoded to a panic.
+    let mut seed = 0x2545_f491_u32;
+    for _ in 0..1000 {
+        let body: Vec<u8> = (0..64)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let _ = encoded_response("deflate", &body).decoded_body();
+    }
+}
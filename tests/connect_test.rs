@@ -0,0 +1,286 @@
+use fastcgi_client::{
+    Address, Client, ClientBuilder, ClientError, Params, Request, Stream, TcpOptions,
+};
+use socket2::SockRef;
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{self, AsyncWriteExt, DuplexStream},
+    net::{TcpListener, TcpStream},
+};
+
+mod common;
+
+#[tokio::test]
+async fn connect_tls() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+    // Stand-in for a TLS handshake wrapping the connected stream.
+    let client: Client<TcpStream> = Client::connect_tls(addr, |stream| async { Ok(stream) }, true)
+        .await
+        .unwrap();
+    drop(client);
+    server.await.unwrap();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let result: Result<Client<DuplexStream>, _> = Client::connect_tls(
+        addr,
+        |_| async { Err(io::Error::new(io::ErrorKind::InvalidData, "bad certificate")) },
+        true,
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn connect_tcp() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+    let client = Client::connect(Address::Tcp("127.0.0.1", port), true)
+        .await
+        .unwrap();
+    drop(client);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn from_halves() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let id = common::read_request(&mut stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        stream.write_all(&reply).await.unwrap();
+        stream
+    });
+
+    let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+    let mut client = Client::from_halves(read, write, true);
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert!(client.is_reusable());
+
+    let _stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_ipv4_and_ipv6() {
+    common::setup();
+
+    for host in ["127.0.0.1", "::1"].iter() {
+        let listener = match TcpListener::bind((*host, 0)).await {
+            Ok(listener) => listener,
+            // No IPv6 loopback on the host.
+            Err(_) if host.contains(':') => continue,
+            Err(err) => panic!("{}", err),
+        };
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            for _ in 0..3 {
+                listener.accept().await.unwrap();
+            }
+        });
+
+        let bracketed = format!("[{}]", host);
+        let addrs = vec![
+            Address::Tcp(host, addr.port()),
+            Address::Tcp(&bracketed, addr.port()),
+            Address::TcpSocket(addr),
+        ];
+        for addr in addrs {
+            let client = Client::connect(addr, true).await.unwrap();
+            drop(client);
+        }
+        server.await.unwrap();
+    }
+}
+
+#[test]
+fn address_display() {
+    assert_eq!(Address::Tcp("127.0.0.1", 9000).to_string(), "127.0.0.1:9000");
+    assert_eq!(Address::Tcp("::1", 9000).to_string(), "[::1]:9000");
+    assert_eq!(Address::Tcp("[::1]", 9000).to_string(), "[::1]:9000");
+    let addr = "[::1]:9000".parse().unwrap();
+    assert_eq!(Address::TcpSocket(addr).to_string(), "[::1]:9000");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_unix() {
+    common::setup();
+
+    let path = std::env::temp_dir().join(format!("fastcgi-client-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+    let client = Client::connect(Address::UnixSock(path.to_str().unwrap()), true)
+        .await
+        .unwrap();
+    drop(client);
+    server.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[tokio::test]
+async fn connect_unix_abstract() {
+    common::setup();
+
+    let name = format!("fastcgi-client-{}", std::process::id());
+    let listener = UnixListener::bind(format!("\0{}", name)).unwrap();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+    let client = Client::connect(Address::UnixAbstract(&name), true)
+        .await
+        .unwrap();
+    drop(client);
+    server.await.unwrap();
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[tokio::test]
+async fn expect_peer_cred() {
+    common::setup();
+
+    // The credentials of the test process, which is the server too.
+    let (stream, _) = tokio::net::UnixStream::pair().unwrap();
+    let cred = stream.peer_cred().unwrap();
+    let name = format!("fastcgi-client-cred-{}", std::process::id());
+    let listener = UnixListener::bind(format!("\0{}", name)).unwrap();
+    let server = tokio::spawn(async move {
+        for _ in 0..2 {
+            listener.accept().await.unwrap();
+        }
+    });
+
+    let builder = ClientBuilder::new().set_keep_alive(true);
+    builder
+        .clone()
+        .expect_peer_cred(cred.uid(), cred.gid())
+        .connect(Address::UnixAbstract(&name))
+        .await
+        .unwrap();
+
+    let result = builder
+        .expect_peer_cred(cred.uid() + 1, cred.gid())
+        .connect(Address::UnixAbstract(&name))
+        .await;
+    match result {
+        Err(ClientError::PeerCredMismatch { uid, gid }) => {
+            assert_eq!((uid, gid), (cred.uid(), cred.gid()));
+        }
+        _ => panic!("expect peer cred mismatch"),
+    }
+    server.await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_unix_path_too_long() {
+    common::setup();
+
+    let path = format!("/tmp/{}.sock", "x".repeat(200));
+    let result = Client::connect(Address::UnixSock(&path), true).await;
+    match result {
+        Err(ClientError::Connect { address, source }) => {
+            assert_eq!(address, format!("unix:{}", path));
+            assert_eq!(source.kind(), io::ErrorKind::InvalidInput);
+        }
+        _ => panic!("expect connect error"),
+    }
+}
+
+#[tokio::test]
+async fn one_shot_request() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let id = common::read_request(&mut stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        stream.write_all(&reply).await.unwrap();
+    });
+
+    let params = Params::default().set_content_length("4");
+    let response = fastcgi_client::request(Address::Tcp("127.0.0.1", port), params, &b"body"[..])
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_failed() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    let result = Client::connect(Address::Tcp("127.0.0.1", port), true).await;
+    match result {
+        Err(ClientError::Connect { address, .. }) => {
+            assert_eq!(address, format!("127.0.0.1:{}", port))
+        }
+        _ => panic!("expect connect error"),
+    }
+}
+
+#[tokio::test]
+async fn tcp_options() {
+    common::setup();
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        for _ in 0..2 {
+            listener.accept().await.unwrap();
+        }
+    });
+
+    match Stream::connect(Address::TcpSocket(addr)).await.unwrap() {
+        Stream::Tcp(stream) => assert!(stream.nodelay().unwrap()),
+        stream => panic!("unexpected stream: {:?}", stream),
+    }
+
+    let options = TcpOptions::default()
+        .set_nodelay(false)
+        .set_keepalive(Some(Duration::from_secs(60)))
+        .set_linger(Some(Duration::from_secs(1)));
+    match Stream::connect_with_options(Address::TcpSocket(addr), &options)
+        .await
+        .unwrap()
+    {
+        Stream::Tcp(stream) => {
+            assert!(!stream.nodelay().unwrap());
+            let socket = SockRef::from(&stream);
+            assert!(socket.keepalive().unwrap());
+            assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(1)));
+        }
+        stream => panic!("unexpected stream: {:?}", stream),
+    }
+
+    server.await.unwrap();
+}
@@ -0,0 +1,142 @@
+use fastcgi_client::{multiplex::MultiplexClient, Client, ClientError, Params, Phase, Request};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn closed_mid_header() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let reply = common::record(6, id, b"hello");
+        server_stream.write_all(&reply[..3]).await.unwrap();
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ConnectionClosed {
+            id: 1,
+            during: Phase::Response
+        })
+    ));
+    assert!(!client.is_reusable());
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn closed_before_request() {
+    common::setup();
+
+    let (client_stream, server_stream) = io::duplex(1024);
+    drop(server_stream);
+    let mut client = Client::new(client_stream, true);
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ConnectionClosed {
+            id: 1,
+            during: Phase::Request
+        })
+    ));
+}
+
+#[tokio::test]
+async fn closed_mid_end_request() {
+    common::setup();
+
+    // Right after the header, and in the middle of the content.
+    for &len in [8, 12].iter() {
+        let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+        let mut client = Client::new(client_stream, true);
+
+        let server = tokio::spawn(async move {
+            let id = common::read_request(&mut server_stream).await;
+            let reply = common::end_request(id, 0, 0);
+            server_stream.write_all(&reply[..len]).await.unwrap();
+        });
+
+        let result = client
+            .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(ClientError::ConnectionClosed {
+                    id: 1,
+                    during: Phase::Response
+                })
+            ),
+            "truncated at {}: {:?}",
+            len,
+            result
+        );
+        assert!(!client.is_reusable());
+
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn closed_mid_end_request_multiplex() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let reply = common::end_request(id, 0, 0);
+        server_stream.write_all(&reply[..8]).await.unwrap();
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ConnectionClosed {
+            during: Phase::Response,
+            ..
+        })
+    ));
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn end_request_too_short() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    // A whole record whose content is shorter than FCGI_END_REQUEST.
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let reply = common::record(3, id, &[0, 0, 0, 0, 0]);
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    match result {
+        Err(ClientError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+        result => panic!("expect invalid data: {:?}", result),
+    }
+    assert!(!client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
@@ -0,0 +1,183 @@
+use fastcgi_client::{ClientError, Phase, RequestType, Role};
+use std::{error::Error, io};
+
+#[test]
+fn display() {
+    let cases = vec![
+        (
+            ClientError::Io(io::Error::new(io::ErrorKind::InvalidData, "oops")),
+            "oops",
+        ),
+        (
+            ClientError::Connect {
+                address: "127.0.0.1:9000".to_owned(),
+                source: io::Error::new(io::ErrorKind::ConnectionRefused, "refused"),
+            },
+            "Connect to `127.0.0.1:9000` failed: refused",
+        ),
+        (
+            ClientError::RequestIdNotFound { id: 1 },
+            "Request id `1` not found",
+        ),
+        (
+            ClientError::ResponseNotFound { id: 1 },
+            "Response not found of request id `1`",
+        ),
+        (
+            ClientError::UnexpectedRecord {
+                id: 2,
+                request_type: RequestType::Stdout,
+            },
+            "Unexpected record of type `Stdout` of request id `2` not in progress",
+        ),
+        (
+            ClientError::UnsupportedVersion { version: 2 },
+            "Unsupported fastcgi version `2`",
+        ),
+        (
+            ClientError::UnknownRequestType {
+                id: 1,
+                request_type: RequestType::GetValuesResult,
+            },
+            "Unexpected record of type `GetValuesResult` (10) for request id `1`",
+        ),
+        (
+            ClientError::UnknownTypeResponse { requested: 9 },
+            "Management record of type `9` not supported by server",
+        ),
+        (
+            ClientError::EndRequestCantMpxConn { app_status: 1 },
+            "This app can't multiplex [CantMpxConn]; AppStatus: 1",
+        ),
+        (
+            ClientError::EndRequestOverloaded { app_status: 1 },
+            "New request rejected; too busy [OVERLOADED]; AppStatus: 1",
+        ),
+        (
+            ClientError::EndRequestUnknownRole { app_status: 1 },
+            "Role value not known [UnknownRole]; AppStatus: 1",
+        ),
+        (
+            ClientError::ReservedRequestId,
+            "Request id 0 is reserved for management records",
+        ),
+        (ClientError::NoAvailableRequestId, "No available request id"),
+        (
+            ClientError::ParamTooLong {
+                name: "NAME".to_owned(),
+            },
+            "Param `NAME` is too long to be encoded",
+        ),
+        (
+            ClientError::InvalidParam {
+                name: "X-FOO".to_owned(),
+            },
+            "Param `X-FOO` has an invalid name or value",
+        ),
+        (
+            ClientError::MissingParam {
+                name: "FCGI_DATA_LENGTH".to_owned(),
+            },
+            "Param `FCGI_DATA_LENGTH` is required",
+        ),
+        (
+            ClientError::InvalidBeginRequest {
+                role: Role::Filter,
+                flags: 2,
+            },
+            "Invalid flags `0x02` of begin request of role `Filter`",
+        ),
+        (
+            ClientError::InvalidResponseHeader {
+                line: "oops".to_owned(),
+            },
+            "Invalid header in response: `oops`",
+        ),
+        (
+            ClientError::InvalidBody {
+                encoding: "gzip".to_owned(),
+                reason: "empty body",
+            },
+            "Can't decode body of content encoding `gzip`: empty body",
+        ),
+        (
+            ClientError::ResponseTooLarge { id: 1, limit: 10 },
+            "Response of request id `1` exceeds the limit of 10 bytes",
+        ),
+        (
+            ClientError::TooManyRecords { id: 1, limit: 10 },
+            "Response of request id `1` exceeds the limit of 10 records",
+        ),
+        (
+            ClientError::PeerCredMismatch { uid: 0, gid: 0 },
+            "Peer of unix socket runs as uid 0 gid 0, not the expected ones",
+        ),
+        (
+            ClientError::ConnectionClosed {
+                id: 1,
+                during: Phase::Response,
+            },
+            "Connection closed while reading response of request id `1`",
+        ),
+        (
+            ClientError::Overloaded { retries: 3 },
+            "Server still overloaded after 3 retries",
+        ),
+        (
+            ClientError::CannotMultiplex { id: 1 },
+            "Backend can't multiplex request of id `1`",
+        ),
+        (
+            ClientError::RetryAdvised {
+                source: Box::new(ClientError::ConnectionClosed {
+                    id: 1,
+                    during: Phase::Response,
+                }),
+            },
+            "Connection lost, retry advised: Connection closed while reading response of request id `1`",
+        ),
+        (
+            ClientError::Busy { id: 1 },
+            "Request of id `1` is still in progress",
+        ),
+        (
+            ClientError::RequestFailed {
+                app_status: 255,
+                stderr: "PHP Fatal error".to_owned(),
+            },
+            "Request failed with app status 255: PHP Fatal error",
+        ),
+        (
+            ClientError::Timeout {
+                id: 0,
+                phase: Phase::Connect,
+            },
+            "Request of id `0` timed out while connecting",
+        ),
+    ];
+
+    for (err, message) in cases {
+        assert_eq!(err.to_string(), message);
+    }
+}
+
+#[test]
+fn source() {
+    let err = ClientError::Connect {
+        address: "127.0.0.1:9000".to_owned(),
+        source: io::Error::new(io::ErrorKind::ConnectionRefused, "refused"),
+    };
+    let source = err.source().unwrap();
+    assert_eq!(source.to_string(), "refused");
+    assert!(source.downcast_ref::<io::Error>().is_some());
+
+    let err = ClientError::RetryAdvised {
+        source: Box::new(ClientError::Busy { id: 1 }),
+    };
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Request of id `1` is still in progress"
+    );
+
+    assert!(ClientError::Busy { id: 1 }.source().is_none());
+}
@@ -0,0 +1,42 @@
+use fastcgi_client::{Client, Params};
+use std::time::Duration;
+use tokio::{
+    io::{self, AsyncWriteExt, BufStream},
+    time::timeout,
+};
+
+mod common;
+
+#[tokio::test]
+async fn no_flush() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(BufStream::new(client_stream), true);
+
+    let id = client
+        .handle_new_request_no_flush(&Params::default(), &mut io::empty())
+        .await
+        .unwrap();
+
+    // The request is still in the buffer.
+    let read = timeout(
+        Duration::from_millis(50),
+        common::read_record(&mut server_stream),
+    )
+    .await;
+    assert!(read.is_err());
+
+    client.flush().await.unwrap();
+    assert_eq!(common::read_request(&mut server_stream).await, id);
+
+    server_stream
+        .write_all(&common::end_request(id, 0, 0))
+        .await
+        .unwrap();
+    let complete = client
+        .handle_response(id, &mut io::sink(), &mut io::sink())
+        .await
+        .unwrap();
+    assert_eq!(complete.app_status, 0);
+}
@@ -0,0 +1,343 @@
+use fastcgi_client::{
+    Address, Client, ClientBuilder, ClientError, Params, Request, RequestType, Stream,
+};
+use std::time::Duration;
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpListener,
+    time::timeout,
+};
+
+mod common;
+
+#[tokio::test]
+async fn get_values() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+        assert_eq!(id, 0);
+        assert_eq!(content, b"\x0e\x00FCGI_MAX_CONNS\x0f\x00FCGI_MPXS_CONNS");
+
+        let reply = common::record(10, 0, b"\x0e\x02FCGI_MAX_CONNS10\x0f\x01FCGI_MPXS_CONNS0");
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let values = client
+        .get_values(&["FCGI_MAX_CONNS", "FCGI_MPXS_CONNS"])
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["FCGI_MAX_CONNS"], "10");
+    assert_eq!(values["FCGI_MPXS_CONNS"], "0");
+    assert_eq!(client.server_values(), &values);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn get_values_split_across_records() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let long_value = vec![b'v'; 200];
+    let server = {
+        let long_value = long_value.clone();
+        tokio::spawn(async move {
+            let (r#type, _, _) = common::read_record(&mut server_stream).await;
+            assert_eq!(r#type, 9);
+
+            let mut content = b"\x0e\x02FCGI_MAX_CONNS10\x06\x80\x00\x00\xc8X_LONG".to_vec();
+            content.extend_from_slice(&long_value);
+            // Split in the middle of the 4 bytes length of the long value.
+            let (first, second) = content.split_at(20);
+            let mut reply = common::record(10, 0, first);
+            reply.extend(common::record(10, 0, second));
+            server_stream.write_all(&reply).await.unwrap();
+            server_stream
+        })
+    };
+
+    let values = client
+        .get_values(&["FCGI_MAX_CONNS", "X_LONG"])
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["FCGI_MAX_CONNS"], "10");
+    assert_eq!(values["X_LONG"].as_bytes(), &long_value[..]);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn get_values_split_between_pairs() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, _, _) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+
+        // An empty record, then one record for each pair.
+        let mut reply = common::record(10, 0, b"");
+        reply.extend(common::record(10, 0, b"\x0e\x02FCGI_MAX_CONNS10"));
+        reply.extend(common::record(10, 0, b"\x0f\x01FCGI_MPXS_CONNS0"));
+        server_stream.write_all(&reply).await.unwrap();
+
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let values = client
+        .get_values(&["FCGI_MAX_CONNS", "FCGI_MPXS_CONNS"])
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["FCGI_MAX_CONNS"], "10");
+    assert_eq!(values["FCGI_MPXS_CONNS"], "0");
+
+    // All the records were read, so the connection is still in sync.
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn get_values_too_long() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, _, _) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+
+        // A bogus length of the value, which the records never complete.
+        let mut reply = common::record(10, 0, b"\x01\xff\xff\xff\xffX");
+        for _ in 0..5 {
+            reply.extend(common::record(10, 0, &[b'v'; 0xffff]));
+        }
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let err = client.get_values(&["X"]).await.unwrap_err();
+    assert!(matches!(err, ClientError::Io(ref err) if err.kind() == io::ErrorKind::InvalidData));
+    assert!(!client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn get_values_unknown_type() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, _, _) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+        let reply = common::record(11, 0, &[9, 0, 0, 0, 0, 0, 0, 0]);
+        server_stream.write_all(&reply).await.unwrap();
+
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let err = client.get_values(&["FCGI_MAX_CONNS"]).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnknownTypeResponse {
+            requested
+        } if requested == RequestType::GetValues as u8
+    ));
+    assert!(client.server_values().is_empty());
+
+    // The reply was read, so the connection is still in sync.
+    assert!(client.is_reusable());
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn get_values_unknown_type_byte() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, _, _) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+        // The rejected type isn't one of the protocol.
+        let reply = common::record(11, 0, &[12, 0, 0, 0, 0, 0, 0, 0]);
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let err = client.get_values(&["FCGI_MAX_CONNS"]).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnknownTypeResponse { requested: 12 }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Management record of type `12` not supported by server"
+    );
+    assert!(client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn ping() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, _, content) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 9);
+        assert_eq!(content, b"\x0f\x00FCGI_MPXS_CONNS");
+        let reply = common::record(10, 0, b"\x0f\x01FCGI_MPXS_CONNS0");
+        server_stream.write_all(&reply).await.unwrap();
+    });
+
+    client.ping().await.unwrap();
+    server.await.unwrap();
+
+    // The server side is dropped.
+    let err = client.ping().await.unwrap_err();
+    assert!(matches!(err, ClientError::ConnectionClosed { id: 0, .. }));
+}
+
+#[tokio::test]
+async fn in_flight_and_capacity() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+    assert_eq!(client.capacity(), 65534);
+
+    let server = tokio::spawn(async move {
+        common::read_record(&mut server_stream).await;
+        let reply = common::record(10, 0, b"\x0d\x02FCGI_MAX_REQS50");
+        server_stream.write_all(&reply).await.unwrap();
+
+        let id = common::read_request(&mut server_stream).await;
+        server_stream
+            .write_all(&common::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        server_stream
+    });
+
+    client.get_values(&["FCGI_MAX_REQS"]).await.unwrap();
+    assert_eq!(client.capacity(), 50);
+
+    assert_eq!(client.in_flight(), 0);
+    let id = client
+        .handle_new_request(&Params::default(), &mut io::empty())
+        .await
+        .unwrap();
+    assert_eq!(client.in_flight(), 1);
+    client
+        .handle_response(id, &mut io::sink(), &mut io::sink())
+        .await
+        .unwrap();
+    assert_eq!(client.in_flight(), 0);
+
+    let _server_stream = server.await.unwrap();
+}
+
+/// Reply to the `FCGI_GET_VALUES` of the first connection with `reply`, if
+/// any, returns the count of connections accepted by then.
+async fn negotiate_server(listener: TcpListener, reply: Option<Vec<u8>>) -> usize {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let (r#type, _, content) = common::read_record(&mut stream).await;
+    assert_eq!(r#type, 9);
+    assert_eq!(content, b"\x0f\x00FCGI_MPXS_CONNS");
+    if let Some(reply) = reply {
+        stream.write_all(&reply).await.unwrap();
+    }
+
+    let mut streams = vec![stream];
+    while let Ok(accepted) = timeout(Duration::from_millis(300), listener.accept()).await {
+        streams.push(accepted.unwrap().0);
+    }
+    streams.len()
+}
+
+async fn negotiate(reply: Option<Vec<u8>>) -> (Client<Stream>, usize) {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(negotiate_server(listener, reply));
+
+    let client = ClientBuilder::new()
+        .set_keep_alive(true)
+        .set_connect_timeout(Duration::from_millis(100))
+        .set_negotiate_values(&["FCGI_MPXS_CONNS"])
+        .connect(Address::TcpSocket(addr))
+        .await
+        .unwrap();
+    (client, server.await.unwrap())
+}
+
+#[tokio::test]
+async fn negotiate_values() {
+    common::setup();
+
+    let reply = common::record(10, 0, b"\x0f\x01FCGI_MPXS_CONNS1");
+    let (client, connections) = negotiate(Some(reply)).await;
+    assert_eq!(client.server_values().len(), 1);
+    assert_eq!(client.server_values()["FCGI_MPXS_CONNS"], "1");
+    assert_eq!(connections, 1);
+}
+
+#[tokio::test]
+async fn negotiate_values_unsupported() {
+    common::setup();
+
+    // FCGI_UNKNOWN_TYPE of FCGI_GET_VALUES, the connection is kept.
+    let reply = common::record(11, 0, &[9, 0, 0, 0, 0, 0, 0, 0]);
+    let (client, connections) = negotiate(Some(reply)).await;
+    assert!(client.server_values().is_empty());
+    assert_eq!(connections, 1);
+}
+
+#[tokio::test]
+async fn negotiate_values_no_answer() {
+    common::setup();
+
+    // The connection left waiting for the answer is replaced.
+    let (client, connections) = negotiate(None).await;
+    assert!(client.server_values().is_empty());
+    assert_eq!(connections, 2);
+}
@@ -0,0 +1,183 @@
+#![cfg(feature = "testutil")]
+
+use fastcgi_client::{
+    testutil::MockServer, Client, ClientError, ClientResult, IdAllocator, Params, Request,
+    RequestIdGenerator,
+};
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncWrite},
+    task::LocalSet,
+};
+
+#[tokio::test]
+async fn shared_id_generator() {
+    let generator = Arc::new(RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(2));
+    // Held by another client.
+    assert_eq!(generator.alloc().await.unwrap(), 1);
+
+    let (stream, server) = MockServer::new().start();
+    let mut client = Client::with_id_generator(stream, true, generator.clone());
+    for _ in 0..2 {
+        client
+            .execute_buffered(Request::new(Params::default(), io::empty()))
+            .await
+            .unwrap();
+    }
+
+    drop(client);
+    let requests = server.await.unwrap().unwrap();
+    let ids: Vec<_> = requests.iter().map(|request| request.id).collect();
+    assert_eq!(ids, [2, 2]);
+
+    generator.release(1);
+    assert_eq!(generator.alloc().await.unwrap(), 1);
+}
+
+/// Allocator of random ids, by a xorshift seeded with `seed`.
+struct RandomAllocator {
+    state: Mutex<(u32, HashSet<u16>)>,
+}
+
+impl RandomAllocator {
+    fn new(seed: u32) -> Self {
+        Self {
+            state: Mutex::new((seed, HashSet::new())),
+        }
+    }
+}
+
+impl IdAllocator for RandomAllocator {
+    fn alloc(&self) -> Pin<Box<dyn Future<Output = ClientResult<u16>> + Send + '_>> {
+        let mut state = self.state.lock().unwrap();
+        let (seed, in_use) = &mut *state;
+        let rv = if in_use.len() >= self.pool_size() as usize {
+            Err(ClientError::NoAvailableRequestId)
+        } else {
+            loop {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 17;
+                *seed ^= *seed << 5;
+                let id = (*seed % self.pool_size() as u32) as u16 + 1;
+                if in_use.insert(id) {
+                    break Ok(id);
+                }
+            }
+        };
+        Box::pin(async move { rv })
+    }
+
+    fn release(&self, id: u16) {
+        self.state.lock().unwrap().1.remove(&id);
+    }
+
+    fn in_use(&self) -> usize {
+        self.state.lock().unwrap().1.len()
+    }
+
+    fn pool_size(&self) -> u16 {
+        u16::MAX - 1
+    }
+}
+
+#[tokio::test]
+async fn random_id_allocator() {
+    let allocator = Arc::new(RandomAllocator::new(42));
+
+    let (stream, server) = MockServer::new().start();
+    let mut client = Client::with_id_generator(stream, true, allocator.clone());
+    for _ in 0..3 {
+        client
+            .execute_buffered(Request::new(Params::default(), io::empty()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(allocator.in_use(), 0);
+
+    drop(client);
+    let requests = server.await.unwrap().unwrap();
+    let ids: Vec<_> = requests.iter().map(|request| request.id).collect();
+
+    let expected = RandomAllocator::new(42);
+    let mut expected_ids = Vec::new();
+    for _ in 0..3 {
+        let id = expected.alloc().await.unwrap();
+        expected.release(id);
+        expected_ids.push(id);
+    }
+    assert_eq!(ids, expected_ids);
+    assert_ne!(ids, [1, 1, 1]);
+}
+
+#[tokio::test]
+async fn sequential_id_allocator() {
+    let allocator: Arc<dyn IdAllocator> =
+        Arc::new(RequestIdGenerator::new(Duration::from_millis(50)));
+    assert_eq!(allocator.alloc().await.unwrap(), 1);
+    assert_eq!(allocator.alloc().await.unwrap(), 2);
+    assert_eq!(allocator.in_use(), 2);
+    allocator.release(1);
+    allocator.release(1);
+    assert_eq!(allocator.in_use(), 1);
+    assert_eq!(allocator.alloc().await.unwrap(), 3);
+    assert_eq!(allocator.pool_size(), 65534);
+}
+
+/// Writer panicking on write.
+struct PanicWriter;
+
+impl AsyncWrite for PanicWriter {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        panic!("failing writer");
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn release_id_on_panic() {
+    let generator = Arc::new(RequestIdGenerator::new(Duration::from_millis(50)).set_pool_size(1));
+
+    let (stream, _server) = MockServer::new().set_stdout("hello").start();
+    let mut client = Client::with_id_generator(stream, true, generator.clone());
+    // The future of execute isn't Send.
+    let local = LocalSet::new();
+    let task = local.spawn_local(async move {
+        client
+            .execute(
+                Request::new(Params::default(), io::empty()),
+                &mut PanicWriter,
+                &mut io::sink(),
+            )
+            .await
+    });
+    let rv = local.run_until(task).await;
+    assert!(rv.unwrap_err().is_panic());
+
+    assert_eq!(generator.alloc().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn reserved_request_id() {
+    let (stream, _server_stream) = io::duplex(1024);
+    let mut client = Client::new(stream, true);
+
+    let result = client
+        .handle_request(0, &Params::default(), &mut io::empty())
+        .await;
+    assert!(matches!(result, Err(ClientError::ReservedRequestId)));
+    assert!(client.is_reusable());
+}
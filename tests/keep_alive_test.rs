@@ -0,0 +1,182 @@
+use fastcgi_client::{BeginRequestFlags, Client, ClientError, Params, Phase, Request};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn sequential_requests() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let mut answered = Vec::new();
+        while answered.len() < 2 {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type == 1 {
+                // The keep alive flag of FCGI_BEGIN_REQUEST.
+                assert_eq!(content[2], 1);
+            }
+            if r#type != 5 || !content.is_empty() || answered.contains(&id) {
+                continue;
+            }
+            answered.push(id);
+
+            let mut reply =
+                common::record(6, id, format!("response {}", answered.len()).as_bytes());
+            reply.extend(common::end_request(id, 0, 0));
+            server_stream.write_all(&reply).await.unwrap();
+        }
+        server_stream
+    });
+
+    for i in 1..=2 {
+        let response = client
+            .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, format!("response {}", i).as_bytes());
+        assert!(client.is_reusable());
+    }
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn not_reusable_after_closed() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        // Close in the middle of the response.
+        let reply = common::record(6, id, b"partial");
+        server_stream.write_all(&reply).await.unwrap();
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(result.is_err());
+    assert!(!client.is_reusable());
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn not_reusable_without_keep_alive() {
+    let (client_stream, _server_stream) = io::duplex(1024);
+    let client = Client::new(client_stream, false);
+    assert!(!client.is_reusable());
+}
+
+#[tokio::test]
+async fn closed_without_keep_alive() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, false);
+
+    let server = tokio::spawn(async move {
+        let (_, id, content) = common::read_record(&mut server_stream).await;
+        // The keep alive flag of FCGI_BEGIN_REQUEST isn't set.
+        assert_eq!(content[2], 0);
+        common::read_request(&mut server_stream).await;
+
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ConnectionClosed {
+            during: Phase::Request,
+            ..
+        })
+    ));
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn with_flags() {
+    common::setup();
+
+    for &flags in [BeginRequestFlags::KEEP_CONN, BeginRequestFlags::empty()].iter() {
+        let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+        let mut client = Client::with_flags(client_stream, flags);
+
+        let server = tokio::spawn(async move {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            assert_eq!(r#type, 1);
+            common::read_request(&mut server_stream).await;
+            let mut reply = common::record(6, id, b"hello");
+            reply.extend(common::end_request(id, 0, 0));
+            server_stream.write_all(&reply).await.unwrap();
+            (content[2], server_stream)
+        });
+
+        client
+            .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        let (sent, _server_stream) = server.await.unwrap();
+        assert_eq!(sent, flags.bits());
+        assert_eq!(
+            client.is_reusable(),
+            flags.contains(BeginRequestFlags::KEEP_CONN)
+        );
+    }
+}
+
+#[tokio::test]
+async fn read_buffer_across_responses() {
+    common::setup();
+
+    for read_buffer_size in [0, 5, 64 * 1024].iter().copied() {
+        let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+        let mut client = Client::new(client_stream, true).set_read_buffer_size(read_buffer_size);
+
+        let server = tokio::spawn(async move {
+            let first = common::read_request(&mut server_stream).await;
+            // The second response is written with the first one, so it's
+            // buffered while the first one is read.
+            let second = first + 1;
+            let mut reply = Vec::new();
+            for (id, content) in [(first, b"first"), (second, b"later")].iter() {
+                for _ in 0..10 {
+                    reply.extend(common::record(6, *id, *content));
+                }
+                reply.extend(common::end_request(*id, 0, 0));
+            }
+            server_stream.write_all(&reply).await.unwrap();
+            assert_eq!(common::read_request(&mut server_stream).await, second);
+            server_stream
+        });
+
+        for content in [b"first", b"later"].iter() {
+            let response = client
+                .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+                .await
+                .unwrap();
+            assert_eq!(response.stdout, content.repeat(10));
+        }
+        assert!(client.is_reusable());
+
+        let _server_stream = server.await.unwrap();
+    }
+}
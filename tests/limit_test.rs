@@ -0,0 +1,175 @@
+use fastcgi_client::{Client, ClientBuilder, ClientError, Params, Request};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn max_response_bytes() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_max_response_bytes(Some(8));
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::record(7, id, b"world"));
+        server_stream.write_all(&reply).await.unwrap();
+
+        // Stop on abort.
+        loop {
+            let (r#type, _, _) = common::read_record(&mut server_stream).await;
+            if r#type == 2 {
+                break;
+            }
+        }
+        let mut reply = common::record(6, id, b"more");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"small");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::ResponseTooLarge { limit: 8, .. })
+    ));
+
+    // The aborted response was drained, so the connection is reusable.
+    let response = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"small");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn max_records_per_response() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_max_records_per_response(Some(100));
+
+    // A runaway backend flooding empty records, until the client is dropped.
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let record = common::record(6, id, b"");
+        let mut sent = 0;
+        while server_stream.write_all(&record).await.is_ok() {
+            sent += 1;
+        }
+        sent
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::TooManyRecords { limit: 100, .. })
+    ));
+    assert!(!client.is_reusable());
+
+    drop(client);
+    assert!(server.await.unwrap() > 100);
+}
+
+#[tokio::test]
+async fn max_records_per_response_duplex() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_max_records_per_response(Some(100));
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let record = common::record(6, id, b"");
+        let mut sent = 0;
+        while server_stream.write_all(&record).await.is_ok() {
+            sent += 1;
+        }
+        sent
+    });
+
+    let result = client
+        .execute_duplex(
+            Request::new(Params::default(), io::empty()),
+            &mut io::sink(),
+            &mut io::sink(),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::TooManyRecords { limit: 100, .. })
+    ));
+    assert!(!client.is_reusable());
+
+    drop(client);
+    assert!(server.await.unwrap() > 100);
+}
+
+#[tokio::test]
+async fn max_records_per_response_exact() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_max_records_per_response(Some(3));
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::record(7, id, b"warning"));
+        reply.extend(common::record(6, id, b""));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert!(client.is_reusable());
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn max_records_per_response_builder() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = ClientBuilder::new()
+        .set_max_records_per_response(Some(2))
+        .build(client_stream);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::record(7, id, b"warning"));
+        reply.extend(common::record(6, id, b""));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::TooManyRecords { limit: 2, .. })
+    ));
+
+    let _server_stream = server.await.unwrap();
+}
@@ -0,0 +1,85 @@
+use fastcgi_client::client::handle_fastcgi_response;
+use tokio::io;
+
+mod common;
+
+/// Deterministic pseudo random bytes, xorshift of the seed.
+fn random_bytes(seed: &mut u64, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed as u8
+        })
+        .collect()
+}
+
+/// Records of the response of id 1 with the header fields overwritten.
+fn with_header(r#type: u8, content_length: u16, padding_length: u8, content: &[u8]) -> Vec<u8> {
+    let mut record = common::record(r#type, 1, content);
+    record[4..6].copy_from_slice(&content_length.to_be_bytes());
+    record[6] = padding_length;
+    record
+}
+
+#[tokio::test]
+async fn adversarial_lengths() {
+    common::setup();
+
+    let inputs = vec![
+        // Content longer than the stream.
+        with_header(6, u16::MAX, 0, b"short"),
+        // Padding longer than the stream.
+        with_header(6, 5, u8::MAX, b"hello"),
+        // FCGI_END_REQUEST of any content length shorter than 8 bytes.
+        with_header(3, 0, 0, b""),
+        with_header(3, 7, 0, b"\x00\x00\x00\x00\x00\x00\x00"),
+        // Content length of 51 to 100 bytes, the edges of the logging.
+        with_header(6, 51, 0, &[b'x'; 51]),
+        with_header(6, 100, 0, &[0xe2; 100]),
+        // Unknown version and record type.
+        with_header(0xff, 0, 0, b""),
+    ];
+
+    for input in inputs {
+        let rv =
+            handle_fastcgi_response(&mut &input[..], 1, &mut io::sink(), &mut io::sink()).await;
+        assert!(rv.is_err());
+    }
+}
+
+#[tokio::test]
+async fn random_input() {
+    common::setup();
+
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+    for len in 0..2000 {
+        let mut input = random_bytes(&mut seed, len % 300);
+        // Valid headers of id 1 more often than by chance.
+        if input.len() >= 8 {
+            input[0] = 1;
+            input[1] = 3 + input[1] % 5;
+            input[2..4].copy_from_slice(&1u16.to_be_bytes());
+            input[4] = 0;
+        }
+        let _ = handle_fastcgi_response(&mut &input[..], 1, &mut io::sink(), &mut io::sink()).await;
+    }
+}
+
+#[cfg(feature = "codec")]
+#[test]
+fn random_codec_input() {
+    use bytes::BytesMut;
+    use fastcgi_client::codec::FastCgiCodec;
+
+    let mut seed = 0x9e37_79b9_7f4a_7c15;
+    let mut codec = FastCgiCodec::new();
+    for len in 0..2000 {
+        let mut src = BytesMut::from(&random_bytes(&mut seed, len % 300)[..]);
+        if !src.is_empty() {
+            src[0] = 1;
+        }
+        while let Ok(Some(_)) = codec.decode(&mut src) {}
+    }
+}
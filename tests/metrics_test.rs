@@ -0,0 +1,56 @@
+#![cfg(feature = "metrics")]
+
+use fastcgi_client::{metrics, Client, Params, Request};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn counters() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let mut answered = Vec::new();
+        while answered.len() < 2 {
+            let id = common::read_request(&mut server_stream).await;
+            if answered.contains(&id) {
+                continue;
+            }
+            answered.push(id);
+
+            let mut reply = common::record(6, id, b"hello");
+            reply.extend(common::record(7, id, b"oops"));
+            // The second response is overloaded.
+            reply.extend(common::end_request(id, 0, answered.len() as u8 * 2 - 2));
+            server_stream.write_all(&reply).await.unwrap();
+        }
+        server_stream
+    });
+
+    let before = metrics::snapshot();
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(response.is_ok());
+    let response = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(response.is_err());
+
+    let after = metrics::snapshot();
+    assert_eq!(after.requests_total - before.requests_total, 2);
+    assert_eq!(after.stdout_bytes - before.stdout_bytes, 10);
+    assert_eq!(after.stderr_bytes - before.stderr_bytes, 8);
+    assert_eq!(
+        after.errors_total["overloaded"] - before.errors_total["overloaded"],
+        1
+    );
+    assert_eq!(after.errors_total["io"], before.errors_total["io"]);
+    assert!(after.request_duration >= before.request_duration);
+
+    let _server_stream = server.await.unwrap();
+}
@@ -0,0 +1,213 @@
+#![cfg(feature = "testutil")]
+
+use fastcgi_client::{testutil::MockServer, Client, ClientError, Params, Request, Role};
+use tokio::io;
+
+#[tokio::test]
+async fn execute_end_to_end() {
+    let (stream, server) = MockServer::new()
+        .set_stdout(vec![b'x'; 70000])
+        .set_stderr("PHP Notice: undefined index")
+        .set_app_status(3)
+        .start();
+    let mut client = Client::new(stream, true);
+
+    let params = Params::default()
+        .set_request_method("POST")
+        .set_content_length("4");
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let complete = client
+        .execute(
+            Request::new(params, &mut &b"body"[..]),
+            &mut stdout,
+            &mut stderr,
+        )
+        .await
+        .unwrap();
+    assert_eq!(complete.app_status, 3);
+    assert_eq!(stdout.len(), 70000);
+    assert_eq!(stderr, b"PHP Notice: undefined index");
+
+    // The app status isn't 0.
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::RequestFailed { app_status: 3, ref stderr })
+            if stderr == "PHP Notice: undefined index"
+    ));
+
+    drop(client);
+    let requests = server.await.unwrap().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].role, Role::Responder);
+    assert!(requests[0].keep_alive);
+    assert_eq!(requests[0].param("REQUEST_METHOD"), Some("POST"));
+    assert_eq!(requests[0].param("CONTENT_LENGTH"), Some("4"));
+    assert_eq!(requests[0].stdin, b"body");
+    assert_eq!(requests[1].param("REQUEST_METHOD"), None);
+}
+
+#[tokio::test]
+async fn single_end_of_empty_stdin() {
+    let (stream, server) = MockServer::new().start();
+    let mut client = Client::new(stream, true);
+
+    let params = Params::default().set_request_method("GET");
+    for _ in 0..2 {
+        client
+            .execute_buffered(Request::new(params.clone(), io::empty()))
+            .await
+            .unwrap();
+    }
+
+    // A second empty STDIN record would fail the server.
+    drop(client);
+    let requests = server.await.unwrap().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|request| request.stdin.is_empty()));
+}
+
+#[tokio::test]
+async fn execute_discard() {
+    let (stream, server) = MockServer::new()
+        .set_stdout("Content-type: text/plain\r\n\r\nwarmed")
+        .set_stderr("PHP Notice: cache miss")
+        .set_app_status(2)
+        .start();
+    let mut client = Client::new(stream, false);
+
+    let app_status = client
+        .execute_discard(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(app_status, 2);
+
+    drop(client);
+    assert_eq!(server.await.unwrap().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn request_failed_with_stderr() {
+    let (stream, _server) = MockServer::new()
+        .set_stderr(format!("PHP Fatal error: {}", "x".repeat(10000)))
+        .set_app_status(255)
+        .start();
+    let mut client = Client::new(stream, true);
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    match result {
+        Err(ClientError::RequestFailed { app_status, stderr }) => {
+            assert_eq!(app_status, 255);
+            assert!(stderr.starts_with("PHP Fatal error: xxx"));
+            assert_eq!(stderr.len(), 4096);
+        }
+        _ => panic!("expect request failed"),
+    }
+    assert!(client.is_reusable());
+}
+
+#[tokio::test]
+async fn stderr_only() {
+    let (stream, _server) = MockServer::new()
+        .set_stderr("PHP Fatal error: out of memory")
+        .set_app_status(255)
+        .start();
+    let mut client = Client::new(stream, true);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let complete = client
+        .execute(
+            Request::new(Params::default(), io::empty()),
+            &mut stdout,
+            &mut stderr,
+        )
+        .await
+        .unwrap();
+    assert_eq!(complete.app_status, 255);
+    assert!(stdout.is_empty());
+    assert_eq!(stderr, b"PHP Fatal error: out of memory");
+
+    let stats = client.last_request_stats();
+    assert!(!stats.had_stdout);
+    assert!(stats.had_stderr);
+    assert_eq!(stats.stdout_bytes, 0);
+
+    // An empty body still has its STDOUT.
+    let (stream, _server) = MockServer::new()
+        .set_stdout("Content-type: text/plain\r\n\r\n")
+        .start();
+    let mut client = Client::new(stream, true);
+    client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    let stats = client.last_request_stats();
+    assert!(stats.had_stdout);
+    assert!(!stats.had_stderr);
+}
+
+#[tokio::test]
+async fn collect_response() {
+    let (stream, _server) = MockServer::new()
+        .set_stdout(vec![b'x'; 70000])
+        .set_stderr("PHP Notice: undefined index")
+        .start();
+    let mut client = Client::new(stream, true);
+
+    let buffered = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    let (collected, complete) = client
+        .execute_stream(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap()
+        .collect_response()
+        .await
+        .unwrap();
+    assert_eq!(collected.stdout, buffered.stdout);
+    assert_eq!(collected.stderr, buffered.stderr);
+    assert_eq!(complete.app_status, 0);
+    assert!(client.is_reusable());
+
+    // A stream already ended.
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    while stream.next().await.is_some() {}
+    let id = stream.id();
+    assert!(matches!(
+        stream.collect_response().await,
+        Err(ClientError::ResponseNotFound { id: not_found }) if not_found == id
+    ));
+    // A non-zero app status is returned, where `execute_buffered` fails.
+    let (stream, _server) = MockServer::new()
+        .set_stderr("PHP Fatal error")
+        .set_app_status(3)
+        .start();
+    let mut client = Client::new(stream, true);
+
+    let (collected, complete) = client
+        .execute_stream(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap()
+        .collect_response()
+        .await
+        .unwrap();
+    assert_eq!(complete.app_status, 3);
+    assert_eq!(collected.stderr, b"PHP Fatal error");
+    let buffered = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(matches!(
+        buffered,
+        Err(ClientError::RequestFailed { app_status: 3, .. })
+    ));
+}
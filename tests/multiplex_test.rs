@@ -0,0 +1,258 @@
+use fastcgi_client::{multiplex::MultiplexClient, ClientError, Params, Request};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::io::{self, AsyncRead, AsyncWriteExt, DuplexStream, ReadBuf};
+
+mod common;
+
+/// Read a request sent one at a time, returns its id and STDIN.
+async fn read_serial_request(stream: &mut DuplexStream) -> (u16, Vec<u8>) {
+    let mut stdin = Vec::new();
+    loop {
+        let (r#type, id, content) = common::read_record(stream).await;
+        if r#type == 5 {
+            if content.is_empty() {
+                return (id, stdin);
+            }
+            stdin.extend(content);
+        }
+    }
+}
+
+/// Read requests until `count` of them are complete, returns their ids.
+async fn read_requests(stream: &mut DuplexStream, count: usize) -> Vec<u16> {
+    let mut ids = Vec::new();
+    while ids.len() < count {
+        let (r#type, id, content) = common::read_record(stream).await;
+        // The empty stdin record ends a request.
+        if r#type == 5 && content.is_empty() && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+#[tokio::test]
+async fn concurrent_requests() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let server = tokio::spawn(async move {
+        let ids = read_requests(&mut server_stream, 2).await;
+        let mut output = Vec::new();
+        // Respond in reverse order, with interleaved records.
+        for id in ids.iter().rev() {
+            output.extend(common::record(6, *id, format!("hello {}", id).as_bytes()));
+        }
+        for id in ids.iter().rev() {
+            output.extend(common::end_request(*id, 0, 0));
+        }
+        server_stream.write_all(&output).await.unwrap();
+        server_stream
+    });
+
+    let (first, second) = tokio::join!(
+        client.execute_buffered(Request::new(Params::default(), io::empty())),
+        client.execute_buffered(Request::new(Params::default(), io::empty())),
+    );
+    let first = String::from_utf8(first.unwrap().stdout).unwrap();
+    let second = String::from_utf8(second.unwrap().stdout).unwrap();
+
+    let mut outputs = vec![first, second];
+    outputs.sort();
+    assert_eq!(outputs, vec!["hello 1", "hello 2"]);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn stream_closed() {
+    common::setup();
+
+    let (client_stream, server_stream) = io::duplex(1024);
+    let client = MultiplexClient::new(client_stream);
+    drop(server_stream);
+
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn fall_back_to_serial() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let server = tokio::spawn(async move {
+        // Accept the first request and reject the concurrent one.
+        let ids = read_requests(&mut server_stream, 2).await;
+        let mut output = common::record(6, ids[0], b"hello");
+        output.extend(common::end_request(ids[0], 0, 0));
+        output.extend(common::end_request(ids[1], 0, 1));
+        server_stream.write_all(&output).await.unwrap();
+
+        // Then the requests come one at a time, starting with the rejected
+        // one.
+        for _ in 0..3 {
+            let (id, stdin) = read_serial_request(&mut server_stream).await;
+            let mut output = common::record(6, id, &stdin);
+            output.extend(common::end_request(id, 0, 0));
+            server_stream.write_all(&output).await.unwrap();
+        }
+        server_stream
+    });
+
+    let (first, second) = tokio::join!(
+        client.execute_buffered(Request::new(Params::default(), io::empty())),
+        client.execute_buffered(Request::new(Params::default(), &b"serial"[..])),
+    );
+    assert_eq!(first.unwrap().stdout, b"hello");
+    // Re-sent with its STDIN.
+    assert_eq!(second.unwrap().stdout, b"serial");
+    assert!(client.is_serialized());
+
+    let (first, second) = tokio::join!(
+        client.execute_buffered(Request::new(Params::default(), &b"serial"[..])),
+        client.execute_buffered(Request::new(Params::default(), &b"serial"[..])),
+    );
+    assert_eq!(first.unwrap().stdout, b"serial");
+    assert_eq!(second.unwrap().stdout, b"serial");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn cannot_multiplex_serial() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let server = tokio::spawn(async move {
+        // Reject the request, and again once re-sent.
+        for _ in 0..2 {
+            let (id, _) = read_serial_request(&mut server_stream).await;
+            server_stream
+                .write_all(&common::end_request(id, 0, 1))
+                .await
+                .unwrap();
+        }
+        server_stream
+    });
+
+    let rv = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(matches!(rv, Err(ClientError::CannotMultiplex { .. })));
+    assert!(client.is_serialized());
+    assert_eq!(client.in_flight(), 0);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_request_released() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let (cancelled_tx, cancelled_rx) = tokio::sync::oneshot::channel();
+    let server = tokio::spawn(async move {
+        let cancelled = read_requests(&mut server_stream, 1).await[0];
+        cancelled_rx.await.unwrap();
+
+        // The response of the cancelled request is discarded.
+        let mut output = common::record(6, cancelled, b"late");
+        output.extend(common::end_request(cancelled, 0, 0));
+        server_stream.write_all(&output).await.unwrap();
+
+        let id = read_requests(&mut server_stream, 1).await[0];
+        let mut output = common::record(6, id, b"hello");
+        output.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&output).await.unwrap();
+        server_stream
+    });
+
+    let result = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.execute_buffered(Request::new(Params::default(), io::empty())),
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(client.in_flight(), 0);
+    cancelled_tx.send(()).unwrap();
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert_eq!(client.in_flight(), 0);
+
+    let _server_stream = server.await.unwrap();
+}
+
+/// A body failing while the request is written.
+struct FailingBody;
+
+impl AsyncRead for FailingBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::Error::other("body failed")))
+    }
+}
+
+#[tokio::test]
+async fn poisoned_by_partial_write() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let client = MultiplexClient::new(client_stream);
+
+    let (pending_tx, pending_rx) = tokio::sync::oneshot::channel();
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        read_requests(&mut server_stream, 1).await;
+        pending_tx.send(()).unwrap();
+        // Never answer, the pending request is failed by the poisoning.
+        let _ = done_rx.await;
+        server_stream
+    });
+
+    let (pending, failed) = tokio::join!(
+        client.execute_buffered(Request::new(Params::default(), io::empty())),
+        async {
+            pending_rx.await.unwrap();
+            client
+                .execute_buffered(Request::new(Params::default(), FailingBody))
+                .await
+        }
+    );
+    assert!(failed.is_err());
+    assert!(matches!(
+        pending,
+        Err(ClientError::ConnectionClosed { id: 1, .. })
+    ));
+    assert!(client.is_poisoned());
+    assert_eq!(client.in_flight(), 0);
+
+    let later = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await;
+    assert!(later.is_err());
+
+    done_tx.send(()).unwrap();
+    let _server_stream = server.await.unwrap();
+}
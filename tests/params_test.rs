@@ -0,0 +1,380 @@
+use fastcgi_client::{client::handle_fastcgi_request, Client, ClientError, Params, Request};
+use std::collections::HashMap;
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[test]
+fn builder() {
+    let params = Params::default()
+        .request_method("GET")
+        .script_filename("/var/www/index.php")
+        .query_string("a=1")
+        .path_info("/extra")
+        .https("on")
+        .param("HTTP_HOST", "example.com");
+
+    assert_eq!(params["REQUEST_METHOD"], "GET");
+    assert_eq!(params["SCRIPT_FILENAME"], "/var/www/index.php");
+    assert_eq!(params["QUERY_STRING"], "a=1");
+    assert_eq!(params["PATH_INFO"], "/extra");
+    assert_eq!(params["HTTPS"], "on");
+    assert_eq!(params["HTTP_HOST"], "example.com");
+    assert_eq!(params["SERVER_PROTOCOL"], "HTTP/1.1");
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn from_http_parts() {
+    let (parts, _) = http::Request::post("/index.php?a=1&b=2")
+        .header("Content-Type", "application/json")
+        .header("Content-Length", "2")
+        .header("X-Forwarded-For", "10.0.0.1")
+        .header("Accept", "text/html")
+        .header("Accept", "text/plain")
+        .body(())
+        .unwrap()
+        .into_parts();
+
+    let params = Params::from(&parts);
+    assert_eq!(params["REQUEST_METHOD"], "POST");
+    assert_eq!(params["REQUEST_URI"], "/index.php?a=1&b=2");
+    assert_eq!(params["SCRIPT_NAME"], "/index.php");
+    assert_eq!(params["QUERY_STRING"], "a=1&b=2");
+    assert_eq!(params["SERVER_PROTOCOL"], "HTTP/1.1");
+    assert_eq!(params["CONTENT_TYPE"], "application/json");
+    assert_eq!(params["CONTENT_LENGTH"], "2");
+    assert_eq!(params["HTTP_X_FORWARDED_FOR"], "10.0.0.1");
+    assert_eq!(params["HTTP_ACCEPT"], "text/html, text/plain");
+    assert_eq!(params["PATH_INFO"], "/index.php");
+    assert!(!params.contains_key("HTTP_CONTENT_TYPE"));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn from_http_parts_unsafe_headers() {
+    let (parts, _) = http::Request::get("/")
+        .header("Cookie", "a=1")
+        .header("Cookie", "b=2")
+        .header("Foo-Bar", "dash")
+        .header("Foo_Bar", "underscore")
+        .header("Proxy", "http://evil.example.com")
+        .body(())
+        .unwrap()
+        .into_parts();
+
+    let params = Params::from(&parts);
+    assert_eq!(params["HTTP_COOKIE"], "a=1; b=2");
+    assert_eq!(params["HTTP_FOO_BAR"], "dash");
+    assert!(!params.contains_key("HTTP_PROXY"));
+}
+
+#[test]
+fn insertion_order() {
+    let mut params = Params::default()
+        .param("ZETA", "1")
+        .param("ALPHA", "2")
+        .param("MIDDLE", "3");
+    params.insert("GATEWAY_INTERFACE", "CGI/1.1");
+
+    let names: Vec<&str> = params.iter().map(|(name, _)| &**name).collect();
+    assert_eq!(
+        names,
+        [
+            "GATEWAY_INTERFACE",
+            "SERVER_SOFTWARE",
+            "SERVER_PROTOCOL",
+            "ZETA",
+            "ALPHA",
+            "MIDDLE"
+        ]
+    );
+    assert_eq!(params["GATEWAY_INTERFACE"], "CGI/1.1");
+
+    assert_eq!(params.remove("ALPHA").as_deref(), Some("2"));
+    assert!(!params.contains_key("ALPHA"));
+}
+
+#[test]
+fn get_remove_contains() {
+    let mut params = Params::default()
+        .set_script_filename("/router.php")
+        .param("ALPHA", "1")
+        .param("EMPTY", "");
+
+    assert_eq!(params.get("SCRIPT_FILENAME"), Some("/router.php"));
+    assert_eq!(params.get("EMPTY"), Some(""));
+    assert_eq!(params.get("MISSING"), None);
+    // Names are case sensitive.
+    assert_eq!(params.get("script_filename"), None);
+    assert!(params.contains_key("EMPTY"));
+    assert!(!params.contains_key("MISSING"));
+
+    // Override a param computed elsewhere, keeping its position.
+    params.insert("SCRIPT_FILENAME", "/index.php");
+    assert_eq!(params.get("SCRIPT_FILENAME"), Some("/index.php"));
+
+    let len = params.iter().count();
+    assert_eq!(params.remove("MISSING"), None);
+    assert_eq!(params.iter().count(), len);
+    assert_eq!(params.remove("SCRIPT_FILENAME").as_deref(), Some("/index.php"));
+    assert_eq!(params.remove("SCRIPT_FILENAME"), None);
+    assert!(!params.contains_key("SCRIPT_FILENAME"));
+    assert_eq!(params.iter().count(), len - 1);
+    assert_eq!(params.get("ALPHA"), Some("1"));
+}
+
+#[test]
+fn owned_values() {
+    let body = "a=1&b=2";
+    let mut params = Params::default()
+        .content_length(body.len().to_string())
+        .param(format!("HTTP_{}", "X_TOKEN"), "secret");
+    params.insert("QUERY_STRING", String::from(body));
+
+    assert_eq!(params["CONTENT_LENGTH"], "7");
+    assert_eq!(params.get("HTTP_X_TOKEN"), Some("secret"));
+    assert_eq!(params["QUERY_STRING"], body);
+}
+
+#[test]
+fn from_iter() {
+    let vars = vec![
+        ("REQUEST_METHOD".to_owned(), "GET".to_owned()),
+        ("SCRIPT_FILENAME".to_owned(), "/index.php".to_owned()),
+        ("REQUEST_METHOD".to_owned(), "POST".to_owned()),
+    ];
+    let params: Params<'_> = vars.into_iter().collect();
+    let pairs: Vec<(&str, &str)> = params.iter().map(|(k, v)| (&**k, &**v)).collect();
+    assert_eq!(
+        pairs,
+        [("REQUEST_METHOD", "POST"), ("SCRIPT_FILENAME", "/index.php")]
+    );
+
+    let params: Params<'_> = [("QUERY_STRING", "a=1")].iter().copied().collect();
+    assert_eq!(params["QUERY_STRING"], "a=1");
+    assert!(!params.contains_key("GATEWAY_INTERFACE"));
+}
+
+#[test]
+fn from_hash_map() {
+    let mut vars = HashMap::new();
+    vars.insert("REQUEST_METHOD".to_owned(), "GET".to_owned());
+    vars.insert("CONTENT_LENGTH".to_owned(), "0".to_owned());
+
+    let params = Params::from(vars);
+    assert_eq!(params.len(), 2);
+    assert_eq!(params["REQUEST_METHOD"], "GET");
+    assert_eq!(params["CONTENT_LENGTH"], "0");
+}
+
+#[test]
+fn to_hash_map() {
+    let params = Params::default().set_request_method("POST");
+    let map = params.to_hash_map();
+    assert_eq!(map.len(), params.len());
+    assert_eq!(map.get("REQUEST_METHOD"), Some(&"POST"));
+    assert_eq!(map["GATEWAY_INTERFACE"], "FastCGI/1.0");
+}
+
+#[cfg(feature = "testutil")]
+#[tokio::test]
+async fn sent_in_order() {
+    use fastcgi_client::{testutil::MockServer, Client, Request};
+
+    let (stream, server) = MockServer::new().start();
+    let mut client = Client::new(stream, false);
+
+    let vars = vec![("ZETA", "1"), ("ALPHA", "2"), ("CONTENT_LENGTH", "0")];
+    let params: Params<'_> = vars.iter().copied().collect();
+    client
+        .execute_buffered(Request::new(params, &mut tokio::io::empty()))
+        .await
+        .unwrap();
+
+    let requests = server.await.unwrap().unwrap();
+    let received: Vec<(&str, &str)> = requests[0]
+        .params
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(received, vars);
+}
+
+/// Params exceeding a record, such as a large `HTTP_COOKIE`.
+fn large_params() -> Vec<(String, String)> {
+    let mut vars = vec![("HTTP_COOKIE".to_owned(), "c".repeat(150_000))];
+    for i in 0..1000 {
+        vars.push((format!("HTTP_X_VAR_{}", i), format!("value-{}", i)));
+    }
+    vars
+}
+
+#[tokio::test]
+async fn large_params_framing() {
+    use fastcgi_client::client::handle_fastcgi_request;
+
+    let vars = large_params();
+    let params: Params<'_> = vars.iter().cloned().collect();
+    let mut buf = Vec::new();
+    handle_fastcgi_request(&mut buf, false, 1, &params, &mut tokio::io::empty())
+        .await
+        .unwrap();
+
+    // The lengths of the FCGI_PARAMS records, until the empty one.
+    let mut lengths = Vec::new();
+    let mut rest = &buf[..];
+    while !rest.is_empty() {
+        let content_length = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+        if rest[1] == 4 {
+            lengths.push(content_length);
+        }
+        rest = &rest[8 + content_length + rest[6] as usize..];
+    }
+    let total: usize = lengths.iter().sum();
+    assert!(total > 2 * 0xffff);
+    assert_eq!(lengths[..2], [0xffff, 0xffff]);
+    assert_eq!(lengths[2], total - 2 * 0xffff);
+    assert_eq!(lengths[3..], [0]);
+}
+
+#[cfg(feature = "testutil")]
+#[tokio::test]
+async fn large_params_received() {
+    use fastcgi_client::{testutil::MockServer, Client, Request};
+
+    let (stream, server) = MockServer::new().start();
+    let mut client = Client::new(stream, false);
+
+    let vars = large_params();
+    let params: Params<'_> = vars.iter().cloned().collect();
+    client
+        .execute_buffered(Request::new(params, &mut tokio::io::empty()))
+        .await
+        .unwrap();
+
+    let requests = server.await.unwrap().unwrap();
+    assert_eq!(requests[0].params, vars);
+}
+
+#[test]
+fn encode() {
+    let long_value = "v".repeat(200);
+    let params = Params::default()
+        .param("A", "1")
+        .param("LONG", &*long_value);
+    let encoded = params.encode().unwrap();
+
+    let mut expect = Vec::new();
+    for (name, value) in params.iter() {
+        for len in [name.len(), value.len()].iter() {
+            if *len < 128 {
+                expect.push(*len as u8);
+            } else {
+                expect.extend_from_slice(&(*len as u32 | 1 << 31).to_be_bytes());
+            }
+        }
+        expect.extend_from_slice(name.as_bytes());
+        expect.extend_from_slice(value.as_bytes());
+    }
+    assert_eq!(encoded, expect);
+}
+
+/// Records of the request received by the server, then end the request.
+async fn received_request(mut server_stream: io::DuplexStream) -> Vec<(u8, Vec<u8>)> {
+    let mut records = Vec::new();
+    loop {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        let end = r#type == 5 && content.is_empty();
+        records.push((r#type, content));
+        if end {
+            server_stream
+                .write_all(&common::end_request(id, 0, 0))
+                .await
+                .unwrap();
+            return records;
+        }
+    }
+}
+
+#[tokio::test]
+async fn execute_with_encoded_params() {
+    common::setup();
+
+    let params = Params::default()
+        .request_method("POST")
+        .content_length("3")
+        .param("HTTP_X_LONG", "x".repeat(300));
+
+    let (client_stream, server_stream) = io::duplex(1024 * 1024);
+    let server = tokio::spawn(received_request(server_stream));
+    let mut client = Client::new(client_stream, false);
+    client
+        .execute(
+            Request::new(params.clone(), &b"a=1"[..]),
+            &mut io::sink(),
+            &mut io::sink(),
+        )
+        .await
+        .unwrap();
+    let expect = server.await.unwrap();
+
+    let encoded = params.encode().unwrap();
+    let (client_stream, server_stream) = io::duplex(1024 * 1024);
+    let server = tokio::spawn(received_request(server_stream));
+    let mut client = Client::new(client_stream, false);
+    client
+        .execute_with_encoded_params(&encoded, &mut &b"a=1"[..], &mut io::sink(), &mut io::sink())
+        .await
+        .unwrap();
+    assert_eq!(server.await.unwrap(), expect);
+
+    let params: Vec<u8> = expect
+        .iter()
+        .filter(|(r#type, _)| *r#type == 4)
+        .flat_map(|(_, content)| content.clone())
+        .collect();
+    assert_eq!(params, encoded);
+}
+
+#[test]
+fn invalid_params() {
+    for (name, value) in [
+        ("", "1"),
+        ("X-FOO", "1"),
+        ("HTTP_X\nY", "1"),
+        ("HTTP_X_FOO", "a\r\nSet-Cookie: x"),
+        ("HTTP_X_FOO", "a\0b"),
+    ]
+    .iter()
+    {
+        let params = Params::default().param(*name, *value);
+        match params.encode() {
+            Err(ClientError::InvalidParam { name: invalid }) => assert_eq!(invalid, *name),
+            rv => panic!("unexpected result: {:?}", rv.map(|_| ())),
+        }
+    }
+}
+
+#[tokio::test]
+async fn invalid_params_not_sent() {
+    let params = Params::default().param("HTTP_X_FOO", "a\r\nb");
+    let mut written = Vec::new();
+    let err = handle_fastcgi_request(&mut written, false, 1, &params, &mut io::empty())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::InvalidParam { .. }));
+    assert!(written.is_empty());
+}
+
+#[test]
+fn sanitize() {
+    let params = Params::default()
+        .param("HTTP_X-FOO.BAR", "a\r\nb\0c")
+        .param("", "empty")
+        .sanitize();
+
+    assert_eq!(params["HTTP_X_FOO_BAR"], "abc");
+    assert!(!params.contains_key(""));
+    assert!(params.encode().is_ok());
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "http")]
+
+use fastcgi_client::{ClientError, Response};
+
+fn stdout_response(stdout: &[u8]) -> Response {
+    Response {
+        stdout: stdout.to_vec(),
+        stderr: Vec::new(),
+    }
+}
+
+#[test]
+fn headers_and_body() {
+    let response = stdout_response(
+        b"Status: 404 Not Found\r\nContent-type: text/html\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n<h1>404</h1>",
+    );
+    let (status, headers, body) = response.parse_http().unwrap();
+    assert_eq!(status, 404);
+    assert_eq!(headers["content-type"], "text/html");
+    assert_eq!(headers.get_all("set-cookie").iter().count(), 2);
+    assert!(!headers.contains_key("status"));
+    assert_eq!(body, b"<h1>404</h1>");
+}
+
+#[test]
+fn bare_line_feeds() {
+    let response = stdout_response(b"X-Powered-By: PHP\nContent-type: text/plain\n\nhello\n\nworld");
+    let (status, headers, body) = response.parse_http().unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(headers["x-powered-by"], "PHP");
+    assert_eq!(body, b"hello\n\nworld");
+}
+
+#[test]
+fn no_headers() {
+    for stdout in [&b"just a body"[..], b"hello\n\nworld", b""] {
+        let response = stdout_response(stdout);
+        let (status, headers, body) = response.parse_http().unwrap();
+        assert_eq!(status, 200);
+        assert!(headers.is_empty());
+        assert_eq!(body, stdout);
+    }
+}
+
+#[test]
+fn invalid_header() {
+    let response = stdout_response(b"Content-type: text/html\r\nnot a header\r\n\r\nbody");
+    assert!(matches!(
+        response.parse_http(),
+        Err(ClientError::InvalidResponseHeader { line }) if line == "not a header"
+    ));
+
+    let response = stdout_response(b"Status: abc\r\n\r\nbody");
+    assert!(response.parse_http().is_err());
+}
+
+#[test]
+fn repeated_headers() {
+    let response = stdout_response(
+        b"Set-Cookie: a=1; path=/\r\nContent-type: text/html\r\nSet-Cookie: b=2\r\n\r\n",
+    );
+    let (_, headers, _) = response.parse_http().unwrap();
+    let cookies: Vec<_> = headers.get_all("set-cookie").iter().collect();
+    assert_eq!(cookies, ["a=1; path=/", "b=2"]);
+}
+
+#[test]
+fn folded_headers() {
+    let response = stdout_response(
+        b"X-Long: first \r\n  second\r\n\tthird\r\nContent-type: text/html\r\n\r\nbody",
+    );
+    let (_, headers, body) = response.parse_http().unwrap();
+    assert_eq!(headers["x-long"], "first second third");
+    assert_eq!(headers["content-type"], "text/html");
+    assert_eq!(body, b"body");
+
+    // A continuation line first isn't a header.
+    let response = stdout_response(b" X-Long: first\r\n\r\nbody");
+    let (_, headers, _) = response.parse_http().unwrap();
+    assert!(headers.is_empty());
+}
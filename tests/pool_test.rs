@@ -0,0 +1,115 @@
+use fastcgi_client::{pool::Pool, ClientResult, Params, Request};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::io::{self, DuplexStream};
+
+mod common;
+
+async fn connect(created: Arc<AtomicUsize>) -> ClientResult<DuplexStream> {
+    created.fetch_add(1, Ordering::SeqCst);
+    // The server side is dropped, so any exchange on the stream fails.
+    Ok(io::duplex(1024).0)
+}
+
+#[tokio::test]
+async fn reuse_keep_alive() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::new(|| connect(created.clone()), true, 2);
+
+    drop(pool.get().await.unwrap());
+    assert_eq!(pool.idle_count(), 1);
+    drop(pool.get().await.unwrap());
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    assert_eq!(created.load(Ordering::SeqCst), 2);
+    drop(first);
+    drop(second);
+    assert_eq!(pool.idle_count(), 2);
+}
+
+#[tokio::test]
+async fn discard_not_keep_alive() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::new(|| connect(created.clone()), false, 2);
+
+    drop(pool.get().await.unwrap());
+    assert_eq!(pool.idle_count(), 0);
+    drop(pool.get().await.unwrap());
+    assert_eq!(created.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn discard_broken() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::new(|| connect(created.clone()), true, 2);
+
+    let mut client = pool.get().await.unwrap();
+    let result = client
+        .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+        .await;
+    assert!(result.is_err());
+    drop(client);
+
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[tokio::test]
+async fn ping_discards_stale() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::new(|| connect(created.clone()), true, 2).set_ping(true);
+
+    // The idle connection fails the ping, so a new one is created.
+    drop(pool.get().await.unwrap());
+    assert_eq!(pool.idle_count(), 1);
+    drop(pool.get().await.unwrap());
+    assert_eq!(created.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "testutil")]
+#[tokio::test]
+async fn reuse_across_requests() {
+    use fastcgi_client::testutil::MockServer;
+    use std::sync::Mutex;
+
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let servers = Arc::new(Mutex::new(Vec::new()));
+    let pool = Pool::new(
+        || {
+            created.fetch_add(1, Ordering::SeqCst);
+            let (stream, server) = MockServer::new().set_stdout("hello").start();
+            servers.lock().unwrap().push(server);
+            async move { Ok(stream) }
+        },
+        true,
+        2,
+    );
+
+    for _ in 0..2 {
+        let mut client = pool.get().await.unwrap();
+        let response = client
+            .execute_buffered(Request::new(Params::default(), &mut io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, b"hello");
+    }
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.idle_count(), 1);
+
+    drop(pool);
+    let server = servers.lock().unwrap().pop().unwrap();
+    assert_eq!(server.await.unwrap().unwrap().len(), 2);
+}
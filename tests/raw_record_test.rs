@@ -0,0 +1,84 @@
+use fastcgi_client::{Client, Params, Request, RequestType};
+use tokio::io::{self, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn send_and_read_records() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!((r#type, id), (9, 0));
+        assert_eq!(content, b"\x0e\x00FCGI_MAX_CONNS");
+
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!((r#type, id, content.len()), (5, 1, 0xffff));
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!((r#type, id, content.len()), (5, 1, 70000 - 0xffff));
+
+        let reply = common::padded_record(10, 0, b"\x0e\x02FCGI_MAX_CONNS10", 5);
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    client
+        .send_record(RequestType::GetValues, 0, b"\x0e\x00FCGI_MAX_CONNS")
+        .await
+        .unwrap();
+    client
+        .send_record(RequestType::Stdin, 1, &vec![0; 70000])
+        .await
+        .unwrap();
+
+    let (header, content) = client.read_record().await.unwrap();
+    assert!(matches!(header.request_type, RequestType::GetValuesResult));
+    assert_eq!(header.request_id, 0);
+    assert_eq!(header.content_length, 18);
+    assert_eq!(header.padding_length, 5);
+    assert_eq!(content, b"\x0e\x02FCGI_MAX_CONNS10");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn record_size() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true).set_record_size(10);
+
+    let server = tokio::spawn(async move {
+        // The lengths of the records of STDIN, until the empty one.
+        let mut lengths = Vec::new();
+        loop {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type != 5 {
+                continue;
+            }
+            lengths.push(content.len());
+            if content.is_empty() {
+                server_stream
+                    .write_all(&common::end_request(id, 0, 0))
+                    .await
+                    .unwrap();
+                return lengths;
+            }
+        }
+    });
+
+    let params = Params::default().set_content_length("25");
+    client
+        .execute(
+            Request::new(params, &mut &[b'x'; 25][..]),
+            &mut io::sink(),
+            &mut io::sink(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(server.await.unwrap(), [10, 10, 5, 0]);
+}
@@ -0,0 +1,107 @@
+use fastcgi_client::{ClientError, ClientResult, Params, ReconnectingClient, Request};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::{
+    io::{self, AsyncWriteExt, DuplexStream},
+    sync::mpsc,
+};
+
+mod common;
+
+/// Factory of duplex streams, the server sides are sent to `servers`.
+fn factory(
+    created: Arc<AtomicUsize>,
+    servers: mpsc::UnboundedSender<DuplexStream>,
+) -> impl Fn() -> std::future::Ready<ClientResult<DuplexStream>> {
+    move || {
+        created.fetch_add(1, Ordering::SeqCst);
+        let (client_stream, server_stream) = io::duplex(1024 * 1024);
+        servers.send(server_stream).unwrap();
+        std::future::ready(Ok(client_stream))
+    }
+}
+
+/// Reply to a request with `hello`, then close the connection.
+async fn reply_once(mut server_stream: DuplexStream) {
+    let id = common::read_request(&mut server_stream).await;
+    let mut reply = common::record(6, id, b"hello");
+    reply.extend(common::end_request(id, 0, 0));
+    server_stream.write_all(&reply).await.unwrap();
+}
+
+#[tokio::test]
+async fn reconnect_after_connection_lost() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let (servers, mut connections) = mpsc::unbounded_channel();
+    let mut client = ReconnectingClient::new(factory(created.clone(), servers), true);
+    assert!(!client.is_connected());
+    assert_eq!(created.load(Ordering::SeqCst), 0);
+
+    let server = tokio::spawn(async move {
+        while let Some(server_stream) = connections.recv().await {
+            reply_once(server_stream).await;
+        }
+    });
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert!(client.is_connected());
+
+    // The server closed the connection after the response.
+    match client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+    {
+        Err(ClientError::RetryAdvised { source }) => {
+            assert!(matches!(*source, ClientError::ConnectionClosed { .. }))
+        }
+        result => panic!("expect retry advised: {:?}", result),
+    }
+    assert!(!client.is_connected());
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert_eq!(created.load(Ordering::SeqCst), 2);
+
+    drop(client);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn reconnect_not_keep_alive() {
+    common::setup();
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let (servers, mut connections) = mpsc::unbounded_channel();
+    let mut client = ReconnectingClient::new(factory(created.clone(), servers), false);
+
+    let server = tokio::spawn(async move {
+        while let Some(server_stream) = connections.recv().await {
+            reply_once(server_stream).await;
+        }
+    });
+
+    for _ in 0..3 {
+        let response = client
+            .execute_buffered(Request::new(Params::default(), io::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, b"hello");
+        assert!(!client.is_connected());
+    }
+    assert_eq!(created.load(Ordering::SeqCst), 3);
+
+    drop(client);
+    server.await.unwrap();
+}
@@ -0,0 +1,528 @@
+use bytes::Bytes;
+use fastcgi_client::{
+    client::handle_fastcgi_request, Client, ClientError, OwnedRequest, Params, Request,
+    RequestStats,
+};
+use std::{
+    io::{self, Cursor},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+
+mod common;
+
+/// Writer counting the writes.
+#[derive(Default)]
+struct CountWrites {
+    buf: Vec<u8>,
+    writes: usize,
+}
+
+impl AsyncWrite for CountWrites {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes += 1;
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn buffer_content_length() {
+    let body = &mut &b"name=value&other=1"[..];
+    let mut request = Request::new(Params::default(), body)
+        .buffer_content_length()
+        .await
+        .unwrap();
+    assert_eq!(request.params()["CONTENT_LENGTH"], "18");
+
+    let mut stdin = Vec::new();
+    request.stdin_mut().read_to_end(&mut stdin).await.unwrap();
+    assert_eq!(stdin, b"name=value&other=1");
+}
+
+#[tokio::test]
+async fn owned_body() {
+    let mut request = Request::new_with_body(Params::default(), b"a=1".to_vec());
+    let mut stdin = Vec::new();
+    request.stdin_mut().read_to_end(&mut stdin).await.unwrap();
+    assert_eq!(stdin, b"a=1");
+
+    let mut request = Request::empty_body(Params::default().request_method("GET"));
+    let mut stdin = Vec::new();
+    request.stdin_mut().read_to_end(&mut stdin).await.unwrap();
+    assert!(stdin.is_empty());
+    assert_eq!(request.params()["REQUEST_METHOD"], "GET");
+}
+
+#[tokio::test]
+async fn owned_request() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    // The requests are built by another task than the one executing them.
+    let (sender, mut receiver) = mpsc::channel::<OwnedRequest<Cursor<Vec<u8>>>>(1);
+    let producer = tokio::spawn(async move {
+        let method = String::from("POST");
+        let params = Params::default()
+            .request_method(method.as_str())
+            .content_length("3");
+        let request = Request::new_with_body(params, b"a=1".to_vec()).into_owned();
+        drop(method);
+        sender.send(request).await.unwrap();
+    });
+
+    let mut client = Client::new(client_stream, true);
+    let request = receiver.recv().await.unwrap();
+    assert_eq!(request.params()["REQUEST_METHOD"], "POST");
+    let response = client.execute_buffered(request).await.unwrap();
+    assert_eq!(response.stdout, b"hello");
+
+    producer.await.unwrap();
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn coalesced_writes() {
+    let params = Params::default()
+        .set_request_method("POST")
+        .set_content_length("10240");
+    let mut body = &vec![b'x'; 10240][..];
+    let mut writer = CountWrites::default();
+    handle_fastcgi_request(&mut writer, false, 1, &params, &mut body)
+        .await
+        .unwrap();
+
+    // The records before STDIN, the STDIN record and the end of STDIN.
+    assert_eq!(writer.writes, 3);
+    assert!(writer.buf.windows(10240).any(|window| window.iter().all(|&b| b == b'x')));
+}
+
+#[tokio::test]
+async fn empty_body_framing() {
+    let mut writer = CountWrites::default();
+    handle_fastcgi_request(&mut writer, false, 1, &Params::default(), &mut tokio::io::empty())
+        .await
+        .unwrap();
+
+    // The types and content lengths of the records after FCGI_BEGIN_REQUEST.
+    let mut records = Vec::new();
+    let mut rest = &writer.buf[16..];
+    while !rest.is_empty() {
+        let content_length = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+        records.push((rest[1], content_length));
+        rest = &rest[8 + content_length + rest[6] as usize..];
+    }
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].0, 4);
+    assert_eq!(records[1..], [(4, 0), (5, 0)]);
+}
+
+#[tokio::test]
+async fn execute_buf_read() {
+    common::setup();
+
+    let body: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let server = tokio::spawn(async move {
+        let mut stdin = Vec::new();
+        loop {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type != 5 {
+                continue;
+            }
+            if content.is_empty() {
+                server_stream
+                    .write_all(&common::end_request(id, 0, 0))
+                    .await
+                    .unwrap();
+                return stdin;
+            }
+            stdin.push(content);
+        }
+    });
+
+    let mut client = Client::new(client_stream, false).set_record_size(600);
+    let params = Params::default().set_content_length(body.len().to_string());
+    let reader = BufReader::with_capacity(1000, &body[..]);
+    client
+        .execute_buf_read(
+            Request::new(params, reader),
+            &mut tokio::io::sink(),
+            &mut tokio::io::sink(),
+        )
+        .await
+        .unwrap();
+
+    // Records of the buffer of the body, limited by the record size.
+    let stdin = server.await.unwrap();
+    let lengths: Vec<usize> = stdin.iter().map(Vec::len).collect();
+    assert_eq!(lengths, [600, 400, 600, 400, 500]);
+    assert_eq!(stdin.concat(), body);
+}
+
+#[tokio::test]
+async fn last_request_stats() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        // A record of another request isn't counted.
+        reply.extend(common::record(6, id + 1, b"other"));
+        reply.extend(common::record(6, id, b" world"));
+        reply.extend(common::record(7, id, b"warn"));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let mut client = Client::new(client_stream, true).set_strict_records(false);
+    assert_eq!(client.last_request_stats(), RequestStats::default());
+    let params = Params::default().set_content_length("4");
+    let params_bytes = params.encode().unwrap().len();
+    client
+        .execute_buffered(Request::new(params, &b"body"[..]))
+        .await
+        .unwrap();
+
+    let stats = client.last_request_stats();
+    assert_eq!(stats.stdin_bytes, 4);
+    assert_eq!(stats.params_bytes, params_bytes);
+    assert_eq!(stats.stdout_bytes, 11);
+    assert_eq!(stats.stderr_bytes, 4);
+    assert_eq!(stats.records_read, 4);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn execute_duplex() {
+    common::setup();
+
+    let body = vec![b'a'; 64 * 1024];
+    let (client_stream, mut server_stream) = tokio::io::duplex(4096);
+    let server = tokio::spawn(async move {
+        // Respond once the params are read, before reading STDIN.
+        let id = loop {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type == 4 && content.is_empty() {
+                break id;
+            }
+        };
+        for _ in 0..16 {
+            server_stream
+                .write_all(&common::record(6, id, &[b'b'; 4096]))
+                .await
+                .unwrap();
+        }
+
+        let mut stdin = Vec::new();
+        loop {
+            let (r#type, _, content) = common::read_record(&mut server_stream).await;
+            if r#type == 5 && content.is_empty() {
+                break;
+            }
+            stdin.extend(content);
+        }
+        server_stream
+            .write_all(&common::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        stdin
+    });
+
+    let mut client = Client::new(client_stream, true);
+    let params = Params::default().set_content_length(body.len().to_string());
+    let mut stdout = Vec::new();
+    let complete = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.execute_duplex(
+            Request::new(params, &body[..]),
+            &mut stdout,
+            &mut tokio::io::sink(),
+        ),
+    )
+    .await
+    .expect("request and response should be exchanged concurrently")
+    .unwrap();
+    assert_eq!(complete.app_status, 0);
+    assert_eq!(stdout, vec![b'b'; 64 * 1024]);
+    assert_eq!(server.await.unwrap(), body);
+    assert!(client.is_reusable());
+
+    let stats = client.last_request_stats();
+    assert_eq!(stats.stdin_bytes, body.len());
+    assert_eq!(stats.stdout_bytes, 64 * 1024);
+    assert_eq!(stats.records_read, 17);
+}
+
+#[tokio::test]
+async fn execute_stream_body() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let server = tokio::spawn(async move {
+        let mut stdin = Vec::new();
+        loop {
+            let (r#type, id, content) = common::read_record(&mut server_stream).await;
+            if r#type != 5 {
+                continue;
+            }
+            if content.is_empty() {
+                server_stream
+                    .write_all(&common::end_request(id, 0, 0))
+                    .await
+                    .unwrap();
+                return stdin;
+            }
+            stdin.push(content);
+        }
+    });
+
+    let (sender, receiver) = mpsc::channel(1);
+    let producer = tokio::spawn(async move {
+        for chunk in [&b"abc"[..], b"", &[b'x'; 70000]].iter() {
+            sender.send(Ok(Bytes::from_static(chunk))).await.unwrap();
+        }
+    });
+
+    let mut client = Client::new(client_stream, true);
+    let params = Params::default().set_content_length("70003");
+    client
+        .execute_stream_body(
+            &params,
+            receiver,
+            &mut tokio::io::sink(),
+            &mut tokio::io::sink(),
+        )
+        .await
+        .unwrap();
+    producer.await.unwrap();
+
+    // A record for every chunk, split by the record size, none for the empty
+    // chunk, which doesn't end the STDIN.
+    let stdin = server.await.unwrap();
+    let lengths: Vec<usize> = stdin.iter().map(Vec::len).collect();
+    assert_eq!(lengths, [3, 65535, 4465]);
+    assert_eq!(&stdin.concat()[..3], b"abc");
+}
+
+#[tokio::test]
+async fn execute_stream_body_error() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let (r#type, abort_id, _) = common::read_record(&mut server_stream).await;
+        assert_eq!((r#type, abort_id), (2, id));
+        server_stream
+            .write_all(&common::end_request(id, 0, 0))
+            .await
+            .unwrap();
+        server_stream
+    });
+
+    let (sender, receiver) = mpsc::channel(2);
+    sender.send(Ok(Bytes::from_static(b"partial"))).await.unwrap();
+    sender
+        .send(Err(io::Error::new(io::ErrorKind::InvalidData, "upload failed").into()))
+        .await
+        .unwrap();
+
+    let mut client = Client::new(client_stream, true);
+    let result = client
+        .execute_stream_body(
+            &Params::default(),
+            receiver,
+            &mut tokio::io::sink(),
+            &mut tokio::io::sink(),
+        )
+        .await;
+    match result {
+        Err(ClientError::Io(err)) => assert_eq!(err.to_string(), "upload failed"),
+        result => panic!("expect the error of the body: {:?}", result),
+    }
+
+    // The request was aborted cleanly, so the connection is reusable.
+    assert!(client.is_reusable());
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn pipelined_send_recv() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(common::read_request(&mut server_stream).await);
+        }
+        for id in ids {
+            let mut reply = common::record(6, id, format!("response {}", id).as_bytes());
+            reply.extend(common::end_request(id, 0, 0));
+            server_stream.write_all(&reply).await.unwrap();
+        }
+        server_stream
+    });
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let request = Request::new(Params::default(), tokio::io::empty());
+        ids.push(client.send(request).await.unwrap());
+    }
+    assert_eq!(ids, [1, 2, 3]);
+    assert!(!client.is_reusable());
+
+    // Received in the order sent.
+    let mut stdout = Vec::new();
+    assert!(matches!(
+        client.recv(2, &mut stdout, &mut tokio::io::sink()).await,
+        Err(ClientError::Busy { id: 1 })
+    ));
+    assert!(matches!(
+        client
+            .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+            .await,
+        Err(ClientError::Busy { id: 1 })
+    ));
+    for id in ids {
+        let mut stdout = Vec::new();
+        client
+            .recv(id, &mut stdout, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(stdout, format!("response {}", id).as_bytes());
+    }
+    assert!(matches!(
+        client.recv(3, &mut stdout, &mut tokio::io::sink()).await,
+        Err(ClientError::ResponseNotFound { id: 3 })
+    ));
+    assert!(client.is_reusable());
+    assert_eq!(client.in_flight(), 0);
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn stderr_before_stdout() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(7, id, b"PHP Warning: deprecated");
+        reply.extend(common::record(7, id, b""));
+        reply.extend(common::record(6, id, b"hello"));
+        reply.extend(common::record(6, id, b""));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let response = client
+        .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert_eq!(response.stderr, b"PHP Warning: deprecated");
+    let stats = client.last_request_stats();
+    assert!(stats.had_stdout && stats.had_stderr);
+
+    let _server_stream = server.await.unwrap();
+}
+
+/// Writer recording the bytes written at every flush.
+#[derive(Clone, Default)]
+struct RecordFlushes {
+    buf: Arc<Mutex<Vec<u8>>>,
+    flushes: Arc<Mutex<Vec<usize>>>,
+}
+
+impl AsyncWrite for RecordFlushes {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buf.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let len = self.buf.lock().unwrap().len();
+        self.flushes.lock().unwrap().push(len);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn flush_interval() {
+    let writer = RecordFlushes::default();
+    let mut client = Client::from_halves(tokio::io::empty(), writer.clone(), true)
+        .set_record_size(1024)
+        .set_flush_interval(Some(4));
+
+    let params = Params::default().set_content_length("10240");
+    client
+        .handle_new_request(&params, &mut &[b'x'; 10240][..])
+        .await
+        .unwrap();
+
+    let buf = writer.buf.lock().unwrap().clone();
+    let flushes = writer.flushes.lock().unwrap().clone();
+    // Flushed after the end of PARAMS, before any STDIN.
+    let head = flushes[0];
+    assert!(buf[..head].ends_with(&[1, 4, 0, 1, 0, 0, 0, 0]));
+    // Then every 4 records of STDIN, and at the end of request.
+    let record = 8 + 1024;
+    assert_eq!(
+        &flushes[1..],
+        [head + 4 * record, head + 8 * record, buf.len()]
+    );
+
+    // Flushed once by default.
+    let writer = RecordFlushes::default();
+    let mut client = Client::from_halves(tokio::io::empty(), writer.clone(), true);
+    client
+        .handle_new_request(&params, &mut &[b'x'; 10240][..])
+        .await
+        .unwrap();
+    assert_eq!(writer.flushes.lock().unwrap().len(), 1);
+}
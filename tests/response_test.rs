@@ -0,0 +1,227 @@
+use fastcgi_client::{
+    client::handle_fastcgi_response, Client, ClientError, Params, ProtocolStatus, Request,
+    RequestType,
+};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn request_complete_status() {
+    common::setup();
+
+    let mut input = common::record(6, 1, b"hello");
+    input.extend(common::end_request(1, 255, 0));
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let complete = handle_fastcgi_response(&mut &input[..], 1, &mut stdout, &mut stderr)
+        .await
+        .unwrap();
+
+    assert_eq!(complete.app_status, 255);
+    assert_eq!(complete.protocol_status, ProtocolStatus::RequestComplete);
+    assert_eq!(stdout, b"hello");
+    assert!(stderr.is_empty());
+}
+
+#[tokio::test]
+async fn request_not_complete_status() {
+    common::setup();
+
+    let statuses = [
+        (1, ProtocolStatus::CantMpxConn),
+        (2, ProtocolStatus::Overloaded),
+        (3, ProtocolStatus::UnknownRole),
+    ];
+    for &(protocol_status, expected) in &statuses {
+        let input = common::end_request(1, 7, protocol_status);
+        let err = handle_fastcgi_response(&mut &input[..], 1, &mut tokio::io::sink(), &mut tokio::io::sink())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.protocol_status(), Some(expected));
+        assert_eq!(err.app_status(), Some(7));
+        match (expected, err) {
+            (ProtocolStatus::CantMpxConn, ClientError::EndRequestCantMpxConn { app_status })
+            | (ProtocolStatus::Overloaded, ClientError::EndRequestOverloaded { app_status })
+            | (ProtocolStatus::UnknownRole, ClientError::EndRequestUnknownRole { app_status }) => {
+                assert_eq!(app_status, 7)
+            }
+            (_, err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+/// Writer accepting at most one byte per write.
+struct SlowWriter(Vec<u8>);
+
+impl AsyncWrite for SlowWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = buf.len().min(1);
+        self.0.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn short_writes() {
+    common::setup();
+
+    let mut input = common::record(6, 1, b"hello");
+    input.extend(common::record(7, 1, b"world"));
+    input.extend(common::end_request(1, 0, 0));
+
+    let mut stdout = SlowWriter(Vec::new());
+    let mut stderr = SlowWriter(Vec::new());
+    handle_fastcgi_response(&mut &input[..], 1, &mut stdout, &mut stderr)
+        .await
+        .unwrap();
+
+    assert_eq!(stdout.0, b"hello");
+    assert_eq!(stderr.0, b"world");
+}
+
+#[tokio::test]
+async fn skip_padding() {
+    common::setup();
+
+    let mut input = common::padded_record(6, 1, b"hello", 3);
+    input.extend(common::padded_record(7, 1, b"oops", 4));
+    input.extend(common::padded_record(6, 1, b" world", 255));
+    let mut end_request = common::end_request(1, 7, 0);
+    end_request[6] = 8;
+    end_request.extend([0xff; 8]);
+    input.extend(end_request);
+    // Anything after the end of request is left in the stream.
+    input.extend(common::record(6, 2, b"next"));
+
+    let mut stream = &input[..];
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let complete = handle_fastcgi_response(&mut stream, 1, &mut stdout, &mut stderr)
+        .await
+        .unwrap();
+
+    assert_eq!(complete.app_status, 7);
+    assert_eq!(stdout, b"hello world");
+    assert_eq!(stderr, b"oops");
+    assert_eq!(stream, &common::record(6, 2, b"next")[..]);
+}
+
+/// Reply to the request with a stray record of an ended request first.
+async fn reply_with_stray_record(mut server_stream: tokio::io::DuplexStream) {
+    let id = common::read_request(&mut server_stream).await;
+    let mut reply = common::padded_record(6, id + 1, b"stray", 3);
+    reply.extend(common::record(6, id, b"hello"));
+    reply.extend(common::end_request(id, 0, 0));
+    server_stream.write_all(&reply).await.unwrap();
+}
+
+#[tokio::test]
+async fn unexpected_record() {
+    common::setup();
+
+    let input = common::record(6, 2, b"stray");
+    let err = handle_fastcgi_response(&mut &input[..], 1, &mut tokio::io::sink(), &mut tokio::io::sink())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::UnexpectedRecord { id: 2, .. }));
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let server = tokio::spawn(reply_with_stray_record(server_stream));
+    let mut client = Client::new(client_stream, true);
+    let err = client
+        .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::UnexpectedRecord {
+            request_type: RequestType::Stdout,
+            ..
+        }
+    ));
+    assert!(!client.is_reusable());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn discard_unexpected_record() {
+    common::setup();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let server = tokio::spawn(reply_with_stray_record(server_stream));
+    let mut client = Client::new(client_stream, true).set_strict_records(false);
+    let response = client
+        .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    assert!(client.is_reusable());
+    server.await.unwrap();
+}
+
+/// Check the version of the request records, reply with records of version 3.
+async fn reply_with_version(mut server_stream: tokio::io::DuplexStream, version: u8) {
+    loop {
+        let mut header = [0u8; 8];
+        server_stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], version);
+        let id = u16::from_be_bytes([header[2], header[3]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize;
+        let mut content = vec![0; length];
+        server_stream.read_exact(&mut content).await.unwrap();
+
+        if header[1] == 5 && length == 0 {
+            let mut reply = common::record(6, id, b"hello");
+            reply.extend(common::end_request(id, 0, 0));
+            reply[0] = 3;
+            reply[8 + 5] = 3;
+            server_stream.write_all(&reply).await.unwrap();
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+async fn record_version() {
+    common::setup();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let server = tokio::spawn(reply_with_version(server_stream, 2));
+    let mut client = Client::new(client_stream, true).set_version(2);
+    let response = client
+        .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    server.await.unwrap();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let server = tokio::spawn(reply_with_version(server_stream, 1));
+    let mut client = Client::new(client_stream, true).set_strict_version(true);
+    let err = client
+        .execute_buffered(Request::new(Params::default(), tokio::io::empty()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::UnsupportedVersion { version: 3 }));
+    server.await.unwrap();
+}
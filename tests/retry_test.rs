@@ -0,0 +1,67 @@
+use fastcgi_client::{Client, ClientResult, Params, Request};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::io::{self, AsyncWriteExt, DuplexStream};
+
+mod common;
+
+async fn connect(connected: Arc<AtomicUsize>) -> ClientResult<DuplexStream> {
+    connected.fetch_add(1, Ordering::SeqCst);
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+    });
+    Ok(client_stream)
+}
+
+#[tokio::test]
+async fn retry_after_connection_lost() {
+    common::setup();
+
+    // The connection was closed by the server.
+    let (client_stream, _) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let connected = Arc::new(AtomicUsize::new(0));
+    let request = Request::new(Params::default(), &b"body"[..]);
+    let mut stdout = Vec::new();
+    client
+        .execute_retry(
+            || connect(connected.clone()),
+            &request,
+            &mut stdout,
+            &mut io::sink(),
+            2,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(stdout, b"hello");
+    assert_eq!(connected.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn retries_exhausted() {
+    common::setup();
+
+    let (client_stream, _) = io::duplex(1024);
+    let mut client = Client::new(client_stream, true);
+
+    let request = Request::new(Params::default(), &b"body"[..]);
+    let result = client
+        .execute_retry(
+            || async { Ok(io::duplex(1024).0) },
+            &request,
+            &mut io::sink(),
+            &mut io::sink(),
+            2,
+        )
+        .await;
+
+    assert!(result.is_err());
+}
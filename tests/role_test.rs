@@ -0,0 +1,268 @@
+use fastcgi_client::{Client, ClientError, Params, RequestBuilder, Role};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+#[tokio::test]
+async fn authorizer() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, false);
+
+    let server = tokio::spawn(async move {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 1);
+        assert_eq!(&content[..2], &[0, 2]);
+
+        loop {
+            let (r#type, _, content) = common::read_record(&mut server_stream).await;
+            assert_eq!(r#type, 4);
+            if content.is_empty() {
+                break;
+            }
+        }
+
+        let mut reply = common::record(6, id, b"Status: 200\r\n\r\n");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+
+        // No STDIN is sent to the authorizer.
+        let mut rest = Vec::new();
+        server_stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    });
+
+    let response = client.execute_authorizer(&Params::default()).await.unwrap();
+    assert_eq!(response.stdout, b"Status: 200\r\n\r\n");
+    drop(client);
+
+    server.await.unwrap();
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn authorize() {
+    common::setup();
+
+    let replies: [&[u8]; 2] = [
+        b"Variable-AUTH_USER: alice\r\nvariable-Auth_Role:  admin \r\n\r\n",
+        b"Status: 403 Forbidden\r\nContent-Type: text/plain\r\n\r\ndenied",
+    ];
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        for reply in replies.iter() {
+            let id = loop {
+                let (r#type, id, content) = common::read_record(&mut server_stream).await;
+                if r#type == 4 && content.is_empty() {
+                    break id;
+                }
+            };
+            let mut reply = common::record(6, id, reply);
+            reply.extend(common::end_request(id, 0, 0));
+            server_stream.write_all(&reply).await.unwrap();
+        }
+    });
+
+    let result = client.authorize(&Params::default()).await.unwrap();
+    assert!(result.is_authorized());
+    assert_eq!(result.variables.len(), 2);
+    assert_eq!(result.variables["AUTH_USER"], "alice");
+    assert_eq!(result.variables["Auth_Role"], "admin");
+    assert!(result.headers.is_empty());
+    assert!(result.body.is_empty());
+
+    // The response of a denied request is kept to be sent to the client.
+    let result = client.authorize(&Params::default()).await.unwrap();
+    assert!(!result.is_authorized());
+    assert_eq!(result.status, 403);
+    assert!(result.variables.is_empty());
+    assert_eq!(result.headers["content-type"], "text/plain");
+    assert_eq!(result.body, b"denied");
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn filter() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, false);
+
+    let server = tokio::spawn(async move {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 1);
+        assert_eq!(&content[..2], &[0, 3]);
+
+        // Collect the streams until the DATA stream ends.
+        let mut records = Vec::new();
+        loop {
+            let (r#type, _, content) = common::read_record(&mut server_stream).await;
+            if r#type == 4 {
+                continue;
+            }
+            let end = r#type == 8 && content.is_empty();
+            records.push((r#type, content));
+            if end {
+                break;
+            }
+        }
+        let stdin: Vec<u8> = records
+            .iter()
+            .filter(|(r#type, _)| *r#type == 5)
+            .flat_map(|(_, content)| content.clone())
+            .collect();
+        let data: Vec<u8> = records
+            .iter()
+            .filter(|(r#type, _)| *r#type == 8)
+            .flat_map(|(_, content)| content.clone())
+            .collect();
+        let last_stdin = records.iter().rposition(|(r#type, _)| *r#type == 5).unwrap();
+        let first_data = records.iter().position(|(r#type, _)| *r#type == 8).unwrap();
+        assert!(last_stdin < first_data);
+        assert_eq!(stdin, b"input");
+        assert_eq!(data, b"file to filter");
+
+        let mut reply = common::record(6, id, b"filtered");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+    });
+
+    let params = Params::default().param("FCGI_DATA_LENGTH", "14");
+    let response = client
+        .execute_filter(&params, &mut &b"input"[..], &mut &b"file to filter"[..])
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"filtered");
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn filter_missing_data_length() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let err = client
+        .execute_filter(&Params::default(), &mut io::empty(), &mut &b"data"[..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::MissingParam { ref name } if name == "FCGI_DATA_LENGTH"
+    ));
+    assert!(client.is_reusable());
+
+    // Nothing was written.
+    drop(client);
+    let mut rest = Vec::new();
+    server_stream.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn request_builder() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let (r#type, id, content) = common::read_record(&mut server_stream).await;
+        assert_eq!(r#type, 1);
+        // Filter role without keep alive.
+        assert_eq!(&content[..3], &[0, 3, 0]);
+
+        let mut params = Vec::new();
+        let mut records = Vec::new();
+        loop {
+            let (r#type, _, content) = common::read_record(&mut server_stream).await;
+            if r#type == 4 {
+                params.extend(content);
+                continue;
+            }
+            let end = r#type == 8;
+            records.push((r#type, content));
+            if end {
+                break;
+            }
+        }
+        // The empty STDIN and DATA streams, whose length is set.
+        assert_eq!(records, vec![(5, Vec::new()), (8, Vec::new())]);
+        assert!(params.ends_with(b"\x10\x01FCGI_DATA_LENGTH0"));
+
+        let mut reply = common::record(6, id, b"filtered");
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+    });
+
+    let params = Params::default();
+    let mut stdout = Vec::new();
+    let complete = client
+        .execute_request(
+            RequestBuilder::new(&params)
+                .set_role(Role::Filter)
+                .set_keep_alive(false),
+            &mut stdout,
+            &mut io::sink(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(complete.app_status, 0);
+    assert_eq!(stdout, b"filtered");
+
+    // The server closes the connection after the response.
+    assert!(!client.is_reusable());
+    let err = client
+        .execute_request(
+            RequestBuilder::new(&params),
+            &mut io::sink(),
+            &mut io::sink(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::ConnectionClosed { .. }));
+
+    server.await.unwrap();
+}
+
+#[cfg(feature = "testutil")]
+#[tokio::test]
+async fn builder_send() {
+    use fastcgi_client::testutil::MockServer;
+
+    common::setup();
+
+    let (stream, server) = MockServer::new().set_stdout("hello").start();
+    let mut client = Client::new(stream, true);
+    let params = Params::default();
+    let mut stdin = &b"body"[..];
+    let response = RequestBuilder::new(&params)
+        .set_stdin(&mut stdin)
+        .send(&mut client)
+        .await
+        .unwrap();
+    assert_eq!(response.stdout, b"hello");
+    drop(client);
+    let requests = server.await.unwrap().unwrap();
+    assert_eq!(requests[0].stdin, b"body");
+
+    let (stream, _server) = MockServer::new()
+        .set_stderr("failed")
+        .set_app_status(3)
+        .start();
+    let mut client = Client::new(stream, true);
+    let err = RequestBuilder::new(&params)
+        .send(&mut client)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::RequestFailed { app_status: 3, ref stderr } if stderr == "failed"
+    ));
+}
@@ -0,0 +1,193 @@
+use fastcgi_client::{Client, Params, ProtocolStatus, Request, ResponseChunk};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::sleep,
+};
+
+mod common;
+
+#[tokio::test]
+async fn execute_stream() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::record(7, id, b"warning"));
+        reply.extend(common::record(6, id, b" world"));
+        reply.extend(common::end_request(id, 3, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let mut stream = client
+        .execute_stream(Request::new(Params::default(), io::empty()))
+        .await
+        .unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        chunks.push(chunk.unwrap());
+    }
+
+    assert_eq!(
+        chunks,
+        vec![
+            ResponseChunk::Stdout("hello".into()),
+            ResponseChunk::Stderr("warning".into()),
+            ResponseChunk::Stdout(" world".into()),
+            ResponseChunk::End {
+                app_status: 3,
+                protocol_status: ProtocolStatus::RequestComplete
+            },
+        ]
+    );
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn execute_interleaved() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"<p>");
+        reply.extend(common::record(7, id, b"PHP Warning: undefined variable"));
+        reply.extend(common::record(6, id, b"</p>"));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let mut chunks = Vec::new();
+    let complete = client
+        .execute_interleaved(Request::new(Params::default(), io::empty()), &mut chunks)
+        .await
+        .unwrap();
+
+    assert_eq!(complete.protocol_status, ProtocolStatus::RequestComplete);
+    assert_eq!(
+        chunks,
+        vec![
+            ResponseChunk::Stdout("<p>".into()),
+            ResponseChunk::Stderr("PHP Warning: undefined variable".into()),
+            ResponseChunk::Stdout("</p>".into()),
+        ]
+    );
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn execute_dyn_sinks() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        let id = common::read_request(&mut server_stream).await;
+        let mut reply = common::record(6, id, b"hello");
+        reply.extend(common::record(7, id, b"warning"));
+        reply.extend(common::end_request(id, 0, 0));
+        server_stream.write_all(&reply).await.unwrap();
+        server_stream
+    });
+
+    let mut stdout = Vec::new();
+    let mut stderr: Pin<Box<dyn AsyncWrite>> = Box::pin(Vec::new());
+    let body: &mut (dyn AsyncRead + Unpin) = &mut io::empty();
+    let sink: &mut (dyn AsyncWrite + Unpin) = &mut stdout;
+    client
+        .execute(Request::new(Params::default(), body), sink, &mut stderr)
+        .await
+        .unwrap();
+    assert_eq!(stdout, b"hello");
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn slow_sink_backpressure() {
+    common::setup();
+
+    const RECORDS: usize = 64;
+    const RECORD_LEN: usize = 4096;
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let (client_stream, mut server_stream) = io::duplex(2 * RECORD_LEN);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn({
+        let sent = sent.clone();
+        async move {
+            let id = common::read_request(&mut server_stream).await;
+            for _ in 0..RECORDS {
+                server_stream
+                    .write_all(&common::record(6, id, &[b'x'; RECORD_LEN]))
+                    .await
+                    .unwrap();
+                sent.fetch_add(RECORD_LEN, Ordering::SeqCst);
+            }
+            server_stream
+                .write_all(&common::end_request(id, 0, 0))
+                .await
+                .unwrap();
+        }
+    });
+
+    // Sink consuming a record every millisecond, tracking how far the server
+    // got ahead of it.
+    let (mut sink, mut consumer) = io::duplex(1024);
+    let consumer = tokio::spawn({
+        let sent = sent.clone();
+        async move {
+            let mut consumed = 0;
+            let mut max_ahead = 0;
+            let mut buf = vec![0; RECORD_LEN];
+            loop {
+                sleep(Duration::from_millis(1)).await;
+                match consumer.read(&mut buf).await.unwrap() {
+                    0 => return (consumed, max_ahead),
+                    n => consumed += n,
+                }
+                max_ahead = max_ahead.max(sent.load(Ordering::SeqCst).saturating_sub(consumed));
+            }
+        }
+    });
+
+    client
+        .execute(
+            Request::new(Params::default(), io::empty()),
+            &mut sink,
+            &mut io::sink(),
+        )
+        .await
+        .unwrap();
+    drop(sink);
+    server.await.unwrap();
+
+    // Bounded by the buffers of the connection and the sink, and the record
+    // held by the client, not the whole response.
+    let (consumed, max_ahead) = consumer.await.unwrap();
+    assert_eq!(consumed, RECORDS * RECORD_LEN);
+    assert!(
+        max_ahead <= 5 * RECORD_LEN,
+        "server got {} bytes ahead",
+        max_ahead
+    );
+}
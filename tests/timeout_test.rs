@@ -0,0 +1,146 @@
+use fastcgi_client::{Address, Client, ClientBuilder, ClientError, Params, Phase, Request};
+use std::time::Duration;
+use tokio::{io, net::TcpSocket, time::Instant};
+
+mod common;
+
+#[tokio::test]
+async fn timeout_reading_response() {
+    common::setup();
+
+    let (client_stream, mut server_stream) = io::duplex(1024 * 1024);
+    let mut client = Client::new(client_stream, true);
+
+    let server = tokio::spawn(async move {
+        // Never respond, expect the abort after the request.
+        loop {
+            let (r#type, _, _) = common::read_record(&mut server_stream).await;
+            if r#type == 2 {
+                return server_stream;
+            }
+        }
+    });
+
+    let result = client
+        .execute_with_timeout(
+            Request::new(Params::default(), io::empty()),
+            &mut io::sink(),
+            &mut io::sink(),
+            Duration::from_millis(100),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Timeout {
+            id: 1,
+            phase: Phase::Response
+        })
+    ));
+
+    let _server_stream = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn timeout_writing_request() {
+    common::setup();
+
+    // The server never reads, so the writes are blocked once the buffer is full.
+    let (client_stream, _server_stream) = io::duplex(64);
+    let mut client = Client::new(client_stream, true);
+
+    let result = client
+        .execute_with_timeout(
+            Request::new(Params::default(), &mut &[0u8; 1024][..]),
+            &mut io::sink(),
+            &mut io::sink(),
+            Duration::from_millis(100),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Timeout {
+            phase: Phase::Request,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn builder_request_timeout() {
+    common::setup();
+
+    // The server never reads, so the writes are blocked once the buffer is full.
+    let (client_stream, _server_stream) = io::duplex(64);
+    let mut client = ClientBuilder::new()
+        .set_keep_alive(true)
+        .set_request_timeout(Duration::from_millis(100))
+        .build(client_stream);
+
+    let result = client
+        .execute(
+            Request::new(Params::default(), &mut &[0u8; 1024][..]),
+            &mut io::sink(),
+            &mut io::sink(),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Timeout {
+            phase: Phase::Request,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn builder_deadline() {
+    common::setup();
+
+    let (client_stream, _server_stream) = io::duplex(64);
+    let mut client = ClientBuilder::new()
+        .set_request_timeout(Duration::from_secs(60))
+        .set_deadline(Instant::now() + Duration::from_millis(100))
+        .build(client_stream);
+
+    let started = Instant::now();
+    let result = client
+        .execute_with_timeout(
+            Request::new(Params::default(), &mut &[0u8; 1024][..]),
+            &mut io::sink(),
+            &mut io::sink(),
+            Duration::from_secs(60),
+        )
+        .await;
+    assert!(matches!(result, Err(ClientError::Timeout { .. })));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn builder_connect_timeout() {
+    common::setup();
+
+    // Without accepting, the backlog of the listener is soon full, then the
+    // handshakes of the later connections don't complete.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(([127, 0, 0, 1], 0).into()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut pending = Vec::new();
+    let result = loop {
+        let result = ClientBuilder::new()
+            .set_connect_timeout(Duration::from_millis(100))
+            .connect(Address::Tcp("127.0.0.1", port))
+            .await;
+        match result {
+            Ok(client) if pending.len() < 16 => pending.push(client),
+            result => break result,
+        }
+    };
+    assert!(matches!(
+        result,
+        Err(ClientError::Timeout {
+            id: 0,
+            phase: Phase::Connect
+        })
+    ));
+}